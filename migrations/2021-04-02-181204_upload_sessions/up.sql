@@ -0,0 +1,14 @@
+CREATE TABLE upload_sessions (
+    id SERIAL PRIMARY KEY,
+    token text NOT NULL,
+    user_id integer NOT NULL,
+    local_name text NOT NULL,
+    request text NOT NULL,
+    expected_size bigint NOT NULL,
+    received bigint NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    UNIQUE(token),
+    UNIQUE(local_name),
+    foreign key (user_id) references users(id)
+);
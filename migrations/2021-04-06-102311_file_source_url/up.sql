@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN source_url text;
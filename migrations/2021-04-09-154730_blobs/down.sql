@@ -0,0 +1,5 @@
+-- Give every file its own local file again. Files sharing
+-- a blob have to be copied before running this migration
+ALTER TABLE files DROP COLUMN blob_id;
+ALTER TABLE files ADD CONSTRAINT files_local_name_key UNIQUE(local_name);
+DROP TABLE blobs;
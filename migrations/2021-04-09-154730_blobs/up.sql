@@ -0,0 +1,16 @@
+CREATE TABLE blobs (
+    id SERIAL PRIMARY KEY,
+    hash text NOT NULL,
+    local_name text NOT NULL,
+    size bigint NOT NULL,
+    ref_count integer NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    UNIQUE(hash),
+    UNIQUE(local_name)
+);
+
+-- Multiple files can share the same blob. Existing files get
+-- linked to their blob by running 'dv_admin migrations run'
+ALTER TABLE files DROP CONSTRAINT files_local_name_key;
+ALTER TABLE files ADD COLUMN blob_id integer REFERENCES blobs(id);
+CREATE INDEX files_blob_id_idx ON files(blob_id);
@@ -0,0 +1,2 @@
+ALTER TABLE files DROP COLUMN wrapped_key;
+ALTER TABLE blobs DROP COLUMN encrypted;
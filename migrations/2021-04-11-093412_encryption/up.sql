@@ -0,0 +1,5 @@
+-- Existing blobs stay unencrypted
+ALTER TABLE blobs ADD COLUMN encrypted boolean NOT NULL DEFAULT false;
+
+-- The key of an encrypted blob, wrapped with the key of the files owner
+ALTER TABLE files ADD COLUMN wrapped_key text;
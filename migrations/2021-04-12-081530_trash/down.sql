@@ -0,0 +1,2 @@
+-- Files in the trash become visible again
+ALTER TABLE files DROP COLUMN deleted_at;
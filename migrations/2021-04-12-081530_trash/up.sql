@@ -0,0 +1,3 @@
+-- Deleted files are kept in the trash until they get purged
+ALTER TABLE files ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
+CREATE INDEX files_deleted_at_idx ON files(deleted_at);
@@ -0,0 +1,3 @@
+-- Blobs only referenced by versions have to be
+-- released before running this migration
+DROP TABLE file_versions;
@@ -0,0 +1,16 @@
+-- Previous contents of replaced files
+CREATE TABLE file_versions (
+    id SERIAL PRIMARY KEY,
+    file_id integer NOT NULL,
+    version integer NOT NULL,
+    blob_id integer,
+    local_name text NOT NULL,
+    file_size bigint NOT NULL,
+    file_type text NOT NULL,
+    checksum text NOT NULL,
+    wrapped_key text,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    UNIQUE(file_id, version),
+    foreign key (file_id) references files(id),
+    foreign key (blob_id) references blobs(id)
+);
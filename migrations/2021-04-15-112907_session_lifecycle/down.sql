@@ -0,0 +1,3 @@
+ALTER TABLE login_sessions DROP COLUMN expires_at;
+ALTER TABLE login_sessions DROP COLUMN last_used;
+ALTER TABLE login_sessions DROP COLUMN created_at;
@@ -0,0 +1,6 @@
+ALTER TABLE login_sessions ADD COLUMN created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL;
+ALTER TABLE login_sessions ADD COLUMN last_used TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL;
+
+-- Existing sessions expire 30 days after this migration
+ALTER TABLE login_sessions ADD COLUMN expires_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP + interval '30 days' NOT NULL;
+ALTER TABLE login_sessions ALTER COLUMN expires_at DROP DEFAULT;
@@ -0,0 +1,14 @@
+-- Bytes transferred per user, namespace and hour
+CREATE TABLE traffic (
+    id SERIAL PRIMARY KEY,
+    user_id integer NOT NULL,
+    namespace_id integer,
+    period TIMESTAMP WITH TIME ZONE NOT NULL,
+    upload bigint NOT NULL DEFAULT 0,
+    download bigint NOT NULL DEFAULT 0,
+    preview bigint NOT NULL DEFAULT 0,
+    UNIQUE(user_id, namespace_id, period),
+    foreign key (user_id) references users(id),
+    -- Keep the traffic of a user when one of their namespaces gets deleted
+    foreign key (namespace_id) references namespaces(id) ON DELETE SET NULL
+);
@@ -0,0 +1,4 @@
+ALTER TABLE users DROP COLUMN quota_bytes;
+ALTER TABLE users DROP COLUMN quota_files;
+ALTER TABLE namespaces DROP COLUMN quota_bytes;
+ALTER TABLE namespaces DROP COLUMN quota_files;
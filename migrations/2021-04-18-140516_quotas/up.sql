@@ -0,0 +1,5 @@
+-- Storage limits. NULL means unlimited
+ALTER TABLE users ADD COLUMN quota_bytes bigint;
+ALTER TABLE users ADD COLUMN quota_files bigint;
+ALTER TABLE namespaces ADD COLUMN quota_bytes bigint;
+ALTER TABLE namespaces ADD COLUMN quota_files bigint;
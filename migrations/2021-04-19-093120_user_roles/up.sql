@@ -0,0 +1,2 @@
+-- 0: user, 1: admin
+ALTER TABLE users ADD COLUMN role smallint NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Set by the storage scrub if the content of a file is missing or damaged
+ALTER TABLE files ADD COLUMN corrupted boolean NOT NULL DEFAULT false;
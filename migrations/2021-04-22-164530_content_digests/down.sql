@@ -0,0 +1,4 @@
+ALTER TABLE files DROP COLUMN sha256;
+ALTER TABLE files DROP COLUMN blake3;
+ALTER TABLE file_versions DROP COLUMN sha256;
+ALTER TABLE file_versions DROP COLUMN blake3;
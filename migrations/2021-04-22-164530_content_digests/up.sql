@@ -0,0 +1,8 @@
+-- Hex encoded digests of the content. Blobs are hashed using sha256 already
+ALTER TABLE files ADD COLUMN sha256 text;
+ALTER TABLE files ADD COLUMN blake3 text;
+ALTER TABLE file_versions ADD COLUMN sha256 text;
+ALTER TABLE file_versions ADD COLUMN blake3 text;
+
+UPDATE files SET sha256 = blobs.hash FROM blobs WHERE files.blob_id = blobs.id;
+UPDATE file_versions SET sha256 = blobs.hash FROM blobs WHERE file_versions.blob_id = blobs.id;
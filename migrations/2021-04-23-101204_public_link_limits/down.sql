@@ -0,0 +1,3 @@
+ALTER TABLE files DROP COLUMN public_expires_at;
+ALTER TABLE files DROP COLUMN public_max_downloads;
+ALTER TABLE files DROP COLUMN public_downloads;
@@ -0,0 +1,4 @@
+-- Public links can expire and be limited to an amount of downloads
+ALTER TABLE files ADD COLUMN public_expires_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE files ADD COLUMN public_max_downloads integer;
+ALTER TABLE files ADD COLUMN public_downloads integer NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Argon2id hash of the password protecting the public link
+ALTER TABLE files ADD COLUMN public_password text;
@@ -0,0 +1,19 @@
+ALTER TABLE files ADD COLUMN public_filename text;
+ALTER TABLE files ADD COLUMN public_expires_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE files ADD COLUMN public_max_downloads integer;
+ALTER TABLE files ADD COLUMN public_downloads integer NOT NULL DEFAULT 0;
+ALTER TABLE files ADD COLUMN public_password text;
+
+-- Only the oldest link of each file is kept
+UPDATE files SET
+    public_filename = l.slug,
+    public_expires_at = l.expires_at,
+    public_max_downloads = l.max_downloads,
+    public_downloads = l.downloads,
+    public_password = l.password
+FROM (
+    SELECT DISTINCT ON (file_id) * FROM share_links ORDER BY file_id, created_at, id
+) l
+WHERE files.id = l.file_id;
+
+DROP TABLE share_links;
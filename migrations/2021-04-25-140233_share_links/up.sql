@@ -0,0 +1,31 @@
+-- Public links of files. A file can be shared using multiple links,
+-- which only work while the file is public
+CREATE TABLE share_links (
+    id SERIAL PRIMARY KEY,
+    file_id integer NOT NULL,
+    slug text NOT NULL,
+    label text,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE,
+    password text,
+    max_downloads integer,
+    downloads integer NOT NULL DEFAULT 0,
+    UNIQUE(slug),
+    foreign key (file_id) references files(id) ON DELETE CASCADE
+);
+CREATE INDEX share_links_file_id_idx ON share_links(file_id);
+
+-- Existing public names stay valid. They weren't unique
+-- before, so public files win over unpublished ones
+INSERT INTO share_links (file_id, slug, created_at, expires_at, password, max_downloads, downloads)
+    SELECT DISTINCT ON (public_filename) id, public_filename, uploaded_at, public_expires_at,
+        public_password, public_max_downloads, public_downloads
+    FROM files
+    WHERE public_filename IS NOT NULL
+    ORDER BY public_filename, is_public DESC, id;
+
+ALTER TABLE files DROP COLUMN public_filename;
+ALTER TABLE files DROP COLUMN public_expires_at;
+ALTER TABLE files DROP COLUMN public_max_downloads;
+ALTER TABLE files DROP COLUMN public_downloads;
+ALTER TABLE files DROP COLUMN public_password;
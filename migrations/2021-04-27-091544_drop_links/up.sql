@@ -0,0 +1,21 @@
+-- Upload-only links. Anonymous visitors can upload files
+-- into a namespace of the links owner through them
+CREATE TABLE drop_links (
+    id SERIAL PRIMARY KEY,
+    user_id integer NOT NULL,
+    namespace_id integer NOT NULL,
+    slug text NOT NULL,
+    label text,
+    -- Tags added to each uploaded file
+    tags text[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE,
+    password text,
+    max_file_size bigint,
+    max_files integer,
+    uploads integer NOT NULL DEFAULT 0,
+    UNIQUE(slug),
+    foreign key (user_id) references users(id) ON DELETE CASCADE,
+    foreign key (namespace_id) references namespaces(id) ON DELETE CASCADE
+);
+CREATE INDEX drop_links_user_id_idx ON drop_links(user_id);
@@ -0,0 +1,2 @@
+ALTER TABLE files DROP COLUMN uploader_id;
+DROP TABLE namespace_members;
@@ -0,0 +1,19 @@
+-- Users a namespace is shared with. The owner
+-- of the namespace is never listed as member
+CREATE TABLE namespace_members (
+    id SERIAL PRIMARY KEY,
+    namespace_id integer NOT NULL,
+    user_id integer NOT NULL,
+    role int2 NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    UNIQUE(namespace_id, user_id),
+    foreign key (namespace_id) references namespaces(id) ON DELETE CASCADE,
+    foreign key (user_id) references users(id) ON DELETE CASCADE
+);
+CREATE INDEX namespace_members_user_id_idx ON namespace_members(user_id);
+
+-- Files stay owned by the owner of their namespace,
+-- members uploading files are recorded separately
+ALTER TABLE files ADD COLUMN uploader_id integer;
+ALTER TABLE files ADD foreign key (uploader_id) references users(id) ON DELETE SET NULL;
+UPDATE files SET uploader_id = user_id;
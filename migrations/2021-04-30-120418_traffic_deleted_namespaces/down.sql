@@ -0,0 +1 @@
+DROP INDEX traffic_deleted_namespaces_idx;
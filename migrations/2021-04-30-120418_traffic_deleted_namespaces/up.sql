@@ -0,0 +1,13 @@
+-- Traffic of deleted namespaces is kept without namespace. NULLs never
+-- conflict in the unique constraint, so those rows need their own index.
+-- Rows written before are merged first
+WITH orphaned AS (
+    DELETE FROM traffic WHERE namespace_id IS NULL
+    RETURNING user_id, period, upload, download, preview
+)
+INSERT INTO traffic (user_id, namespace_id, period, upload, download, preview)
+    SELECT user_id, NULL, period, SUM(upload), SUM(download), SUM(preview)
+    FROM orphaned
+    GROUP BY user_id, period;
+
+CREATE UNIQUE INDEX traffic_deleted_namespaces_idx ON traffic(user_id, period) WHERE namespace_id IS NULL;
@@ -0,0 +1,366 @@
+//! Administrative command line tool. All results are
+//! printed as JSON to stdout, errors as {"error": "..."}
+
+#[macro_use]
+extern crate diesel_migrations;
+
+use std::{
+    collections::HashSet,
+    io::{self, BufRead},
+};
+
+use diesel::prelude::*;
+use diesel_migrations::MigrationConnection;
+use dv_server::{
+    config::{Config, OrphanAction, StorageBackendType},
+    db,
+    handlers::file_action::delete_objects,
+    models::{
+        blob,
+        login_session::LoginSession,
+        traffic,
+        user::{Role, User},
+    },
+    response_code::RestError,
+    storage::{
+        self,
+        encryption::Keyring,
+        scrub::{self, ScrubOptions},
+    },
+    DbConnection, DbPool,
+};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+embed_migrations!();
+
+const USAGE: &str = "Usage: dv_admin <command>
+
+Commands:
+    users list
+    users create <username> [password] [--admin]
+    users disable <username>
+    users enable <username>
+    users password <username> [password]
+    users role <username> <user|admin>
+    users legacy-passwords
+    sessions <username>
+    usage [username]
+    migrations status
+    migrations run
+    storage scrub [--checksums] [--flag] [--orphans <report|quarantine|delete>]
+    storage repair [scrub options]
+    keys rotate
+
+Passwords which aren't passed as argument are read from stdin";
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("{0}")]
+    Usage(String),
+
+    #[error("{0}")]
+    Rest(#[from] RestError),
+
+    #[error("{0}")]
+    Database(#[from] diesel::result::Error),
+
+    #[error("{0}")]
+    Migration(#[from] diesel_migrations::RunMigrationsError),
+
+    #[error("{0}")]
+    Pool(#[from] r2d2::Error),
+
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[actix_web::main]
+async fn main() {
+    // Logs go to stderr and don't interfere with the output
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("warn"));
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|i| i.as_str()).collect();
+
+    if args.is_empty() || args.contains(&"--help") {
+        eprintln!("{}", USAGE);
+        return;
+    }
+
+    match run(&args).await {
+        Ok(output) => println!("{}", output),
+        Err(err) => {
+            println!("{}", json!({ "error": err.to_string() }));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Run the command given by 'args'
+async fn run(args: &[&str]) -> Result<Value> {
+    let config = Config::new().await.map_err(Error::Usage)?;
+    let pool = db::connect();
+    let db = pool.get()?;
+
+    match args {
+        ["users", "list"] => list_users(&db),
+        ["users", "create", username, rest @ ..] => {
+            let admin = rest.contains(&"--admin");
+            let rest: Vec<&str> = rest.iter().copied().filter(|i| *i != "--admin").collect();
+
+            let mut new_user = User::new(username.to_string(), password(rest.first())?);
+            if admin {
+                new_user.role = Role::Admin;
+            }
+
+            new_user.create(&db, &config)?;
+            Ok(user_json(&User::find_by_name(&db, username)?))
+        }
+        ["users", action @ ("disable" | "enable"), username] => {
+            let user = User::find_by_name(&db, username)?;
+            user.set_disabled(&db, *action == "disable")?;
+            Ok(user_json(&User::find_by_id(&db, user.id)?))
+        }
+        ["users", "password", username, rest @ ..] if rest.len() <= 1 => {
+            let user = User::find_by_name(&db, username)?;
+            user.set_password(&db, &config, &password(rest.first())?)?;
+            Ok(user_json(&user))
+        }
+        ["users", "role", username, role] => {
+            let role = match *role {
+                "user" => Role::User,
+                "admin" => Role::Admin,
+                _ => return Err(Error::Usage(format!("Unknown role {}", role))),
+            };
+
+            let user = User::find_by_name(&db, username)?;
+            user.set_role(&db, role)?;
+            Ok(user_json(&User::find_by_id(&db, user.id)?))
+        }
+        ["users", "legacy-passwords"] => Ok(json!({
+            "count": User::count_legacy_passwords(&db)?,
+        })),
+        ["sessions", username] => {
+            let user = User::find_by_name(&db, username)?;
+            let sessions: Vec<Value> = LoginSession::list(&db, user.id)?
+                .into_iter()
+                .map(|i| {
+                    json!({
+                        "id": i.id,
+                        "mid": i.machine_id,
+                        "creation": i.created_at,
+                        "lastused": i.last_used,
+                        "expires": i.expires_at,
+                        "expired": i.is_expired(&config),
+                        "requests": i.requests,
+                    })
+                })
+                .collect();
+
+            Ok(json!({ "sessions": sessions }))
+        }
+        ["usage"] => usage(&db, None),
+        ["usage", username] => usage(&db, Some(username)),
+        ["migrations", "status"] => migrations(&db, false),
+        ["migrations", "run"] => {
+            let mut output = migrations(&db, true)?;
+            output["linked"] = json!(link_files(&db, &config)?);
+            Ok(output)
+        }
+        ["storage", "scrub", rest @ ..] => {
+            let options = scrub_options(&config, rest)?;
+            let storage = storage::from_config(&config).map_err(Error::Usage)?;
+            let report = scrub::scrub(&pool, &config, &*storage, options).await?;
+            Ok(json!(report))
+        }
+        ["storage", "repair", rest @ ..] => {
+            let options = scrub_options(&config, rest)?;
+            repair_storage(&config, &pool, options).await
+        }
+        ["keys", "rotate"] => {
+            // Wrap all data keys with the current master key
+            let keyring = Keyring::from_config(&config)
+                .map_err(Error::Usage)?
+                .ok_or_else(|| Error::Usage("No master key set".to_string()))?;
+
+            Ok(json!({ "rotated": blob::rotate_keys(&db, &keyring)? }))
+        }
+        _ => Err(Error::Usage(format!("Unknown command\n\n{}", USAGE))),
+    }
+}
+
+/// Use the given password or read it from stdin
+fn password(arg: Option<&&str>) -> Result<String> {
+    let password = match arg {
+        Some(password) => password.to_string(),
+        None => {
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            line.trim_end_matches(&['\r', '\n'][..]).to_string()
+        }
+    };
+
+    if password.is_empty() {
+        return Err(Error::Usage("Empty password".to_string()));
+    }
+
+    Ok(password)
+}
+
+fn user_json(user: &User) -> Value {
+    json!({
+        "id": user.id,
+        "username": user.username,
+        "role": user.role,
+        "disabled": user.disabled,
+    })
+}
+
+fn list_users(db: &DbConnection) -> Result<Value> {
+    let users: Vec<Value> = User::list(db)?.iter().map(user_json).collect();
+    Ok(json!({ "users": users }))
+}
+
+/// Get the storage usage and traffic of all users or a single one. Traffic
+/// the server hasn't written to the database yet isn't included
+fn usage(db: &DbConnection, username: Option<&str>) -> Result<Value> {
+    let users = match username {
+        Some(username) => vec![User::find_by_name(db, username)?],
+        None => User::list(db)?,
+    };
+
+    let mut usage = vec![];
+    for user in users {
+        let traffic = traffic::usage(db, user.id, None, None, None)?;
+
+        usage.push(json!({
+            "username": user.username,
+            "files": user.total_filecount(db, None)?,
+            "bytes": user.total_filesize(db, None)?,
+            "namespaces": user.total_namespace_count(db)?,
+            "maxbytes": user.quota_bytes,
+            "maxfiles": user.quota_files,
+            "trafficupload": traffic.upload,
+            "trafficdownload": traffic.download,
+            "trafficpreview": traffic.preview,
+        }));
+    }
+
+    Ok(json!({ "users": usage }))
+}
+
+/// Get the applied and pending migrations. Pending migrations are
+/// determined by running them in a transaction which gets rolled
+/// back, unless 'apply' is set
+fn migrations(db: &DbConnection, apply: bool) -> Result<Value> {
+    let conn: &PgConnection = db;
+
+    // Make sure the migrations table exists
+    diesel_migrations::setup_database(conn)?;
+    let applied = conn.previously_run_migration_versions()?;
+
+    let mut pending = None;
+    let res = conn.transaction::<(), Error, _>(|| {
+        embedded_migrations::run(conn)?;
+        pending = Some(new_versions(
+            &applied,
+            conn.previously_run_migration_versions()?,
+        ));
+
+        if apply {
+            Ok(())
+        } else {
+            Err(Error::Database(diesel::result::Error::RollbackTransaction))
+        }
+    });
+
+    match res {
+        Ok(()) | Err(Error::Database(diesel::result::Error::RollbackTransaction)) => (),
+        Err(err) => return Err(err),
+    }
+
+    let mut applied: Vec<String> = applied.into_iter().collect();
+    applied.sort();
+    let pending = pending.unwrap_or_default();
+
+    Ok(if apply {
+        json!({ "applied": applied, "ran": pending })
+    } else {
+        json!({ "applied": applied, "pending": pending })
+    })
+}
+
+/// Link files stored before deduplication was introduced to the blobs of
+/// their content. The server doesn't start while such files exist
+fn link_files(db: &DbConnection, config: &Config) -> Result<usize> {
+    if blob::count_unlinked_files(db)? == 0 {
+        return Ok(0);
+    }
+
+    // These files only exist locally
+    if config.storage.backend != StorageBackendType::Local {
+        return Err(Error::Usage(
+            "Files stored before deduplication can only be linked using the local storage"
+                .to_string(),
+        ));
+    }
+
+    let linked = blob::link_unlinked_files(db, config)?;
+    match blob::count_unlinked_files(db)? {
+        0 => Ok(linked),
+        n => Err(Error::Usage(format!("Couldn't link {} file(s)", n))),
+    }
+}
+
+/// Versions in 'current' which aren't in 'previous'
+fn new_versions(previous: &HashSet<String>, current: HashSet<String>) -> Vec<String> {
+    let mut versions: Vec<String> = current.difference(previous).cloned().collect();
+    versions.sort();
+    versions
+}
+
+/// Get the scrub options of the config, overridden by 'args'
+fn scrub_options(config: &Config, args: &[&str]) -> Result<ScrubOptions> {
+    let mut options = ScrubOptions::from(&config.scrub);
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--checksums" => options.checksums = true,
+            "--flag" => options.flag_corrupted = true,
+            "--orphans" => {
+                options.orphans = match args.next().copied() {
+                    Some("report") => OrphanAction::Report,
+                    Some("quarantine") => OrphanAction::Quarantine,
+                    Some("delete") => OrphanAction::Delete,
+                    _ => return Err(Error::Usage("Invalid orphan action".to_string())),
+                }
+            }
+            _ => return Err(Error::Usage(format!("Unknown option {}", arg))),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Fix the reference counts of blobs and delete unreferenced blobs along with
+/// their objects, then scrub the storage. Objects which are still missing or
+/// damaged afterwards can't be repaired
+async fn repair_storage(config: &Config, pool: &DbPool, options: ScrubOptions) -> Result<Value> {
+    let storage = storage::from_config(config).map_err(Error::Usage)?;
+
+    let (fixed, deleted) = blob::repair_ref_counts(&pool.get()?)?;
+    let deleted: Vec<String> = deleted.into_iter().map(|i| i.local_name).collect();
+    delete_objects(&*storage, &deleted).await;
+
+    let report = scrub::scrub(pool, config, &*storage, options).await?;
+
+    Ok(json!({
+        "fixedblobs": fixed,
+        "deletedblobs": deleted,
+        "scrub": report,
+    }))
+}
@@ -12,6 +12,20 @@ use async_std::{
 pub struct Config {
     pub server: ServerConfig,
     pub preview: Preview,
+    #[serde(default)]
+    pub url_upload: UrlUpload,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    #[serde(default)]
+    pub password_hashing: PasswordHashing,
+    #[serde(default)]
+    pub quota: QuotaConfig,
+    #[serde(default)]
+    pub scrub: ScrubConfig,
+    #[serde(default)]
+    pub hashing: HashingConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -24,6 +38,10 @@ pub struct ServerConfig {
     pub cors_allow: Option<Vec<String>>,
     pub max_preview_filesize: Option<u64>,
     pub listen_address: String,
+    pub upload_session_expiry: Option<u64>,
+    pub trash_retention: Option<u64>,
+    pub session_idle_timeout: Option<u64>,
+    pub session_lifetime: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -31,6 +49,121 @@ pub struct Preview {
     pub ace_theme: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct UrlUpload {
+    pub max_size: Option<u64>,
+    pub timeout: Option<u64>,
+    pub allowed_hosts: Option<Vec<String>>,
+    pub denied_hosts: Option<Vec<String>>,
+    #[serde(default)]
+    pub allow_private_hosts: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackendType,
+    pub s3: Option<S3Config>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackendType {
+    #[default]
+    Local,
+    S3,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    #[serde(default)]
+    pub path_style: bool,
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct EncryptionConfig {
+    pub master_key: Option<String>,
+    #[serde(default)]
+    pub previous_master_keys: Vec<String>,
+}
+
+/// Quotas applied to newly registered users. None means unlimited
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct QuotaConfig {
+    pub default_bytes: Option<i64>,
+    pub default_files: Option<i64>,
+}
+
+/// Digests calculated for uploaded content. Crc32
+/// and sha256 digests are always calculated
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct HashingConfig {
+    #[serde(default)]
+    pub blake3: bool,
+}
+
+/// Storage integrity checks
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct ScrubConfig {
+    /// Hours between scheduled scrubs. Disabled if not set
+    pub interval: Option<u64>,
+    #[serde(default)]
+    pub checksums: bool,
+    #[serde(default)]
+    pub orphans: OrphanAction,
+    #[serde(default)]
+    pub flag_corrupted: bool,
+    pub quarantine_path: Option<String>,
+}
+
+/// What to do with local files not belonging to any file or upload
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanAction {
+    #[default]
+    Report,
+    Quarantine,
+    Delete,
+}
+
+/// Argon2id parameters used for hashing passwords
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PasswordHashing {
+    /// Memory in KiB
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashing {
+    fn default() -> Self {
+        PasswordHashing {
+            memory_cost: 19 * 1024,
+            time_cost: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Default lifetime of an idle upload session (24h)
+pub const DEFAULT_UPLOAD_SESSION_EXPIRY: u64 = 24 * 60 * 60;
+
+/// Default time deleted files are kept in the trash (30 days)
+pub const DEFAULT_TRASH_RETENTION: u64 = 30 * 24 * 60 * 60;
+
+/// Default time after which an unused login session expires (7 days)
+pub const DEFAULT_SESSION_IDLE_TIMEOUT: u64 = 7 * 24 * 60 * 60;
+
+/// Default time after which a login session expires regardless of its use (30 days)
+pub const DEFAULT_SESSION_LIFETIME: u64 = 30 * 24 * 60 * 60;
+
 impl Config {
     /// Create a new config object
     pub async fn new() -> Result<Self, String> {
@@ -88,6 +221,61 @@ impl Config {
         Ok(())
     }
 
+    /// Time after which an idle upload session expires
+    pub fn upload_session_expiry(&self) -> chrono::Duration {
+        chrono::Duration::seconds(
+            self.server
+                .upload_session_expiry
+                .unwrap_or(DEFAULT_UPLOAD_SESSION_EXPIRY) as i64,
+        )
+    }
+
+    /// Time after which deleted files get purged from the trash
+    pub fn trash_retention(&self) -> chrono::Duration {
+        chrono::Duration::seconds(
+            self.server
+                .trash_retention
+                .unwrap_or(DEFAULT_TRASH_RETENTION) as i64,
+        )
+    }
+
+    /// Time after which an unused login session expires
+    pub fn session_idle_timeout(&self) -> chrono::Duration {
+        chrono::Duration::seconds(
+            self.server
+                .session_idle_timeout
+                .unwrap_or(DEFAULT_SESSION_IDLE_TIMEOUT) as i64,
+        )
+    }
+
+    /// Time after which a login session expires regardless of its use
+    pub fn session_lifetime(&self) -> chrono::Duration {
+        chrono::Duration::seconds(
+            self.server
+                .session_lifetime
+                .unwrap_or(DEFAULT_SESSION_LIFETIME) as i64,
+        )
+    }
+
+    /// Directory orphaned files are moved into
+    pub fn quarantine_path(&self) -> std::path::PathBuf {
+        match self.scrub.quarantine_path {
+            Some(ref path) => std::path::PathBuf::from(path),
+            None => std::path::Path::new(&self.server.file_output_path).join("quarantine"),
+        }
+    }
+
+    /// A default config storing files in a new temporary directory
+    #[cfg(test)]
+    pub fn temporary() -> Self {
+        let dir = std::env::temp_dir().join(crate::utils::random_string(10));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut config = Config::default();
+        config.server.file_output_path = dir.to_str().unwrap().to_owned();
+        config
+    }
+
     // Create missing folders and return the config file
     pub async fn get_config_file() -> Result<PathBuf, String> {
         let conf_dir: PathBuf = Path::new("./").join("data");
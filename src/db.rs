@@ -1,5 +1,7 @@
 use std::env;
 
+#[cfg(test)]
+use diesel::Connection;
 use diesel::{r2d2::ConnectionManager, PgConnection};
 use dotenv::dotenv;
 use r2d2::Pool;
@@ -16,3 +18,38 @@ pub fn connect() -> Pool<ConnectionManager<PgConnection>> {
         .build(manager)
         .expect("Failed to create pool")
 }
+
+/// A pool of the database tests are run against. Its only connection runs
+/// in a test transaction, so nothing written through it gets committed.
+/// Tests using it need DATABASE_URL and are run with 'cargo test -- --ignored'
+#[cfg(test)]
+pub fn test_pool() -> crate::DbPool {
+    dotenv().ok();
+    let url = env::var("DATABASE_URL").expect("DATABASE_URL must be set to run database tests");
+
+    r2d2::Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(TestTransaction))
+        .build(ConnectionManager::<PgConnection>::new(url))
+        .expect("Couldn't connect to the test database")
+}
+
+/// A connection of the pool returned by 'test_pool'
+#[cfg(test)]
+pub fn test_connection() -> crate::DbConnection {
+    test_pool()
+        .get()
+        .expect("Couldn't connect to the test database")
+}
+
+#[cfg(test)]
+#[derive(Debug)]
+struct TestTransaction;
+
+#[cfg(test)]
+impl r2d2::CustomizeConnection<PgConnection, diesel::r2d2::Error> for TestTransaction {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.begin_test_transaction()
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
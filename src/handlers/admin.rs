@@ -0,0 +1,224 @@
+use super::{
+    authentication::{clear_namespace_cache, AdminUser},
+    file_action::delete_objects,
+    requests::admin::{
+        CreateUserRequest, UserPasswordRequest, UserQuotaRequest, UserRequest, UserRoleRequest,
+    },
+    response::{AdminUserItemResponse, AdminUserListResponse, QuotaResponse},
+};
+use crate::{
+    config::Config,
+    models::{
+        quota::Quota,
+        traffic,
+        user::{Role, User},
+    },
+    response_code::{RestError, Success, SUCCESS},
+    storage::StorageBackend,
+    DbPool,
+};
+
+use actix_web::web::{self, Json};
+
+/// Endpoint for listing all users along with their usage
+pub async fn ep_list_users(
+    pool: web::Data<DbPool>,
+    _admin: AdminUser,
+) -> Result<Json<AdminUserListResponse>, RestError> {
+    let db = pool.get()?;
+
+    let users = web::block(move || -> Result<_, RestError> {
+        // Include the traffic which wasn't written yet
+        traffic::flush(&db)?;
+
+        let mut users = vec![];
+        for user in User::list(&db)? {
+            let traffic = traffic::usage(&db, user.id, None, None, None)?;
+            let total_files = user.total_filecount(&db, None)?;
+            let total_filesize = user.total_filesize(&db, None)?;
+
+            users.push(AdminUserItemResponse {
+                namespaces_count: user.total_namespace_count(&db)?,
+                traffic_used: traffic.total(),
+                quota: QuotaResponse::new(user.quota(), total_filesize, total_files),
+                id: user.id,
+                role: user.role,
+                disabled: user.disabled,
+                username: user.username,
+            });
+        }
+
+        Ok(users)
+    })
+    .await??;
+
+    Ok(Json(AdminUserListResponse { users }))
+}
+
+/// Endpoint for creating a user. Works regardless
+/// of whether registrations are allowed or not
+pub async fn ep_create_user(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    _admin: AdminUser,
+    request: Json<CreateUserRequest>,
+) -> Result<Json<Success>, RestError> {
+    let request = request.into_inner();
+
+    if request.username.is_empty() || request.password.is_empty() {
+        return Err(RestError::BadRequest);
+    }
+
+    let mut new_user = User::new(request.username, request.password);
+    new_user.role = request.role;
+
+    let db = pool.get()?;
+    web::block(move || new_user.create(&db, &config)).await??;
+
+    Ok(SUCCESS)
+}
+
+/// Endpoint for disabling a user. All of their sessions are ended
+pub async fn ep_disable_user(
+    pool: web::Data<DbPool>,
+    admin: AdminUser,
+    request: Json<UserRequest>,
+) -> Result<Json<Success>, RestError> {
+    set_disabled(pool, admin, request.into_inner(), true).await
+}
+
+/// Endpoint for enabling a disabled user
+pub async fn ep_enable_user(
+    pool: web::Data<DbPool>,
+    admin: AdminUser,
+    request: Json<UserRequest>,
+) -> Result<Json<Success>, RestError> {
+    set_disabled(pool, admin, request.into_inner(), false).await
+}
+
+async fn set_disabled(
+    pool: web::Data<DbPool>,
+    admin: AdminUser,
+    request: UserRequest,
+    disabled: bool,
+) -> Result<Json<Success>, RestError> {
+    let db = pool.get()?;
+
+    web::block(move || -> Result<(), RestError> {
+        let user = User::find_by_name(&db, &request.username)?;
+
+        // Admins can't lock themselves out
+        if user.id == admin.0.user.id {
+            return Err(RestError::IllegalOperation);
+        }
+
+        user.set_disabled(&db, disabled)
+    })
+    .await??;
+
+    Ok(SUCCESS)
+}
+
+/// Endpoint for resetting the password of a user. All of their sessions are ended
+pub async fn ep_set_password(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    _admin: AdminUser,
+    request: Json<UserPasswordRequest>,
+) -> Result<Json<Success>, RestError> {
+    if request.password.is_empty() {
+        return Err(RestError::BadRequest);
+    }
+
+    let db = pool.get()?;
+
+    web::block(move || -> Result<(), RestError> {
+        let user = User::find_by_name(&db, &request.username)?;
+        user.set_password(&db, &config, &request.password)
+    })
+    .await??;
+
+    Ok(SUCCESS)
+}
+
+/// Endpoint for setting the quota of a user
+pub async fn ep_set_quota(
+    pool: web::Data<DbPool>,
+    _admin: AdminUser,
+    request: Json<UserQuotaRequest>,
+) -> Result<Json<Success>, RestError> {
+    if request.bytes.map(|i| i < 0).unwrap_or(false)
+        || request.files.map(|i| i < 0).unwrap_or(false)
+    {
+        return Err(RestError::BadRequest);
+    }
+
+    let db = pool.get()?;
+
+    web::block(move || -> Result<(), RestError> {
+        let user = User::find_by_name(&db, &request.username)?;
+        user.set_quota(
+            &db,
+            Quota {
+                bytes: request.bytes,
+                files: request.files,
+            },
+        )
+    })
+    .await??;
+
+    Ok(SUCCESS)
+}
+
+/// Endpoint for changing the role of a user
+pub async fn ep_set_role(
+    pool: web::Data<DbPool>,
+    admin: AdminUser,
+    request: Json<UserRoleRequest>,
+) -> Result<Json<Success>, RestError> {
+    let db = pool.get()?;
+
+    web::block(move || -> Result<(), RestError> {
+        let user = User::find_by_name(&db, &request.username)?;
+
+        // Admins can't take away their own role
+        if user.id == admin.0.user.id && request.role != Role::Admin {
+            return Err(RestError::IllegalOperation);
+        }
+
+        user.set_role(&db, request.role)
+    })
+    .await??;
+
+    Ok(SUCCESS)
+}
+
+/// Endpoint for deleting a user along with all of their namespaces and files
+pub async fn ep_delete_user(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    admin: AdminUser,
+    request: Json<UserRequest>,
+) -> Result<Json<Success>, RestError> {
+    let db = pool.get()?;
+
+    let (user_id, orphaned) = web::block(move || -> Result<_, RestError> {
+        let user = User::find_by_name(&db, &request.username)?;
+
+        if user.id == admin.0.user.id {
+            return Err(RestError::IllegalOperation);
+        }
+
+        // Write pending traffic first so it can be deleted as well
+        traffic::flush(&db)?;
+
+        Ok((user.id, user.delete(&db, &config)?))
+    })
+    .await??;
+
+    clear_namespace_cache(user_id);
+    delete_objects(&**storage, &orphaned).await;
+
+    Ok(SUCCESS)
+}
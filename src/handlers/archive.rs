@@ -0,0 +1,723 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    convert::TryFrom,
+    io::{self, Write},
+    rc::Rc,
+    sync::Arc,
+};
+
+use super::{
+    authentication::Authenticateduser,
+    requests::file::{ArchiveFormat, ArchiveRequest},
+    utils::retrieve_namespace_by_name,
+};
+use crate::{
+    config::Config,
+    models::{
+        attribute::{Attribute, AttributeType},
+        file::File,
+        namespace::Namespace,
+        namespace_member::MemberRole,
+        traffic::{self, TrafficKind},
+    },
+    response_code::{Origin, RestError},
+    storage::{self, encryption::DataKey, StorageBackend},
+    DbConnection, DbPool,
+};
+
+use actix_web::{
+    http::header::{self, ContentDisposition, DispositionParam, DispositionType},
+    rt,
+    web::{self, Json},
+    HttpResponse,
+};
+use bytes::Bytes;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::{write::GzEncoder, Compression};
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use itertools::Itertools;
+use serde::Serialize;
+
+/// Name of the entry listing the attributes of the files
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Amount of chunks buffered for a client
+const CHANNEL_SIZE: usize = 4;
+
+type ArchiveSender = mpsc::Sender<Result<Bytes, actix_web::Error>>;
+type SelectedFile = (File, Namespace, Vec<Attribute>);
+
+/// Endpoint for downloading multiple files as a single archive.
+/// The archive is built while it's being sent
+pub async fn ep_download_archive(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    request: Json<ArchiveRequest>,
+    user: Authenticateduser,
+) -> Result<HttpResponse, RestError> {
+    let request = request.into_inner();
+    let format = request.format;
+    let manifest = request.manifest;
+
+    let db = pool.get()?;
+    let (files, name) = web::block(move || select_files(&db, &request, user)).await??;
+
+    if files.is_empty() {
+        return Err(RestError::DNotFound(Origin::Files));
+    }
+
+    let archive = Archive::new(&config, format, files, manifest)?;
+
+    let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+    let storage = Arc::clone(&storage);
+    rt::spawn(async move { send_archive(archive, &*storage, tx).await });
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, format.content_type()))
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "{}.{}",
+                name,
+                format.extension()
+            ))],
+        })
+        .streaming(rx))
+}
+
+/// Select the files of an archive request. Exactly one kind of selection
+/// has to be set. Returns the files and the name of the archive
+fn select_files(
+    db: &DbConnection,
+    request: &ArchiveRequest,
+    user: Authenticateduser,
+) -> Result<(Vec<SelectedFile>, String), RestError> {
+    match (&request.file_ids, &request.search, &request.namespace) {
+        (Some(ids), None, None) => {
+            let ids: Vec<i32> = ids.iter().copied().unique().collect();
+            let files = File::list_with_attributes(db, user.user.id, Some(&ids), None)?;
+
+            // All requested files have to exist
+            if files.len() != ids.len() {
+                return Err(RestError::DNotFound(Origin::Files));
+            }
+
+            Ok((files, "files".to_string()))
+        }
+        (None, Some(search), None) => {
+            Ok((File::search(db, search, user.user)?, "files".to_string()))
+        }
+        (None, None, Some(namespace)) => {
+            let namespace = retrieve_namespace_by_name(db, namespace, &user, MemberRole::Viewer)?;
+            let files = File::list_with_attributes(db, user.user.id, None, Some(namespace.id))?;
+            Ok((files, namespace.name))
+        }
+        _ => Err(RestError::BadRequest),
+    }
+}
+
+/// The content of an archive
+struct Archive {
+    format: ArchiveFormat,
+    entries: Vec<Entry>,
+    manifest: Option<Vec<u8>>,
+}
+
+/// A file written into an archive
+struct Entry {
+    name: String,
+    file: File,
+    key: Option<DataKey>,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    files: Vec<ManifestItem>,
+}
+
+#[derive(Serialize)]
+struct ManifestItem {
+    /// Name of the files entry
+    name: String,
+    id: i32,
+    #[serde(rename = "filename")]
+    file_name: String,
+    #[serde(rename = "ns")]
+    namespace: String,
+    tags: Vec<String>,
+    groups: Vec<String>,
+    size: i64,
+    #[serde(rename = "creation")]
+    creation_date: DateTime<Utc>,
+    checksum: String,
+    sha256: Option<String>,
+}
+
+impl Archive {
+    /// Name the entries of the selected files and check whether the
+    /// archive can be built. Files with equal names get numbered
+    fn new(
+        config: &Config,
+        format: ArchiveFormat,
+        files: Vec<SelectedFile>,
+        manifest: bool,
+    ) -> Result<Self, RestError> {
+        let mut used = HashSet::new();
+        if manifest {
+            used.insert(MANIFEST_NAME.to_string());
+        }
+
+        let mut entries = Vec::with_capacity(files.len());
+        let mut items = Vec::with_capacity(files.len());
+
+        for (file, namespace, attributes) in files {
+            let name = unique_name(&file.name, &mut used);
+
+            if manifest {
+                let (tags, groups): (Vec<Attribute>, Vec<Attribute>) = attributes
+                    .into_iter()
+                    .partition(|i| i.type_ == AttributeType::Tag);
+
+                items.push(ManifestItem {
+                    name: name.clone(),
+                    id: file.id,
+                    file_name: file.name.clone(),
+                    namespace: namespace.name,
+                    tags: tags.into_iter().map(|i| i.name).collect(),
+                    groups: groups.into_iter().map(|i| i.name).collect(),
+                    size: file.file_size,
+                    creation_date: file.uploaded_at,
+                    checksum: file.checksum.clone(),
+                    sha256: file.sha256.clone(),
+                });
+            }
+
+            entries.push(Entry {
+                name,
+                key: file.data_key(config)?,
+                file,
+            });
+        }
+
+        let manifest = if manifest {
+            Some(
+                serde_json::to_vec_pretty(&Manifest { files: items })
+                    .map_err(|_| RestError::Internal)?,
+            )
+        } else {
+            None
+        };
+
+        let archive = Archive {
+            format,
+            entries,
+            manifest,
+        };
+
+        if format == ArchiveFormat::Zip && !archive.fits_zip() {
+            return Err(RestError::ArchiveTooLarge);
+        }
+
+        Ok(archive)
+    }
+
+    /// Zip archives without zip64 extensions are limited
+    /// to 4GB and 65535 entries, which the writer can't create
+    fn fits_zip(&self) -> bool {
+        let mut sizes: Vec<(usize, u64)> = self
+            .entries
+            .iter()
+            .map(|i| (i.name.len(), i.file.file_size as u64))
+            .collect();
+
+        if let Some(ref manifest) = self.manifest {
+            sizes.push((MANIFEST_NAME.len(), manifest.len() as u64));
+        }
+
+        // Local header, content, data descriptor and
+        // central directory header of each entry
+        let total: u64 = sizes
+            .iter()
+            .map(|(name, size)| 30 + 16 + 46 + 2 * *name as u64 + size)
+            .sum::<u64>()
+            + 22;
+
+        sizes.len() <= u16::MAX as usize && total <= u32::MAX as u64
+    }
+}
+
+/// Write an archive into 'tx'. The response was started already
+/// so errors can only be reported by aborting it
+async fn send_archive(archive: Archive, storage: &dyn StorageBackend, mut tx: ArchiveSender) {
+    if let Err(err) = write_archive(archive, storage, &mut tx).await {
+        debug!("Archive download aborted: {:?}", err);
+        tx.send(Err(err.into())).await.ok();
+    }
+}
+
+async fn write_archive(
+    archive: Archive,
+    storage: &dyn StorageBackend,
+    tx: &mut ArchiveSender,
+) -> Result<(), RestError> {
+    let sink = Sink::default();
+    let mut writer = ArchiveWriter::new(archive.format, sink.clone());
+
+    if let Some(manifest) = archive.manifest {
+        let size = manifest.len() as u64;
+        writer.start_entry(MANIFEST_NAME, size, Utc::now())?;
+        writer.write(&manifest)?;
+        writer.finish_entry(size)?;
+    }
+
+    for entry in archive.entries {
+        let file = &entry.file;
+        let size = file.file_size as u64;
+        writer.start_entry(&entry.name, size, file.uploaded_at)?;
+
+        let mut stream =
+            storage::read(storage, &file.local_name, entry.key.as_ref(), 0, size).await?;
+        let mut written = 0;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|_| RestError::UnknownIo)?;
+            writer.write(&chunk)?;
+            written += chunk.len() as u64;
+
+            send(tx, &sink).await?;
+            traffic::record(
+                file.user_id,
+                file.namespace_id,
+                TrafficKind::Download,
+                chunk.len() as u64,
+            );
+        }
+
+        // The size was announced in the header already
+        if written != size {
+            return Err(RestError::UnknownIo);
+        }
+
+        writer.finish_entry(size)?;
+    }
+
+    writer.finish()?;
+    send(tx, &sink).await
+}
+
+/// Send the data written into 'sink' so far
+async fn send(tx: &mut ArchiveSender, sink: &Sink) -> Result<(), RestError> {
+    let data = sink.take();
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    // Fails if the client is gone
+    tx.send(Ok(data)).await.map_err(|_| RestError::UnknownIo)
+}
+
+/// Get a name for an entry which isn't used yet. Names are compared
+/// case insensitive, since not all filesystems distinguish them by case
+fn unique_name(name: &str, used: &mut HashSet<String>) -> String {
+    let name = sanitize_name(name);
+    let (stem, extension) = match name.rfind('.') {
+        Some(pos) if pos > 0 => name.split_at(pos),
+        _ => (name.as_str(), ""),
+    };
+
+    let mut candidate = name.clone();
+    let mut i = 1;
+    while !used.insert(candidate.to_lowercase()) {
+        candidate = format!("{} ({}){}", stem, i, extension);
+        i += 1;
+    }
+
+    candidate
+}
+
+/// Make a file name usable as entry name
+fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    match name.trim() {
+        "" | "." | ".." => "file".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Writes entries in one of the supported formats into a Sink
+enum ArchiveWriter {
+    Zip(ZipStream<Sink>),
+    Tar(tar::Builder<Sink>),
+    TarGz(tar::Builder<GzEncoder<Sink>>),
+}
+
+impl ArchiveWriter {
+    fn new(format: ArchiveFormat, sink: Sink) -> Self {
+        match format {
+            ArchiveFormat::Zip => ArchiveWriter::Zip(ZipStream::new(sink)),
+            ArchiveFormat::Tar => ArchiveWriter::Tar(tar::Builder::new(sink)),
+            ArchiveFormat::TarGz => {
+                ArchiveWriter::TarGz(tar::Builder::new(GzEncoder::new(sink, Compression::fast())))
+            }
+        }
+    }
+
+    /// Start an entry with a content of 'size' bytes
+    fn start_entry(&mut self, name: &str, size: u64, modified: DateTime<Utc>) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => zip.start_entry(name, modified),
+            ArchiveWriter::Tar(builder) => append_tar_header(builder, name, size, modified),
+            ArchiveWriter::TarGz(builder) => append_tar_header(builder, name, size, modified),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => zip.write(data),
+            ArchiveWriter::Tar(builder) => builder.get_mut().write_all(data),
+            ArchiveWriter::TarGz(builder) => builder.get_mut().write_all(data),
+        }
+    }
+
+    /// Finish an entry with a content of 'size' bytes
+    fn finish_entry(&mut self, size: u64) -> io::Result<()> {
+        // Tar entries are padded to full blocks
+        let padding = [0; 512];
+        let remaining = ((512 - size % 512) % 512) as usize;
+
+        match self {
+            ArchiveWriter::Zip(zip) => zip.finish_entry(),
+            ArchiveWriter::Tar(builder) => builder.get_mut().write_all(&padding[..remaining]),
+            ArchiveWriter::TarGz(builder) => builder.get_mut().write_all(&padding[..remaining]),
+        }
+    }
+
+    /// Write the end of the archive
+    fn finish(self) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => {
+                zip.finish()?;
+            }
+            ArchiveWriter::Tar(builder) => {
+                builder.into_inner()?;
+            }
+            ArchiveWriter::TarGz(builder) => {
+                builder.into_inner()?.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Write the header of a tar entry. The content is written separately
+/// since it's only available as stream
+fn append_tar_header<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    size: u64,
+    modified: DateTime<Utc>,
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(modified.timestamp().max(0) as u64);
+
+    // Long names are written as extra entry
+    builder.append_data(&mut header, name, io::empty())
+}
+
+/// Entries have a data descriptor and UTF-8 encoded names
+const ZIP_FLAGS: u16 = 1 << 3 | 1 << 11;
+
+/// Writes a zip archive of uncompressed entries without seeking. The
+/// checksum and size of an entry are written in a data descriptor
+/// following its content, since they're unknown when it starts
+struct ZipStream<W: Write> {
+    inner: W,
+    /// Amount of bytes written
+    offset: u64,
+    entries: Vec<ZipEntry>,
+    current: Option<(ZipEntry, crc32fast::Hasher)>,
+}
+
+struct ZipEntry {
+    name: String,
+    /// Offset of the local header
+    offset: u32,
+    time: u16,
+    date: u16,
+    crc32: u32,
+    size: u32,
+}
+
+impl<W: Write> ZipStream<W> {
+    fn new(inner: W) -> Self {
+        ZipStream {
+            inner,
+            offset: 0,
+            entries: vec![],
+            current: None,
+        }
+    }
+
+    fn start_entry(&mut self, name: &str, modified: DateTime<Utc>) -> io::Result<()> {
+        let (time, date) = dos_date_time(modified);
+        let entry = ZipEntry {
+            name: name.to_string(),
+            offset: zip_offset(self.offset)?,
+            time,
+            date,
+            crc32: 0,
+            size: 0,
+        };
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&0x0403_4b50_u32.to_le_bytes());
+        header.extend_from_slice(&20_u16.to_le_bytes()); // Version needed
+        header.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+        header.extend_from_slice(&0_u16.to_le_bytes()); // Stored
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&date.to_le_bytes());
+        header.extend_from_slice(&[0; 12]); // Checksum and sizes
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0_u16.to_le_bytes()); // Extra field length
+        header.extend_from_slice(name.as_bytes());
+
+        self.write_raw(&header)?;
+        self.current = Some((entry, crc32fast::Hasher::new()));
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let (entry, hasher) = self.current.as_mut().ok_or_else(no_entry)?;
+        hasher.update(data);
+        entry.size = zip_offset(entry.size as u64 + data.len() as u64)?;
+        self.write_raw(data)
+    }
+
+    fn finish_entry(&mut self) -> io::Result<()> {
+        let (mut entry, hasher) = self.current.take().ok_or_else(no_entry)?;
+        entry.crc32 = hasher.finalize();
+
+        let mut descriptor = Vec::with_capacity(16);
+        descriptor.extend_from_slice(&0x0807_4b50_u32.to_le_bytes());
+        descriptor.extend_from_slice(&entry.crc32.to_le_bytes());
+        descriptor.extend_from_slice(&entry.size.to_le_bytes());
+        descriptor.extend_from_slice(&entry.size.to_le_bytes());
+
+        self.write_raw(&descriptor)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Write the central directory
+    fn finish(mut self) -> io::Result<W> {
+        let start = zip_offset(self.offset)?;
+        let entries = std::mem::take(&mut self.entries);
+
+        for entry in entries.iter() {
+            let mut header = Vec::with_capacity(46 + entry.name.len());
+            header.extend_from_slice(&0x0201_4b50_u32.to_le_bytes());
+            header.extend_from_slice(&(3 << 8 | 20_u16).to_le_bytes()); // Made by unix
+            header.extend_from_slice(&20_u16.to_le_bytes()); // Version needed
+            header.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+            header.extend_from_slice(&0_u16.to_le_bytes()); // Stored
+            header.extend_from_slice(&entry.time.to_le_bytes());
+            header.extend_from_slice(&entry.date.to_le_bytes());
+            header.extend_from_slice(&entry.crc32.to_le_bytes());
+            header.extend_from_slice(&entry.size.to_le_bytes());
+            header.extend_from_slice(&entry.size.to_le_bytes());
+            header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&[0; 8]); // Extra, comment, disk and internal attributes
+            header.extend_from_slice(&(0o100644_u32 << 16).to_le_bytes());
+            header.extend_from_slice(&entry.offset.to_le_bytes());
+            header.extend_from_slice(entry.name.as_bytes());
+            self.write_raw(&header)?;
+        }
+
+        let count = u16::try_from(entries.len()).map_err(|_| too_large())?;
+        let size = zip_offset(self.offset)? - start;
+
+        let mut end = Vec::with_capacity(22);
+        end.extend_from_slice(&0x0605_4b50_u32.to_le_bytes());
+        end.extend_from_slice(&[0; 4]); // Disk numbers
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&size.to_le_bytes());
+        end.extend_from_slice(&start.to_le_bytes());
+        end.extend_from_slice(&0_u16.to_le_bytes()); // Comment length
+        self.write_raw(&end)?;
+
+        Ok(self.inner)
+    }
+
+    fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+}
+
+/// Offsets and sizes of zip archives without zip64 extensions are 32 bit
+fn zip_offset(offset: u64) -> io::Result<u32> {
+    u32::try_from(offset).map_err(|_| too_large())
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "Archive too large")
+}
+
+fn no_entry() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "No entry started")
+}
+
+/// Convert a date into the MS-DOS time and date format used by zip
+/// archives. Dates before 1980 can't be represented and are clamped
+fn dos_date_time(date: DateTime<Utc>) -> (u16, u16) {
+    if date.year() < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+
+    let time = (date.hour() << 11 | date.minute() << 5 | (date.second() / 2)) as u16;
+    let day = ((date.year() - 1980).min(127) as u32) << 9 | date.month() << 5 | date.day();
+    (time, day as u16)
+}
+
+/// Collects the output of an archive writer until it gets sent
+#[derive(Clone, Default)]
+struct Sink(Rc<RefCell<Vec<u8>>>);
+
+impl Sink {
+    /// Take the buffered data
+    fn take(&self) -> Bytes {
+        Bytes::from(std::mem::take(&mut *self.0.borrow_mut()))
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::io::{Cursor, Read};
+
+    /// Write entries of (name, content) and collect the output
+    fn build(format: ArchiveFormat, entries: &[(&str, &[u8])]) -> io::Result<Vec<u8>> {
+        let sink = Sink::default();
+        let mut writer = ArchiveWriter::new(format, sink.clone());
+        let mut output = vec![];
+
+        for (name, content) in entries {
+            let size = content.len() as u64;
+            writer.start_entry(name, size, Utc::now())?;
+            // Send the header before the content is written
+            output.extend_from_slice(&sink.take());
+            writer.write(content)?;
+            writer.finish_entry(size)?;
+        }
+
+        writer.finish()?;
+        output.extend_from_slice(&sink.take());
+        Ok(output)
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let mut used = HashSet::new();
+        used.insert(MANIFEST_NAME.to_string());
+
+        assert_eq!(unique_name("a.txt", &mut used), "a.txt");
+        assert_eq!(unique_name("A.txt", &mut used), "A (1).txt");
+        assert_eq!(unique_name("a.txt", &mut used), "a (2).txt");
+        assert_eq!(unique_name("manifest.json", &mut used), "manifest (1).json");
+        assert_eq!(unique_name("../x/y", &mut used), ".._x_y");
+        assert_eq!(unique_name("..", &mut used), "file");
+        assert_eq!(unique_name(".bashrc", &mut used), ".bashrc");
+        assert_eq!(unique_name(".bashrc", &mut used), ".bashrc (1)");
+    }
+
+    #[test]
+    fn test_zip() {
+        let data = build(
+            ArchiveFormat::Zip,
+            &[("a.txt", b"hello"), ("empty", b""), ("b.txt", b"world")],
+        )
+        .unwrap();
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+        assert_eq!(zip.len(), 3);
+
+        // Reading verifies the checksums of the entries
+        for (name, expected) in &[("a.txt", "hello"), ("empty", ""), ("b.txt", "world")] {
+            let mut entry = zip.by_name(name).unwrap();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            assert_eq!(&content, expected);
+            assert_eq!(entry.unix_mode(), Some(0o100644));
+        }
+    }
+
+    #[test]
+    fn test_dos_date_time() {
+        let date = Utc.ymd(2021, 4, 20).and_hms(13, 37, 42);
+        assert_eq!(
+            dos_date_time(date),
+            (13 << 11 | 37 << 5 | 21, 41 << 9 | 4 << 5 | 20)
+        );
+        assert_eq!(dos_date_time(Utc.timestamp(0, 0)), (0, 1 << 5 | 1));
+    }
+
+    #[test]
+    fn test_tar() {
+        let long_name = "x".repeat(150);
+        let data = build(
+            ArchiveFormat::TarGz,
+            &[(&long_name, b"hello"), ("b.txt", b"world")],
+        )
+        .unwrap();
+
+        let decoder = flate2::read::GzDecoder::new(Cursor::new(data));
+        let mut tar = tar::Archive::new(decoder);
+        let entries: Vec<(String, String)> = tar
+            .entries()
+            .unwrap()
+            .map(|i| {
+                let mut entry = i.unwrap();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                let name = entry.path().unwrap().to_string_lossy().to_string();
+                (name, content)
+            })
+            .collect();
+
+        assert_eq!(
+            entries,
+            vec![
+                (long_name, "hello".to_string()),
+                ("b.txt".to_string(), "world".to_string())
+            ]
+        );
+    }
+}
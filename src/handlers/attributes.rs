@@ -3,6 +3,7 @@ use crate::{
     models::{
         attribute::{self, Attribute, AttributeType},
         namespace::Namespace,
+        namespace_member::MemberRole,
     },
     response_code, DbConnection,
 };
@@ -20,7 +21,6 @@ struct RequestData {
     attr_type: AttributeType,
     namespace: Namespace,
     request: UpdateAttribute,
-    user: Authenticateduser,
 }
 
 /// Endpoint for registering new users
@@ -37,7 +37,8 @@ pub async fn ep_list_attributes(
     let namespace = utils::retrieve_namespace_by_name_async(
         pool.get()?,
         request.namespace.clone(),
-        user.clone(),
+        user,
+        MemberRole::Viewer,
     )
     .await?;
 
@@ -47,7 +48,6 @@ pub async fn ep_list_attributes(
         attr_type: to_attribute(attr_type.into_inner())?,
         namespace,
         request: request.clone(),
-        user,
     };
 
     let res = web::block(move || request_data.get()).await??;
@@ -70,7 +70,8 @@ pub async fn ep_attribute_action(
     let namespace = utils::retrieve_namespace_by_name_async(
         pool.get()?,
         request.namespace.clone(),
-        user.clone(),
+        user,
+        MemberRole::Editor,
     )
     .await?;
 
@@ -80,7 +81,6 @@ pub async fn ep_attribute_action(
         attr_type: to_attribute(attr)?,
         namespace,
         request: request.clone(),
-        user,
     };
 
     web::block(move || request_data.run_action()).await??;
@@ -114,7 +114,7 @@ impl RequestData {
         let new_attr = attribute::NewAttribute {
             name: self.request.name,
             type_: self.attr_type,
-            user_id: self.user.user.id,
+            user_id: self.namespace.user_id,
             namespace_id: self.namespace.id,
         };
         new_attr.create(&self.db)?;
@@ -143,7 +143,7 @@ impl RequestData {
             &self.db,
             &self.request.name,
             self.attr_type,
-            self.user.user.id,
+            self.namespace.user_id,
             self.namespace.id,
         )
         .map_err(|i| diesel_option(i, self.attr_type))
@@ -1,4 +1,4 @@
-use crate::{models::user::User, DbPool};
+use crate::{config::Config, models::user::User, DbPool};
 use crate::{
     models::{login_session, namespace::Namespace},
     response_code::RestError,
@@ -24,6 +24,18 @@ pub struct Authenticateduser {
     pub token: String,
 }
 
+impl Authenticateduser {
+    /// Act on behalf of 'user' without a session. Used for
+    /// anonymous uploads into the drop links of a user
+    pub fn without_session(user: User) -> Self {
+        Authenticateduser {
+            default_ns: None,
+            user,
+            token: String::new(),
+        }
+    }
+}
+
 impl FromRequest for Authenticateduser {
     type Error = actix_web::Error;
     type Future = Ready<Result<Self, Error>>;
@@ -38,9 +50,11 @@ impl FromRequest for Authenticateduser {
                     .and_then(|i| i.get().ok())
                     .ok_or(RestError::Internal)?;
 
+                let config = req.app_data::<Data<Config>>().ok_or(RestError::Internal)?;
+
                 // Find session by token
-                let user =
-                    login_session::find_session(&db, &token)?.ok_or(RestError::Unauthorized)?;
+                let user = login_session::find_session(&db, config, &token)?
+                    .ok_or(RestError::Unauthorized)?;
 
                 // Disable disabled user // **pun not intended!!!
                 if user.disabled {
@@ -68,6 +82,35 @@ impl FromRequest for Authenticateduser {
     }
 }
 
+/// An authenticated user with the admin role. Requests
+/// of other users are rejected with 'Forbidden'
+#[derive(Clone)]
+pub struct AdminUser(pub Authenticateduser);
+
+impl FromRequest for AdminUser {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let res = match Authenticateduser::from_request(req, payload).into_inner() {
+            Ok(user) if user.user.is_admin() => Ok(AdminUser(user)),
+            Ok(_) => Err(RestError::Forbidden.into()),
+            Err(err) => Err(err),
+        };
+
+        futures::future::ready(res)
+    }
+}
+
+/// Drop the cached default namespace of a user. Has
+/// to be called whenever the namespace gets modified
+pub fn clear_namespace_cache(user_id: i32) {
+    if let Ok(mut ns_cache) = NS_CACHE.lock() {
+        ns_cache.remove(&user_id);
+    }
+}
+
 /// Get the bearer token from request headers
 pub fn get_bearer_token(req: &HttpRequest) -> Option<String> {
     req.headers().get("Authorization").and_then(|i| {
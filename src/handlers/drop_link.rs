@@ -0,0 +1,102 @@
+use super::{
+    authentication::Authenticateduser,
+    requests::{CreateDropLinkRequest, RevokeDropLinkRequest},
+    response::{DropLinkItemResponse, DropLinkListResponse},
+    utils::retrieve_namespace_by_name,
+};
+use crate::{
+    config::Config,
+    models::{
+        drop_link::{DropLink, NewDropLink},
+        namespace_member::MemberRole,
+        share_link,
+        user::User,
+    },
+    response_code::{RestError, Success, SUCCESS},
+    utils::random_string,
+    DbPool,
+};
+
+use actix_web::web::{self, Json};
+use chrono::{Duration, Utc};
+
+/// Endpoint for listing the drop links of a user
+pub async fn ep_list_drop_links(
+    pool: web::Data<DbPool>,
+    user: Authenticateduser,
+) -> Result<Json<DropLinkListResponse>, RestError> {
+    let db = pool.get()?;
+
+    let links = web::block(move || DropLink::list(&db, user.user.id))
+        .await??
+        .into_iter()
+        .map(DropLinkItemResponse::from)
+        .collect();
+
+    Ok(Json(DropLinkListResponse { links }))
+}
+
+/// Endpoint for creating a link anonymous
+/// visitors can upload files through
+pub async fn ep_create_drop_link(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    request: Json<CreateDropLinkRequest>,
+    user: Authenticateduser,
+) -> Result<Json<DropLinkItemResponse>, RestError> {
+    let request = request.into_inner();
+    if request.expiry.map(|i| i <= 0).unwrap_or(false) {
+        return Err(RestError::BadRequest);
+    }
+
+    let db = pool.get()?;
+    let hashing = config.password_hashing.clone();
+
+    let link = web::block(move || -> Result<_, RestError> {
+        let namespace =
+            retrieve_namespace_by_name(&db, &request.namespace, &user, MemberRole::Uploader)?;
+
+        let password = request.password.as_deref().unwrap_or_default();
+        let password = share_link::hash_link_password(&hashing, password)?;
+
+        let link = NewDropLink {
+            user_id: user.user.id,
+            namespace_id: namespace.id,
+            slug: request
+                .slug
+                .filter(|i| !i.is_empty())
+                .unwrap_or_else(|| random_string(25)),
+            label: request.label.filter(|i| !i.is_empty()),
+            tags: request.tags,
+            expires_at: request.expiry.map(|i| Utc::now() + Duration::seconds(i)),
+            password,
+            max_file_size: request.max_file_size,
+            max_files: request.max_files,
+        }
+        .create(&db)?;
+
+        let owner = User::find_by_id(&db, namespace.user_id)?;
+        Ok((link, namespace, owner.username))
+    })
+    .await??;
+
+    Ok(Json(link.into()))
+}
+
+/// Endpoint for revoking a drop link. Files
+/// uploaded through it are kept
+pub async fn ep_revoke_drop_link(
+    pool: web::Data<DbPool>,
+    request: Json<RevokeDropLinkRequest>,
+    user: Authenticateduser,
+) -> Result<Json<Success>, RestError> {
+    let db = pool.get()?;
+
+    web::block(move || -> Result<(), RestError> {
+        let link = DropLink::find_by_id(&db, request.id, user.user.id)?;
+        Ok(link.revoke(&db)?)
+    })
+    .await??;
+
+    Ok(SUCCESS)
+}
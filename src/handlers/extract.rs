@@ -0,0 +1,412 @@
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use super::{
+    authentication::Authenticateduser,
+    chunked::ChunkedReadFile,
+    range,
+    requests::{
+        file::{ArchiveEntryRequest, ImportEntryRequest},
+        upload_request::UploadRequest,
+    },
+    response::UploadResponse,
+    upload_file::{replaced_file, save_stream, select_file, store_upload, SizeLimit},
+};
+use crate::{
+    config::Config,
+    models::{
+        file::{ArchiveEntry, File},
+        namespace::Namespace,
+        namespace_member::MemberRole,
+        quota::{self, Allowance},
+        traffic::TrafficKind,
+    },
+    response_code::RestError,
+    storage::{self, ByteStream, LocalCopy, StorageBackend},
+    utils, DbPool,
+};
+
+use actix_web::{
+    dev::SizedStream,
+    http::header,
+    web::{self, Json},
+    HttpResponse,
+};
+use async_std::fs;
+use bytes::Bytes;
+use flate2::{read::GzDecoder, write::DeflateDecoder};
+use futures::{ready, Stream, StreamExt};
+
+/// Endpoint for downloading a single member of an archive file
+pub async fn ep_extract_entry(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    request: Json<ArchiveEntryRequest>,
+    user: Authenticateduser,
+) -> Result<HttpResponse, RestError> {
+    let db = pool.get()?;
+    let file_id = request.file_id;
+    let file = web::block(move || File::find_by_id(&db, file_id, user.user.id, MemberRole::Viewer))
+        .await??;
+
+    let (entry, stream) = open_entry(&file, &config, &**storage, &request.path).await?;
+    let stream = verified(&entry, stream);
+    let stream = range::counted(stream, &file, TrafficKind::Download);
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Filename", entry.file_name()))
+        .insert_header((header::CONTENT_TYPE, content_type(&entry)))
+        .body(SizedStream::new(entry.size, stream)))
+}
+
+/// Endpoint for importing a single member of an archive file as new file
+pub async fn ep_import_entry(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    request: Json<ImportEntryRequest>,
+    user: Authenticateduser,
+) -> Result<Json<UploadResponse>, RestError> {
+    let db = pool.get()?;
+    let file_id = request.file_id;
+    let user_id = user.user.id;
+    let archive =
+        web::block(move || File::find_by_id(&db, file_id, user_id, MemberRole::Viewer)).await??;
+
+    let (entry, stream) = open_entry(&archive, &config, &**storage, &request.path).await?;
+
+    let name = request.name.as_deref().unwrap_or_else(|| entry.file_name());
+    let upload_request = UploadRequest::new(name, &request.namespace);
+    upload_request.validate(&user)?;
+
+    // Pick the new file and check whether the member fits into the quotas
+    let db = pool.get()?;
+    let request_cloned = upload_request.clone();
+    let user_cloned = user.clone();
+    let size = entry.size as i64;
+    let (file, namespace, allowance) = web::block(move || -> Result<_, RestError> {
+        let (file, namespace) = select_file(&request_cloned, &db, user_cloned.clone())?;
+        let allowance = quota::allowance(&db, &namespace)?;
+        allowance.check(replaced_file(&file), size)?;
+        Ok((file, namespace, allowance))
+    })
+    .await??;
+
+    let local_name = file.local_name.clone();
+    let import = Import {
+        pool,
+        config: config.clone(),
+        storage,
+        upload_request,
+        user,
+        namespace,
+        allowance,
+    };
+
+    let result = import.handle(file, entry, stream).await;
+    if result.is_err() {
+        // Delete local file on fail
+        fs::remove_file(storage::staging_path(&config, &local_name))
+            .await
+            .ok();
+    }
+
+    result.map(Json)
+}
+
+struct Import {
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    upload_request: UploadRequest,
+    user: Authenticateduser,
+    namespace: Namespace,
+    allowance: Allowance,
+}
+
+impl Import {
+    async fn handle(
+        self,
+        mut file: File,
+        entry: ArchiveEntry,
+        stream: ByteStream,
+    ) -> Result<UploadResponse, RestError> {
+        // The size in the archive headers isn't trusted
+        let limit = SizeLimit::quota(self.allowance.max_size(replaced_file(&file)));
+        let blake3 = self.upload_request.wants_blake3(&self.config);
+        let (digests, size, mime_type) =
+            save_stream(stream, &self.config, &file.local_name, false, limit, blake3).await?;
+
+        if let Some(crc32) = entry.crc32 {
+            if digests.crc32 != format!("{:08x}", crc32) {
+                return Err(RestError::DigestMismatch);
+            }
+        }
+
+        file.source_url = None;
+        file.set_digests(digests);
+        file.file_size = size;
+        file.file_type = mime_type;
+
+        store_upload(
+            &self.pool,
+            &self.config,
+            &**self.storage,
+            self.upload_request,
+            file,
+            self.user,
+            self.namespace,
+        )
+        .await
+    }
+}
+
+/// Find the member 'path' of an archive file and open a stream of its
+/// content. The archive has to be available locally to be searched
+pub async fn open_entry(
+    file: &File,
+    config: &Config,
+    storage: &dyn StorageBackend,
+    path: &str,
+) -> Result<(ArchiveEntry, ByteStream), RestError> {
+    let key = file.data_key(config)?;
+    let local_copy = storage::local_copy(storage, config, &file.local_name, key.as_ref()).await?;
+
+    let file = file.clone();
+    let path = path.to_string();
+    let member_path = storage::staging_path(config, &utils::random_string(30));
+    let (entry, local_copy) = web::block(move || -> Result<_, RestError> {
+        let mut entry = file.find_archive_entry(local_copy.path(), &path)?;
+        if !entry.gzipped {
+            return Ok((entry, local_copy));
+        }
+
+        // Members of compressed tar archives can't be read in place
+        let member = LocalCopy::temporary(member_path);
+        unpack_member(local_copy.path(), &entry, member.path())?;
+        entry.offset = 0;
+        entry.gzipped = false;
+        Ok((entry, member))
+    })
+    .await??;
+
+    let reader = std::fs::File::open(local_copy.path())?;
+    let stream = ChunkedReadFile::new(entry.length, entry.offset, reader)
+        // A temporary copy has to be kept until the member was read
+        .map(move |chunk| {
+            let _ = &local_copy;
+            chunk
+        })
+        .boxed_local();
+
+    let stream = if entry.deflated {
+        Inflate::new(stream).boxed_local()
+    } else {
+        stream
+    };
+
+    Ok((entry, stream))
+}
+
+/// Copy the member 'entry' of the gzipped archive 'archive' to 'output'
+fn unpack_member(archive: &Path, entry: &ArchiveEntry, output: &Path) -> io::Result<()> {
+    let mut decoder = GzDecoder::new(std::fs::File::open(archive)?);
+    io::copy(&mut (&mut decoder).take(entry.offset), &mut io::sink())?;
+
+    let mut output = std::fs::File::create(output)?;
+    let copied = io::copy(&mut decoder.take(entry.length), &mut output)?;
+    if copied != entry.length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(())
+}
+
+/// Check the content of an archive member against the size and crc32 of
+/// its headers while it gets streamed. The headers can't be trusted, so
+/// the stream fails if the content doesn't match them
+pub fn verified(entry: &ArchiveEntry, stream: ByteStream) -> ByteStream {
+    Verify {
+        inner: stream,
+        hasher: Some(crc32fast::Hasher::new()),
+        size: 0,
+        expected_size: entry.size,
+        expected_crc32: entry.crc32,
+    }
+    .boxed_local()
+}
+
+struct Verify {
+    inner: ByteStream,
+    hasher: Option<crc32fast::Hasher>,
+    size: u64,
+    expected_size: u64,
+    expected_crc32: Option<u32>,
+}
+
+impl Stream for Verify {
+    type Item = Result<Bytes, actix_web::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        let hasher = match this.hasher {
+            Some(ref mut hasher) => hasher,
+            None => return Poll::Ready(None),
+        };
+
+        match ready!(this.inner.poll_next_unpin(cx)) {
+            Some(Ok(chunk)) => {
+                this.size += chunk.len() as u64;
+                if this.size > this.expected_size {
+                    this.hasher = None;
+                    return Poll::Ready(Some(Err(RestError::DigestMismatch.into())));
+                }
+
+                hasher.update(&chunk);
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Some(Err(err)) => Poll::Ready(Some(Err(err))),
+            None => {
+                let crc32 = this.hasher.take().map(|i| i.finalize());
+                let crc_matches = this.expected_crc32.is_none() || this.expected_crc32 == crc32;
+
+                if this.size != this.expected_size || !crc_matches {
+                    return Poll::Ready(Some(Err(RestError::DigestMismatch.into())));
+                }
+
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+/// Get the content type of an archive member by its extension
+pub fn content_type(entry: &ArchiveEntry) -> String {
+    match entry.file_name().rsplit_once('.') {
+        Some((_, extension)) => actix_files::file_extension_to_mime(extension).to_string(),
+        None => "application/octet-stream".to_string(),
+    }
+}
+
+/// Decompresses a stream of deflate compressed data
+struct Inflate {
+    inner: ByteStream,
+    decoder: Option<DeflateDecoder<Vec<u8>>>,
+}
+
+impl Inflate {
+    fn new(inner: ByteStream) -> Self {
+        Inflate {
+            inner,
+            decoder: Some(DeflateDecoder::new(vec![])),
+        }
+    }
+}
+
+impl Stream for Inflate {
+    type Item = Result<Bytes, actix_web::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            let decoder = match this.decoder {
+                Some(ref mut decoder) => decoder,
+                None => return Poll::Ready(None),
+            };
+
+            match ready!(this.inner.poll_next_unpin(cx)) {
+                Some(Ok(chunk)) => {
+                    decoder.write_all(&chunk)?;
+
+                    // Not every chunk produces output
+                    let data = std::mem::take(decoder.get_mut());
+                    if !data.is_empty() {
+                        return Poll::Ready(Some(Ok(Bytes::from(data))));
+                    }
+                }
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {
+                    let data = this.decoder.take().map(|i| i.finish()).transpose()?;
+                    return Poll::Ready(data.filter(|i| !i.is_empty()).map(|i| Ok(i.into())));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::DeflateEncoder, Compression};
+    use futures::stream;
+
+    #[actix_rt::test]
+    async fn test_inflate() {
+        let content = "hello world ".repeat(1000);
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        // Feed the compressed data in small chunks
+        let chunks: Vec<Result<Bytes, actix_web::Error>> = compressed
+            .chunks(7)
+            .map(|i| Ok(Bytes::copy_from_slice(i)))
+            .collect();
+
+        let mut inflate = Inflate::new(stream::iter(chunks).boxed_local());
+        let mut output = vec![];
+        while let Some(chunk) = inflate.next().await {
+            output.extend_from_slice(&chunk.unwrap());
+        }
+
+        assert_eq!(output, content.as_bytes());
+    }
+
+    async fn read_verified(content: &'static [u8], entry: &ArchiveEntry) -> Result<Vec<u8>, ()> {
+        let chunks: Vec<Result<Bytes, actix_web::Error>> = content
+            .chunks(3)
+            .map(|i| Ok(Bytes::from_static(i)))
+            .collect();
+
+        let mut stream = verified(entry, stream::iter(chunks).boxed_local());
+        let mut output = vec![];
+        while let Some(chunk) = stream.next().await {
+            output.extend_from_slice(&chunk.map_err(|_| ())?);
+        }
+
+        Ok(output)
+    }
+
+    #[actix_rt::test]
+    async fn test_verified() {
+        let mut entry = ArchiveEntry {
+            name: "a.txt".to_string(),
+            offset: 0,
+            length: 11,
+            size: 11,
+            deflated: false,
+            gzipped: false,
+            crc32: Some(0x0d4a1185),
+        };
+
+        assert_eq!(
+            read_verified(b"hello world", &entry).await,
+            Ok(b"hello world".to_vec())
+        );
+        assert!(read_verified(b"hello worle", &entry).await.is_err());
+        assert!(read_verified(b"hello", &entry).await.is_err());
+        assert!(read_verified(b"hello world!", &entry).await.is_err());
+
+        // Tar members only have a size
+        entry.crc32 = None;
+        assert!(read_verified(b"hello worle", &entry).await.is_ok());
+        assert!(read_verified(b"hello world!", &entry).await.is_err());
+    }
+}
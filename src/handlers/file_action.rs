@@ -1,6 +1,6 @@
 use super::{
     authentication::Authenticateduser,
-    chunked::ChunkedReadFile,
+    range::RangedFile,
     requests::file::{FileRequest, FileUpdateItem},
     response::{BulkPublishResponse, IDsResponse, UploadResponse},
 };
@@ -13,19 +13,22 @@ use crate::{
         },
         file::File,
         namespace::Namespace,
+        namespace_member::MemberRole,
+        share_link::{self, LinkLimits, ShareLink},
+        traffic::TrafficKind,
     },
     response_code::{Origin, RestError},
+    storage::StorageBackend,
     DbConnection, DbPool,
 };
 
-use actix_web::web::HttpResponse;
 use actix_web::web::{self, Json};
-use async_std::path::Path;
+use actix_web::{web::HttpResponse, HttpRequest};
+use std::sync::Arc;
 
 /// Endpoint for running a file action
 pub async fn ep_file_action(
     pool: web::Data<DbPool>,
-    config: web::Data<Config>,
     action: web::Path<String>,
     request: Json<FileRequest>,
     user: Authenticateduser,
@@ -46,7 +49,10 @@ pub async fn ep_file_action(
     let pool_clone = pool.clone();
     let request_clone = request.clone();
     let user_clone = user.clone();
-    let files = web::block(move || find_files(&pool_clone, &request_clone, &user_clone)).await??;
+    let files = web::block(move || {
+        find_files(&pool_clone, &request_clone, &user_clone, MemberRole::Editor)
+    })
+    .await??;
 
     if files.is_empty() {
         return Err(RestError::DNotFound(Origin::Files));
@@ -56,7 +62,9 @@ pub async fn ep_file_action(
         return Err(RestError::MultipleFilesMatch);
     }
 
-    let fids_changed = run_action(&action, files, pool.get()?, &config, &request, &user).await?;
+    let db = pool.get()?;
+    let fids_changed =
+        web::block(move || run_action(&action, files, db, &request, &user)).await??;
 
     Ok(Json(IDsResponse { ids: fids_changed }))
 }
@@ -64,6 +72,7 @@ pub async fn ep_file_action(
 /// Endpoint for publishing files
 pub async fn ep_publish_file(
     pool: web::Data<DbPool>,
+    config: web::Data<Config>,
     request: Json<FileRequest>,
     user: Authenticateduser,
 ) -> Result<Json<BulkPublishResponse>, RestError> {
@@ -73,7 +82,10 @@ pub async fn ep_publish_file(
     let pool_clone = pool.clone();
     let request_clone = request.clone();
     let user_clone = user.clone();
-    let files = web::block(move || find_files(&pool_clone, &request_clone, &user_clone)).await??;
+    let files = web::block(move || {
+        find_files(&pool_clone, &request_clone, &user_clone, MemberRole::Editor)
+    })
+    .await??;
 
     if files.is_empty() {
         return Err(RestError::DNotFound(Origin::Files));
@@ -83,10 +95,19 @@ pub async fn ep_publish_file(
         return Err(RestError::MultipleFilesMatch);
     }
 
+    let limits = LinkLimits::new(request.public_expiry, request.public_max_downloads)?;
+    let password = request.public_password.clone().unwrap_or_default();
+    let hashing = config.password_hashing.clone();
+    let password =
+        web::block(move || share_link::hash_link_password(&hashing, &password)).await??;
+
     let files = publish_files(
         &pool.get()?,
         files,
-        request.public_name.as_ref().cloned().unwrap_or_default(),
+        request.public_name.as_deref().unwrap_or_default(),
+        request.public_label.clone(),
+        limits,
+        password,
     )?;
 
     Ok(Json(BulkPublishResponse { files }))
@@ -96,8 +117,10 @@ pub async fn ep_publish_file(
 pub async fn ep_file_download(
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
     request: Json<FileRequest>,
     user: Authenticateduser,
+    http_request: HttpRequest,
 ) -> Result<HttpResponse, RestError> {
     validate_action_request(&request)?;
 
@@ -109,7 +132,10 @@ pub async fn ep_file_download(
     let pool_clone = pool.clone();
     let request_clone = request.clone();
     let user_clone = user.clone();
-    let files = web::block(move || find_files(&pool_clone, &request_clone, &user_clone)).await??;
+    let files = web::block(move || {
+        find_files(&pool_clone, &request_clone, &user_clone, MemberRole::Viewer)
+    })
+    .await??;
 
     if files.is_empty() {
         return Err(RestError::DNotFound(Origin::Files));
@@ -122,13 +148,9 @@ pub async fn ep_file_download(
     // We only have this one file
     let file = &files[0];
 
-    // Open local file
-    let f = std::fs::File::open(Path::new(&config.server.file_output_path).join(&file.local_name))?;
-    let reader = ChunkedReadFile::new(f.metadata()?.len(), 0, f);
-
     // Build response
     let mut response = HttpResponse::Ok();
-    let mut response = response
+    response
         .insert_header(("X-Filename", file.name.as_str()))
         .insert_header(("Checksum", file.checksum.as_str()))
         .insert_header(("X-FileType", file.file_type.as_str()))
@@ -136,34 +158,45 @@ pub async fn ep_file_download(
         .insert_header(("ContentLength", file.file_size));
 
     if file.encryption > 0 {
-        response = response.insert_header(("X-Encryption", file.encryption));
+        response.insert_header(("X-Encryption", file.encryption));
     }
 
-    Ok(response.streaming(reader))
+    if let Some(ref sha256) = file.sha256 {
+        response.insert_header(("X-Sha256", sha256.as_str()));
+    }
+
+    if let Some(ref blake3) = file.blake3 {
+        response.insert_header(("X-Blake3", blake3.as_str()));
+    }
+
+    RangedFile::new(file, Arc::clone(&storage), file.data_key(&config)?)
+        .count_traffic(TrafficKind::Download)
+        .into_response(&http_request, response)
+        .await
 }
 
 /// Run the actual file action
-async fn run_action(
+fn run_action(
     action: &str,
     files: Vec<File>,
     db: DbConnection,
-    config: &Config,
     request: &FileRequest,
     user: &Authenticateduser,
 ) -> Result<Vec<i32>, RestError> {
     Ok(match action {
         "update" => update_files(db, files, request.updates.clone().unwrap(), user)?,
-        "delete" => delete_files(db, config.to_owned(), files).await?,
+        "delete" => trash_files(db, files)?,
         _ => unreachable!(),
     })
 }
 
-/// Get the files to update based on
-/// the request that was made
+/// Get the files to update based on the request that was made.
+/// The user needs at least the 'required' role in their namespace
 fn find_files(
     pool: &web::Data<DbPool>,
     request: &FileRequest,
     user: &Authenticateduser,
+    required: MemberRole,
 ) -> Result<Vec<File>, RestError> {
     // Whether to search for a single certain file or by name
 
@@ -175,16 +208,22 @@ fn find_files(
             &pool.get()?,
             request.file_id,
             user.user.id,
+            required,
         )?]
     } else {
         // FileName provided, find all matching files
 
         // Pick ns
-        let ns = super::utils::retrieve_namespace(&pool.get()?, &Some(&request.attributes), &user)?;
+        let ns = super::utils::retrieve_namespace(
+            &pool.get()?,
+            &Some(&request.attributes),
+            &user,
+            required,
+        )?;
 
         // Build search file
         let search_file = File {
-            user_id: user.user.id,
+            user_id: ns.user_id,
             id: request.file_id,
             namespace_id: ns.id,
             name: request.name.clone().unwrap_or_default(),
@@ -195,45 +234,46 @@ fn find_files(
     })
 }
 
-/// Delete multiple files
-async fn delete_files(
-    db: DbConnection,
-    config: Config,
-    files: Vec<File>,
-) -> Result<Vec<i32>, RestError> {
-    web::block(move || {
-        for file in files.iter() {
-            file.delete(&db, &config)?;
-        }
+/// Move multiple files into the trash
+fn trash_files(db: DbConnection, files: Vec<File>) -> Result<Vec<i32>, RestError> {
+    for file in files.iter() {
+        file.trash(&db)?;
+    }
 
-        Ok(files.iter().map(|i| i.id).collect())
-    })
-    .await?
+    Ok(files.iter().map(|i| i.id).collect())
+}
+
+/// Delete objects which are not referenced anymore. Errors
+/// are only logged since the files are gone already
+pub async fn delete_objects(storage: &dyn StorageBackend, names: &[String]) {
+    for name in names {
+        if let Err(err) = storage.delete(name).await {
+            warn!("Couldn't delete object {}: {:?}", name, err);
+        }
+    }
 }
 
-/// Publish multiple files
+/// Publish multiple files. Each file gets shared using a new link
 fn publish_files(
     db: &DbConnection,
     files: Vec<File>,
-    public_name: String,
+    public_name: &str,
+    label: Option<String>,
+    limits: LinkLimits,
+    password: Option<String>,
 ) -> Result<Vec<UploadResponse>, RestError> {
     let mut publishes: Vec<UploadResponse> = Vec::new();
 
-    if files.len() == 1 && files[0].is_public {
-        return Err(RestError::AlreadyPublic);
-    }
-
     if files.len() > 1 && !public_name.is_empty() {
         return Err(RestError::AlreadyPublic);
     }
 
-    for mut file in files.into_iter() {
-        if file.is_public {
-            continue;
-        }
+    for file in files.into_iter() {
+        let link = file.publish(&db, public_name, label.clone(), limits, password.clone())?;
 
-        file.publish(&db, &public_name)?;
-        publishes.push(file.into());
+        let mut response: UploadResponse = file.into();
+        response.set_link(&link);
+        publishes.push(response);
     }
 
     Ok(publishes)
@@ -269,8 +309,15 @@ fn update_file(
 
     // Update namespace
     if let Some(ref new_ns) = update.new_namespace {
-        let ns = Namespace::find_by_name(db, &new_ns, user.user.id)?
+        let ns = Namespace::find_by_path(db, &new_ns, user.user.id)?
             .ok_or(RestError::DNotFound(Origin::Namespace))?;
+        ns.authorize(db, user.user.id, MemberRole::Editor, Origin::Namespace)?;
+
+        // Files can't be moved into namespaces of other owners
+        if ns.user_id != file.user_id {
+            return Err(RestError::IllegalOperation);
+        }
+
         if ns.id != file.namespace_id {
             // Update ns
             file.namespace_id = ns.id;
@@ -289,7 +336,7 @@ fn update_file(
     // Publish / Unpublish file
     if let Some(ref public) = update.is_public {
         if !public.is_empty() {
-            if file.public_filename.is_none() {
+            if ShareLink::list(db, file.id)?.is_empty() {
                 // File needs to be shared first
                 return Err(RestError::NotPublic);
             }
@@ -305,14 +352,14 @@ fn update_file(
 
     let add_tags = if let Some(ref add_tags) = update.add_tags {
         did_update = true;
-        NewAttribute::find_and_create(&db, &add_tags, Tag, user.user.id, file.namespace_id)?
+        NewAttribute::find_and_create(&db, &add_tags, Tag, file.user_id, file.namespace_id)?
     } else {
         vec![]
     };
 
     let add_groups = if let Some(ref add_groups) = update.add_groups {
         did_update = true;
-        NewAttribute::find_and_create(&db, &add_groups, Group, user.user.id, file.namespace_id)?
+        NewAttribute::find_and_create(&db, &add_groups, Group, file.user_id, file.namespace_id)?
     } else {
         vec![]
     };
@@ -320,7 +367,7 @@ fn update_file(
 
     let remove_tags = if let Some(ref remove_tags) = update.remove_tags {
         did_update = true;
-        NewAttribute::find_multi_by_name(&db, &remove_tags, Tag, user.user.id, file.namespace_id)?
+        NewAttribute::find_multi_by_name(&db, &remove_tags, Tag, file.user_id, file.namespace_id)?
     } else {
         vec![]
     };
@@ -331,7 +378,7 @@ fn update_file(
             &db,
             &remove_groups,
             Group,
-            user.user.id,
+            file.user_id,
             file.namespace_id,
         )?
     } else {
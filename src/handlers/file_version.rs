@@ -0,0 +1,145 @@
+use super::{
+    authentication::Authenticateduser,
+    file_action::delete_objects,
+    range::RangedFile,
+    requests::file::{PruneVersionsRequest, VersionRequest},
+    response::{PruneVersionsResponse, UploadResponse, VersionItemResponse, VersionListResponse},
+};
+use crate::{
+    config::Config,
+    models::{
+        file::File,
+        file_version::{self, FileVersion},
+        namespace_member::MemberRole,
+        traffic::TrafficKind,
+    },
+    response_code::RestError,
+    storage::StorageBackend,
+    DbPool,
+};
+
+use actix_web::{
+    web::{self, Json},
+    HttpRequest, HttpResponse,
+};
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+
+/// Endpoint for listing the versions of a file
+pub async fn ep_list_versions(
+    pool: web::Data<DbPool>,
+    request: Json<VersionRequest>,
+    user: Authenticateduser,
+) -> Result<Json<VersionListResponse>, RestError> {
+    let db = pool.get()?;
+
+    let versions = web::block(move || -> Result<_, RestError> {
+        let file = File::find_by_id(&db, request.file_id, user.user.id, MemberRole::Viewer)?;
+        Ok(FileVersion::list(&db, file.id)?)
+    })
+    .await??
+    .into_iter()
+    .map(|i| VersionItemResponse {
+        version: i.version,
+        size: i.file_size,
+        checksum: i.checksum,
+        sha256: i.sha256,
+        blake3: i.blake3,
+        creation_date: i.created_at,
+    })
+    .collect();
+
+    Ok(Json(VersionListResponse { versions }))
+}
+
+/// Endpoint for downloading a version of a file
+pub async fn ep_download_version(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    request: Json<VersionRequest>,
+    user: Authenticateduser,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, RestError> {
+    let version = request.version.ok_or(RestError::BadRequest)?;
+    let db = pool.get()?;
+
+    let file = web::block(move || -> Result<File, RestError> {
+        let file = File::find_by_id(&db, request.file_id, user.user.id, MemberRole::Viewer)?;
+        Ok(FileVersion::find(&db, file.id, version)?.as_file(&file))
+    })
+    .await??;
+
+    // Build response
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(("X-Filename", file.name.as_str()))
+        .insert_header(("Checksum", file.checksum.as_str()))
+        .insert_header(("X-FileType", file.file_type.as_str()))
+        .insert_header(("X-FileID", file.id))
+        .insert_header(("X-Version", version))
+        .insert_header(("ContentLength", file.file_size));
+
+    if file.encryption > 0 {
+        response.insert_header(("X-Encryption", file.encryption));
+    }
+
+    if let Some(ref sha256) = file.sha256 {
+        response.insert_header(("X-Sha256", sha256.as_str()));
+    }
+
+    if let Some(ref blake3) = file.blake3 {
+        response.insert_header(("X-Blake3", blake3.as_str()));
+    }
+
+    RangedFile::new(&file, Arc::clone(&storage), file.data_key(&config)?)
+        .count_traffic(TrafficKind::Download)
+        .into_response(&http_request, response)
+        .await
+}
+
+/// Endpoint for restoring a version as the current content of a file
+pub async fn ep_restore_version(
+    pool: web::Data<DbPool>,
+    request: Json<VersionRequest>,
+    user: Authenticateduser,
+) -> Result<Json<UploadResponse>, RestError> {
+    let version = request.version.ok_or(RestError::BadRequest)?;
+    let db = pool.get()?;
+
+    let file = web::block(move || -> Result<File, RestError> {
+        let file = File::find_by_id(&db, request.file_id, user.user.id, MemberRole::Editor)?;
+        file_version::restore(&db, &file, version)
+    })
+    .await??;
+
+    Ok(Json(file.into()))
+}
+
+/// Endpoint for deleting old versions of a file
+pub async fn ep_prune_versions(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    request: Json<PruneVersionsRequest>,
+    user: Authenticateduser,
+) -> Result<Json<PruneVersionsResponse>, RestError> {
+    // Pruning requires at least one limit
+    if request.keep.is_none() && request.max_age.is_none() {
+        return Err(RestError::BadRequest);
+    }
+
+    let before = request
+        .max_age
+        .map(|i| Utc::now() - Duration::seconds(i as i64));
+    let db = pool.get()?;
+
+    let (versions, orphaned) = web::block(move || -> Result<_, RestError> {
+        let file = File::find_by_id(&db, request.file_id, user.user.id, MemberRole::Editor)?;
+        file_version::prune(&db, file.id, request.keep, before)
+    })
+    .await??;
+
+    delete_objects(&**storage, &orphaned).await;
+
+    Ok(Json(PruneVersionsResponse { versions }))
+}
@@ -3,12 +3,15 @@ use crate::{
     models::{
         attribute::{Attribute, AttributeType},
         file::File,
+        share_link,
+        user::User,
     },
     response_code::RestError,
     DbPool,
 };
 
 use actix_web::web::{self, Json};
+use itertools::Itertools;
 use response::FileListResponse;
 
 /// Endpoint for registering new users
@@ -17,12 +20,37 @@ pub async fn ep_list_files(
     request: Json<FileList>,
     user: Authenticateduser,
 ) -> Result<Json<FileListResponse>, RestError> {
-    let files = File::search(&pool.get()?, &request, user.user)?
+    let db = pool.get()?;
+    let uid = user.user.id;
+    let found = File::search(&db, &request, user.user)?;
+
+    // Public files are listed with their oldest link
+    let fids: Vec<i32> = found.iter().map(|(file, _, _)| file.id).collect();
+    let mut slugs = share_link::first_slugs(&db, &fids)?;
+
+    // Names of the uploaders and of the owners of shared namespaces
+    let uids: Vec<i32> = found
+        .iter()
+        .flat_map(|(file, namespace, _)| vec![Some(namespace.user_id), file.uploader_id])
+        .flatten()
+        .unique()
+        .collect();
+    let usernames = User::usernames(&db, &uids)?;
+
+    let files = found
         .into_iter()
         // Map fond files to a responable format
         .map(|(file, namespace, attr)| -> response::FileItemResponse {
+            let uploader = file.uploader_id.and_then(|i| usernames.get(&i).cloned());
+            let owner = usernames
+                .get(&namespace.user_id)
+                .cloned()
+                .unwrap_or_default();
+
             let mut res: response::FileItemResponse = file.into();
-            res.attributes.namespace = namespace.name;
+            res.attributes.namespace = namespace.path_for(uid, &owner);
+            res.public_name = slugs.remove(&res.id).unwrap_or_default();
+            res.uploader = uploader;
 
             let (tags, groups): (Vec<Attribute>, Vec<Attribute>) = attr
                 .into_iter()
@@ -1,14 +1,25 @@
 #![allow(dead_code)]
+pub mod admin;
+pub mod archive;
 pub mod attributes;
 pub mod authentication;
 pub mod chunked;
+pub mod drop_link;
+pub mod extract;
 pub mod file_action;
+pub mod file_version;
 pub mod list_file;
 pub mod namespace;
 pub mod ping;
+pub mod range;
 pub mod requests;
 mod response;
+pub mod share_link;
+pub mod trash;
+pub mod tus;
 pub mod upload_file;
+pub mod upload_session;
+pub mod url_upload;
 pub mod user;
 mod utils;
 pub mod web;
@@ -1,7 +1,16 @@
-use super::{authentication::Authenticateduser, requests::NamespaceRequest, response::VecResponse};
+use super::{
+    authentication::{clear_namespace_cache, Authenticateduser},
+    requests::{NamespaceMemberRequest, NamespaceQuotaRequest, NamespaceRequest},
+    response::{NamespaceMemberListResponse, VecResponse},
+    utils::retrieve_namespace_by_name,
+};
 use crate::{
-    config::Config,
-    models::namespace::{self, Namespace},
+    models::{
+        namespace::{self, Namespace},
+        namespace_member::{MemberRole, NamespaceMember, NewNamespaceMember},
+        quota::Quota,
+        user::User,
+    },
     response_code::{Origin, RestError, Success, SUCCESS},
     DbPool,
 };
@@ -33,17 +42,29 @@ pub async fn ep_create_namespace(
     Ok(SUCCESS)
 }
 
-/// Endpoint for listing available namespaces for a user
+/// Endpoint for listing available namespaces for a user. Namespaces
+/// shared by other users are listed as 'owner/name'
 pub async fn ep_list_namespace(
     pool: web::Data<DbPool>,
     user: Authenticateduser,
 ) -> Result<Json<VecResponse<String>>, RestError> {
     let db = pool.get()?;
 
-    let ns_names = web::block(move || Namespace::list(&db, &user.user))
-        .await??
+    let uid = user.user.id;
+    let (own, shared) = web::block(move || -> Result<_, RestError> {
+        let own = Namespace::list(&db, &user.user)?;
+        Ok((own, Namespace::list_shared(&db, uid)?))
+    })
+    .await??;
+
+    let ns_names = own
         .into_iter()
         .map(|i| i.name)
+        .chain(
+            shared
+                .into_iter()
+                .map(|(ns, owner)| ns.path_for(uid, &owner)),
+        )
         .collect::<Vec<String>>();
 
     Ok(Json(VecResponse { slice: ns_names }))
@@ -52,7 +73,6 @@ pub async fn ep_list_namespace(
 /// Endpoint for deleting a namespace
 pub async fn ep_delete_namespace(
     pool: web::Data<DbPool>,
-    config: web::Data<Config>,
     user: Authenticateduser,
     req: web::Json<NamespaceRequest>,
 ) -> Result<Json<Success>, RestError> {
@@ -64,10 +84,8 @@ pub async fn ep_delete_namespace(
     }
 
     web::block(move || -> Result<(), RestError> {
-        let ns = Namespace::find_by_name(&db, &req.name, user.user.id)?
-            .ok_or(RestError::DNotFound(Origin::Namespace))?;
-
-        ns.delete(&db, &config)
+        let ns = retrieve_namespace_by_name(&db, &req.name, &user, MemberRole::Owner)?;
+        ns.delete(&db)
     })
     .await??;
 
@@ -97,8 +115,10 @@ pub async fn ep_rename_namespace(
     let db = pool.get()?;
 
     web::block(move || -> Result<(), RestError> {
-        let ns = Namespace::find_by_name(&db, &req.name, user.user.id)?
-            .ok_or(RestError::DNotFound(Origin::Namespace))?;
+        let ns = retrieve_namespace_by_name(&db, &req.name, &user, MemberRole::Owner)?;
+        if ns.is_default() {
+            return Err(RestError::IllegalOperation);
+        }
 
         ns.rename(&db, new_name.as_ref())
     })
@@ -106,3 +126,127 @@ pub async fn ep_rename_namespace(
 
     Ok(SUCCESS)
 }
+
+/// Endpoint for setting the quota of a namespace. It
+/// applies in addition to the quota of the user
+pub async fn ep_namespace_quota(
+    pool: web::Data<DbPool>,
+    user: Authenticateduser,
+    req: web::Json<NamespaceQuotaRequest>,
+) -> Result<Json<Success>, RestError> {
+    if req.bytes.map(|i| i < 0).unwrap_or(false) || req.files.map(|i| i < 0).unwrap_or(false) {
+        return Err(RestError::BadRequest);
+    }
+
+    let db = pool.get()?;
+
+    web::block(move || -> Result<(), RestError> {
+        let ns = retrieve_namespace_by_name(&db, &req.name, &user, MemberRole::Owner)?;
+
+        ns.set_quota(
+            &db,
+            Quota {
+                bytes: req.bytes,
+                files: req.files,
+            },
+        )?;
+
+        // The default namespace is cached including its quota
+        clear_namespace_cache(ns.user_id);
+        Ok(())
+    })
+    .await??;
+
+    Ok(SUCCESS)
+}
+
+/// Endpoint for listing the members of a namespace
+pub async fn ep_list_members(
+    pool: web::Data<DbPool>,
+    user: Authenticateduser,
+    req: web::Json<NamespaceRequest>,
+) -> Result<Json<NamespaceMemberListResponse>, RestError> {
+    let db = pool.get()?;
+
+    let (owner, members) = web::block(move || -> Result<_, RestError> {
+        let ns = retrieve_namespace_by_name(&db, &req.name, &user, MemberRole::Viewer)?;
+        let owner = User::find_by_id(&db, ns.user_id)?;
+        Ok((owner.username, NamespaceMember::list(&db, ns.id)?))
+    })
+    .await??;
+
+    Ok(Json(NamespaceMemberListResponse {
+        owner,
+        members: members.into_iter().map(|i| i.into()).collect(),
+    }))
+}
+
+/// Endpoint for sharing a namespace with another
+/// user or changing the role of a member
+pub async fn ep_add_member(
+    pool: web::Data<DbPool>,
+    user: Authenticateduser,
+    req: web::Json<NamespaceMemberRequest>,
+) -> Result<Json<Success>, RestError> {
+    let role = req.role.ok_or(RestError::BadRequest)?;
+
+    // Default namespaces stay private
+    if Namespace::is_default_name(&req.name) {
+        return Err(RestError::IllegalOperation);
+    }
+
+    let db = pool.get()?;
+
+    web::block(move || -> Result<(), RestError> {
+        let ns = retrieve_namespace_by_name(&db, &req.name, &user, MemberRole::Owner)?;
+        if ns.is_default() {
+            return Err(RestError::IllegalOperation);
+        }
+
+        // The owner has all permissions already
+        let member = User::find_by_name(&db, &req.username)?;
+        if member.id == ns.user_id {
+            return Err(RestError::IllegalOperation);
+        }
+
+        NewNamespaceMember {
+            namespace_id: ns.id,
+            user_id: member.id,
+            role,
+        }
+        .save(&db)?;
+
+        Ok(())
+    })
+    .await??;
+
+    Ok(SUCCESS)
+}
+
+/// Endpoint for removing a member from a namespace.
+/// Members can leave namespaces without being an owner
+pub async fn ep_remove_member(
+    pool: web::Data<DbPool>,
+    user: Authenticateduser,
+    req: web::Json<NamespaceMemberRequest>,
+) -> Result<Json<Success>, RestError> {
+    let db = pool.get()?;
+
+    web::block(move || -> Result<(), RestError> {
+        let ns = retrieve_namespace_by_name(&db, &req.name, &user, MemberRole::Viewer)?;
+
+        let member = User::find_by_name(&db, &req.username)?;
+        if member.id != user.user.id {
+            ns.authorize(&db, user.user.id, MemberRole::Owner, Origin::Namespace)?;
+        }
+
+        if !NamespaceMember::remove(&db, ns.id, member.id)? {
+            return Err(RestError::DNotFound(Origin::User));
+        }
+
+        Ok(())
+    })
+    .await??;
+
+    Ok(SUCCESS)
+}
@@ -0,0 +1,492 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use actix_files::HttpRange;
+use actix_web::{
+    dev::{HttpResponseBuilder, SizedStream},
+    http::{
+        header::{
+            self, EntityTag, HttpDate, IfMatch, IfModifiedSince, IfNoneMatch, IfRange,
+            IfUnmodifiedSince,
+        },
+        Method, StatusCode,
+    },
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use bytes::Bytes;
+use futures::{
+    future,
+    stream::{self, Stream, StreamExt, TryStreamExt},
+};
+
+use crate::{
+    models::{
+        file::File,
+        traffic::{self, TrafficKind},
+    },
+    response_code::RestError,
+    storage::{self, encryption::DataKey, ByteStream, StorageBackend},
+    utils,
+};
+
+/// Requests asking for more ranges than this are
+/// answered with the full content instead
+const MAX_RANGES: usize = 32;
+
+/// The parts of a file a request asks for
+enum Selection {
+    PreconditionFailed,
+    NotModified,
+    Unsatisfiable,
+    Full,
+    Ranges(Vec<HttpRange>),
+}
+
+/// A stored file which can be served honouring
+/// Range and conditional request headers
+pub struct RangedFile<'a> {
+    file: &'a File,
+    storage: Arc<dyn StorageBackend>,
+    key: Option<DataKey>,
+    size: u64,
+    traffic: Option<TrafficKind>,
+}
+
+impl<'a> RangedFile<'a> {
+    /// Create a new RangedFile from a file, the storage containing
+    /// its content and the key of the content if it's encrypted
+    pub fn new(file: &'a File, storage: Arc<dyn StorageBackend>, key: Option<DataKey>) -> Self {
+        RangedFile {
+            file,
+            storage,
+            key,
+            size: file.file_size as u64,
+            traffic: None,
+        }
+    }
+
+    /// Account the sent bytes as traffic of the files owner
+    pub fn count_traffic(mut self, kind: TrafficKind) -> Self {
+        self.traffic = Some(kind);
+        self
+    }
+
+    /// Wrap a stream of the content to count the sent bytes
+    fn counted(&self, stream: ByteStream) -> ByteStream {
+        match self.traffic {
+            Some(kind) => counted(stream, self.file, kind),
+            None => stream,
+        }
+    }
+
+    /// The files entity tag, derived from its checksum
+    pub fn etag(&self) -> EntityTag {
+        entity_tag(self.file)
+    }
+
+    /// The files last modification date
+    pub fn last_modified(&self) -> HttpDate {
+        SystemTime::from(self.file.uploaded_at).into()
+    }
+
+    /// Whether the response to the passed request would contain any
+    /// part of the content. Only those count as download
+    pub fn sends_content(&self, request: &HttpRequest) -> Result<bool, RestError> {
+        if request.method() == Method::HEAD {
+            return Ok(false);
+        }
+
+        Ok(matches!(
+            self.select(request)?,
+            Selection::Full | Selection::Ranges(_)
+        ))
+    }
+
+    /// Build the response for the passed request. 'response' can be
+    /// used to pass additional headers which should always be sent
+    pub async fn into_response(
+        self,
+        request: &HttpRequest,
+        mut response: HttpResponseBuilder,
+    ) -> Result<HttpResponse, RestError> {
+        response
+            .insert_header((header::ETAG, self.etag().to_string()))
+            .insert_header((header::LAST_MODIFIED, self.last_modified().to_string()))
+            .insert_header((header::ACCEPT_RANGES, "bytes"));
+
+        match self.select(request)? {
+            Selection::PreconditionFailed => {
+                Ok(response.status(StatusCode::PRECONDITION_FAILED).finish())
+            }
+            Selection::NotModified => Ok(response.status(StatusCode::NOT_MODIFIED).finish()),
+            Selection::Unsatisfiable => Ok(response
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", self.size)))
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .finish()),
+            Selection::Full => self.serve_full(response).await,
+            Selection::Ranges(ranges) if ranges.len() == 1 => {
+                self.serve_single(response, ranges[0]).await
+            }
+            Selection::Ranges(ranges) => Ok(self.serve_multiple(response, ranges)),
+        }
+    }
+
+    /// Evaluate the conditional and Range headers of a request
+    fn select(&self, request: &HttpRequest) -> Result<Selection, RestError> {
+        let etag = self.etag();
+        let last_modified = self.last_modified();
+
+        if precondition_failed(request, &etag, &last_modified) {
+            return Ok(Selection::PreconditionFailed);
+        }
+
+        if not_modified(request, &etag, &last_modified) {
+            return Ok(Selection::NotModified);
+        }
+
+        // Ignore the Range header if it doesn't apply to the current representation
+        let range_header = request
+            .headers()
+            .get(header::RANGE)
+            .filter(|_| if_range_matches(request, &etag, &last_modified));
+
+        let range_header = match range_header {
+            Some(range_header) => range_header.to_str().map_err(|_| RestError::BadRequest)?,
+            None => return Ok(Selection::Full),
+        };
+
+        Ok(match HttpRange::parse(range_header, self.size) {
+            Ok(ranges) if ranges.is_empty() => Selection::Unsatisfiable,
+            Ok(ranges) if ranges.len() > MAX_RANGES => Selection::Full,
+            Ok(ranges) => Selection::Ranges(ranges),
+            Err(_) => Selection::Unsatisfiable,
+        })
+    }
+
+    async fn serve_full(
+        self,
+        mut response: HttpResponseBuilder,
+    ) -> Result<HttpResponse, RestError> {
+        let reader = storage::read(
+            &*self.storage,
+            &self.file.local_name,
+            self.key.as_ref(),
+            0,
+            self.size,
+        )
+        .await?;
+        let reader = self.counted(reader);
+        Ok(response.body(SizedStream::new(self.size, reader)))
+    }
+
+    async fn serve_single(
+        self,
+        mut response: HttpResponseBuilder,
+        range: HttpRange,
+    ) -> Result<HttpResponse, RestError> {
+        let reader = storage::read(
+            &*self.storage,
+            &self.file.local_name,
+            self.key.as_ref(),
+            range.start,
+            range.length,
+        )
+        .await?;
+        let reader = self.counted(reader);
+
+        Ok(response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .insert_header((header::CONTENT_RANGE, content_range(&range, self.size)))
+            .body(SizedStream::new(range.length, reader)))
+    }
+
+    /// Serve multiple ranges as multipart/byteranges body
+    fn serve_multiple(
+        self,
+        mut response: HttpResponseBuilder,
+        ranges: Vec<HttpRange>,
+    ) -> HttpResponse {
+        let boundary = utils::random_string(30);
+        let content_type = if self.file.file_type.is_empty() {
+            "application/octet-stream"
+        } else {
+            self.file.file_type.as_str()
+        };
+
+        let mut length = 0;
+        let mut parts: Vec<ByteStream> = Vec::with_capacity(ranges.len() * 2 + 1);
+
+        for range in ranges.iter() {
+            let part_header = Bytes::from(format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                content_range(range, self.size)
+            ));
+
+            length += part_header.len() as u64 + range.length;
+            parts.push(stream::once(future::ok(part_header)).boxed_local());
+
+            // Each part is read independently once the previous one was sent
+            let backend = self.storage.clone();
+            let local_name = self.file.local_name.clone();
+            let key = self.key.clone();
+            let range = *range;
+            let reader = async move {
+                storage::read(
+                    &*backend,
+                    &local_name,
+                    key.as_ref(),
+                    range.start,
+                    range.length,
+                )
+                .await
+                .map_err(actix_web::Error::from)
+            };
+            parts.push(stream::once(reader).try_flatten().boxed_local());
+        }
+
+        let closing = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+        length += closing.len() as u64;
+        parts.push(stream::once(future::ok(closing)).boxed_local());
+
+        let body = self.counted(stream::iter(parts).flatten().boxed_local());
+
+        response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .insert_header((
+                header::CONTENT_TYPE,
+                format!("multipart/byteranges; boundary={}", boundary),
+            ))
+            .body(SizedStream::new(length, body))
+    }
+}
+
+/// Wrap a stream of content of 'file' to account
+/// the sent bytes as traffic of the files owner
+pub fn counted(stream: ByteStream, file: &File, kind: TrafficKind) -> ByteStream {
+    CountingStream {
+        inner: stream,
+        user_id: file.user_id,
+        namespace_id: file.namespace_id,
+        kind,
+        sent: 0,
+    }
+    .boxed_local()
+}
+
+/// A stream of sent content which records the
+/// amount of sent bytes as traffic once it's dropped
+struct CountingStream {
+    inner: ByteStream,
+    user_id: i32,
+    namespace_id: i32,
+    kind: TrafficKind,
+    sent: u64,
+}
+
+impl Stream for CountingStream {
+    type Item = Result<Bytes, actix_web::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let res = self.inner.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Ok(ref chunk))) = res {
+            self.sent += chunk.len() as u64;
+        }
+        res
+    }
+}
+
+impl Drop for CountingStream {
+    fn drop(&mut self) {
+        traffic::record(self.user_id, self.namespace_id, self.kind, self.sent);
+    }
+}
+
+/// Get the entity tag of a file
+pub fn entity_tag(file: &File) -> EntityTag {
+    EntityTag::strong(format!("{}-{:x}", file.checksum, file.file_size))
+}
+
+/// Format a range as Content-Range value
+fn content_range(range: &HttpRange, size: u64) -> String {
+    format!(
+        "bytes {}-{}/{}",
+        range.start,
+        range.start + range.length - 1,
+        size
+    )
+}
+
+/// Returns true if the If-Match or If-Unmodified-Since
+/// conditions of the request are not fulfilled
+fn precondition_failed(request: &HttpRequest, etag: &EntityTag, modified: &HttpDate) -> bool {
+    match request.get_header::<IfMatch>() {
+        Some(IfMatch::Items(ref items)) => !items.iter().any(|i| i.strong_eq(etag)),
+        Some(IfMatch::Any) => false,
+        None => match request.get_header::<IfUnmodifiedSince>() {
+            Some(IfUnmodifiedSince(ref since)) => unix_secs(modified) > unix_secs(since),
+            None => false,
+        },
+    }
+}
+
+/// Returns true if the client already has the current
+/// representation. If-None-Match takes precedence over
+/// If-Modified-Since
+fn not_modified(request: &HttpRequest, etag: &EntityTag, modified: &HttpDate) -> bool {
+    match request.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Items(ref items)) => items.iter().any(|i| i.weak_eq(etag)),
+        Some(IfNoneMatch::Any) => true,
+        None => match request.get_header::<IfModifiedSince>() {
+            Some(IfModifiedSince(ref since)) => unix_secs(modified) <= unix_secs(since),
+            None => false,
+        },
+    }
+}
+
+/// Returns true if the Range header should be applied
+fn if_range_matches(request: &HttpRequest, etag: &EntityTag, modified: &HttpDate) -> bool {
+    if !request.headers().contains_key(header::IF_RANGE) {
+        return true;
+    }
+
+    match request.get_header::<IfRange>() {
+        Some(IfRange::EntityTag(ref tag)) => tag.strong_eq(etag),
+        Some(IfRange::Date(ref date)) => unix_secs(modified) == unix_secs(date),
+        // Unparsable If-Range header
+        None => false,
+    }
+}
+
+/// Get the seconds since the unix epoch of a HttpDate
+fn unix_secs(date: &HttpDate) -> u64 {
+    SystemTime::from(*date)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|i| i.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use chrono::{TimeZone, Utc};
+
+    fn get_file() -> File {
+        File {
+            checksum: "1c291ca3".to_owned(),
+            file_size: 11,
+            uploaded_at: Utc.timestamp(1_600_000_000, 0),
+            ..File::default()
+        }
+    }
+
+    fn modified(file: &File) -> HttpDate {
+        SystemTime::from(file.uploaded_at).into()
+    }
+
+    #[test]
+    fn test_not_modified_etag() {
+        let file = get_file();
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, entity_tag(&file).to_string()))
+            .to_http_request();
+
+        assert!(not_modified(&req, &entity_tag(&file), &modified(&file)));
+    }
+
+    #[test]
+    fn test_not_modified_etag_precedence() {
+        let file = get_file();
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "\"other\""))
+            .insert_header((header::IF_MODIFIED_SINCE, modified(&file).to_string()))
+            .to_http_request();
+
+        assert!(!not_modified(&req, &entity_tag(&file), &modified(&file)));
+    }
+
+    #[test]
+    fn test_not_modified_since() {
+        let file = get_file();
+        let req = TestRequest::default()
+            .insert_header((header::IF_MODIFIED_SINCE, modified(&file).to_string()))
+            .to_http_request();
+
+        assert!(not_modified(&req, &entity_tag(&file), &modified(&file)));
+    }
+
+    #[test]
+    fn test_if_range_mismatch() {
+        let file = get_file();
+        let req = TestRequest::default()
+            .insert_header((header::IF_RANGE, "\"outdated\""))
+            .to_http_request();
+
+        assert!(!if_range_matches(
+            &req,
+            &entity_tag(&file),
+            &modified(&file)
+        ));
+    }
+
+    #[test]
+    fn test_if_range_date() {
+        let file = get_file();
+        let req = TestRequest::default()
+            .insert_header((header::IF_RANGE, modified(&file).to_string()))
+            .to_http_request();
+
+        assert!(if_range_matches(&req, &entity_tag(&file), &modified(&file)));
+    }
+
+    #[test]
+    fn test_precondition_failed() {
+        let file = get_file();
+        let req = TestRequest::default()
+            .insert_header((header::IF_MATCH, "\"outdated\""))
+            .to_http_request();
+
+        assert!(precondition_failed(
+            &req,
+            &entity_tag(&file),
+            &modified(&file)
+        ));
+    }
+
+    #[test]
+    fn test_sends_content() {
+        let file = get_file();
+        let storage: Arc<dyn StorageBackend> = Arc::new(storage::local::LocalStorage::new("."));
+        let ranged = RangedFile::new(&file, storage, None);
+        let sends_content = |range: Option<&str>| {
+            let mut req = TestRequest::default();
+            if let Some(range) = range {
+                req = req.insert_header((header::RANGE, range));
+            }
+            ranged.sends_content(&req.to_http_request()).unwrap()
+        };
+
+        assert!(sends_content(None));
+        assert!(sends_content(Some("bytes=0-0")));
+        assert!(sends_content(Some("bytes=5-,0-1")));
+        assert!(sends_content(Some("bytes=-20")));
+        assert!(sends_content(Some("bytes=1-")));
+        assert!(!sends_content(Some("bytes=20-")));
+
+        let not_modified = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, entity_tag(&file).to_string()))
+            .to_http_request();
+        assert!(!ranged.sends_content(&not_modified).unwrap());
+
+        let head = TestRequest::default()
+            .method(Method::HEAD)
+            .to_http_request();
+        assert!(!ranged.sends_content(&head).unwrap());
+    }
+}
@@ -0,0 +1,38 @@
+use crate::models::user::Role;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserRequest {
+    pub username: String,
+    #[serde(rename = "pass")]
+    pub password: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserRequest {
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserPasswordRequest {
+    pub username: String,
+    #[serde(rename = "pass")]
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserQuotaRequest {
+    pub username: String,
+    /// Max total size of the files in bytes. Unlimited if not set
+    pub bytes: Option<i64>,
+    /// Max amount of files. Unlimited if not set
+    pub files: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserRoleRequest {
+    pub username: String,
+    pub role: Role,
+}
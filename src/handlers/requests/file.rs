@@ -27,6 +27,16 @@ pub struct FileRequest {
     pub name: Option<String>,
     #[serde(rename = "pubname")]
     pub public_name: Option<String>,
+    #[serde(rename = "publabel")]
+    pub public_label: Option<String>,
+    /// Seconds until the public link expires
+    #[serde(rename = "pubexpiry")]
+    pub public_expiry: Option<i64>,
+    #[serde(rename = "pubmaxdl")]
+    pub public_max_downloads: Option<i32>,
+    /// Password protecting the public link
+    #[serde(rename = "pubpass")]
+    pub public_password: Option<String>,
     pub updates: Option<FileUpdateItem>,
     pub all: bool,
     pub attributes: FileAttributes,
@@ -49,3 +59,118 @@ pub struct FileUpdateItem {
     #[serde(rename = "add_groups")]
     pub add_groups: Option<Vec<String>>,
 }
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ShareLinksRequest {
+    #[serde(rename = "fid")]
+    pub file_id: i32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CreateShareLinkRequest {
+    #[serde(rename = "fid")]
+    pub file_id: i32,
+    /// A random slug is generated if none is given
+    pub slug: Option<String>,
+    pub label: Option<String>,
+    /// Seconds until the link expires
+    pub expiry: Option<i64>,
+    #[serde(rename = "maxdl")]
+    pub max_downloads: Option<i32>,
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RevokeShareLinkRequest {
+    pub id: i32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TrashRequest {
+    #[serde(rename = "fids", default)]
+    pub file_ids: Vec<i32>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct VersionRequest {
+    #[serde(rename = "fid")]
+    pub file_id: i32,
+    pub version: Option<i32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PruneVersionsRequest {
+    #[serde(rename = "fid")]
+    pub file_id: i32,
+    pub keep: Option<usize>,
+    #[serde(rename = "maxage")]
+    pub max_age: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ArchiveRequest {
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    /// Select files by their IDs
+    #[serde(rename = "fids")]
+    pub file_ids: Option<Vec<i32>>,
+    /// Select the results of a search
+    pub search: Option<FileList>,
+    /// Select all files of a namespace
+    #[serde(rename = "ns")]
+    pub namespace: Option<String>,
+    /// Add a manifest containing the tags and groups of the files
+    #[serde(default)]
+    pub manifest: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ArchiveEntryRequest {
+    #[serde(rename = "fid")]
+    pub file_id: i32,
+    /// Path of the member within the archive
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ImportEntryRequest {
+    #[serde(rename = "fid")]
+    pub file_id: i32,
+    pub path: String,
+    /// Namespace to create the new file in
+    #[serde(rename = "ns")]
+    pub namespace: String,
+    /// Name of the new file. The members file name is used if not set
+    pub name: Option<String>,
+}
@@ -1,7 +1,10 @@
+pub mod admin;
 pub mod attribute;
 pub mod file;
 pub mod upload_request;
 
+use crate::models::namespace_member::MemberRole;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -12,6 +15,25 @@ pub struct NamespaceRequest {
     pub new_name: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct NamespaceQuotaRequest {
+    #[serde(rename = "ns")]
+    pub name: String,
+    /// Max total size of the files in bytes. Unlimited if not set
+    pub bytes: Option<i64>,
+    /// Max amount of files. Unlimited if not set
+    pub files: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct NamespaceMemberRequest {
+    #[serde(rename = "ns")]
+    pub name: String,
+    pub username: String,
+    /// The role to grant. Only required for adding members
+    pub role: Option<MemberRole>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CredentialsRequest {
     pub username: String,
@@ -29,7 +51,47 @@ impl CredentialsRequest {
 }
 
 #[derive(Debug, Deserialize)]
+pub struct RevokeSessionRequest {
+    pub id: Option<i32>,
+    #[serde(default)]
+    pub others: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
 pub struct StatsRequest {
     #[serde(rename = "ns")]
     pub namespace: Option<String>,
+    /// Only count traffic since this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only count traffic before this time
+    pub until: Option<DateTime<Utc>>,
+    /// Include the stats of every namespace
+    #[serde(default)]
+    pub breakdown: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateDropLinkRequest {
+    /// Namespace the files get uploaded into
+    #[serde(rename = "ns")]
+    pub namespace: String,
+    /// A random slug is generated if none is given
+    pub slug: Option<String>,
+    pub label: Option<String>,
+    /// Tags added to each uploaded file
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Max size of a single file in bytes
+    #[serde(rename = "maxsize")]
+    pub max_file_size: Option<i64>,
+    #[serde(rename = "maxfiles")]
+    pub max_files: Option<i32>,
+    /// Seconds until the link expires
+    pub expiry: Option<i64>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeDropLinkRequest {
+    pub id: i32,
 }
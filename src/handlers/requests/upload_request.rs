@@ -7,13 +7,18 @@ use futures::future::Ready;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    handlers::authentication::Authenticateduser,
-    models::file::{self, File},
+    config::Config,
+    handlers::{authentication::Authenticateduser, url_upload},
+    models::{
+        digest::Digests,
+        file::{self, File},
+        share_link::LinkLimits,
+    },
     response_code::RestError,
     utils,
 };
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct UploadRequest {
     // Required fields
     #[serde(rename = "type")]
@@ -28,6 +33,14 @@ pub struct UploadRequest {
     pub public: Option<bool>,
     #[serde(rename = "pbname")]
     pub public_name: Option<String>,
+    /// Seconds until the public link expires
+    #[serde(rename = "pbexpiry")]
+    pub public_expiry: Option<i64>,
+    #[serde(rename = "pbmaxdl")]
+    pub public_max_downloads: Option<i32>,
+    /// Password protecting the public link
+    #[serde(rename = "pbpass")]
+    pub public_password: Option<String>,
     #[serde(rename = "e")]
     pub encryption: Option<i8>,
     #[serde(rename = "compr")]
@@ -42,6 +55,9 @@ pub struct UploadRequest {
     pub all: bool,
     #[serde(rename = "attr")]
     pub attributes: Option<FileAttributes>,
+    /// Expected hex encoded digests of the content
+    pub sha256: Option<String>,
+    pub blake3: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -54,6 +70,16 @@ pub struct FileAttributes {
     pub namespace: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadSessionRequest {
+    pub size: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FinalizeUploadRequest {
+    pub checksum: String,
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum UploadType {
     File,
@@ -109,14 +135,18 @@ impl Into<File> for UploadRequest {
     fn into(self) -> File {
         File {
             // Select random string as name if not provided
-            name: if self.name.is_empty() {
-                utils::random_string(20)
-            } else {
+            name: if !self.name.is_empty() {
                 self.name
+            } else {
+                // Url uploads are named after the url if possible
+                self.url
+                    .as_deref()
+                    .filter(|_| self.upload_type == UploadType::Url)
+                    .and_then(url_upload::file_name)
+                    .unwrap_or_else(|| utils::random_string(20))
             },
             encryption: self.encryption.unwrap_or(0) as i32,
             is_public: self.public.unwrap_or(false),
-            public_filename: self.public_name,
             local_name: utils::random_string(30),
             ..file::File::default()
         }
@@ -132,6 +162,48 @@ fn is_encryption_valid(encr: i8) -> Option<bool> {
 }
 
 impl UploadRequest {
+    /// Create a request for uploading a new file into a namespace
+    pub fn new(name: &str, namespace: &str) -> Self {
+        UploadRequest {
+            upload_type: UploadType::File,
+            name: name.to_string(),
+            url: None,
+            public: None,
+            public_name: None,
+            public_expiry: None,
+            public_max_downloads: None,
+            public_password: None,
+            encryption: None,
+            compressed: None,
+            archived: None,
+            replace_file_by_id: None,
+            replace_equal_names: false,
+            all: false,
+            attributes: Some(FileAttributes {
+                namespace: namespace.to_string(),
+                tags: None,
+                groups: None,
+            }),
+            sha256: None,
+            blake3: None,
+        }
+    }
+
+    /// Limits of the public link of the uploaded file
+    pub fn link_limits(&self) -> Result<LinkLimits, RestError> {
+        LinkLimits::new(self.public_expiry, self.public_max_downloads)
+    }
+
+    /// Whether the blake3 digest of the content has to be calculated
+    pub fn wants_blake3(&self, config: &Config) -> bool {
+        config.hashing.blake3 || self.blake3.is_some()
+    }
+
+    /// Check the digests of the received content against the expected ones
+    pub fn verify_digests(&self, digests: &Digests) -> Result<(), RestError> {
+        digests.verify(self.sha256.as_deref(), self.blake3.as_deref())
+    }
+
     pub fn validate(&self, _user: &Authenticateduser) -> Result<(), RestError> {
         if !is_encryption_valid(self.encryption.unwrap_or(0)).unwrap_or(true) {
             return Err(RestError::IllegalOperation);
@@ -141,10 +213,21 @@ impl UploadRequest {
             return Err(RestError::IllegalOperation);
         }
 
+        self.link_limits()?;
+
         // TODO implement user permissions
         match self.upload_type {
             UploadType::File => {}
-            UploadType::Url => {}
+            UploadType::Url => {
+                if self
+                    .url
+                    .as_ref()
+                    .map(|i| i.trim().is_empty())
+                    .unwrap_or(true)
+                {
+                    return Err(RestError::BadRequest);
+                }
+            }
         }
 
         Ok(())
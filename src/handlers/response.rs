@@ -1,7 +1,15 @@
 use chrono::prelude::*;
 use serde::Serialize;
 
-use crate::models::file::File;
+use crate::models::{
+    drop_link::DropLink,
+    file::File,
+    namespace::Namespace,
+    namespace_member::{MemberRole, NamespaceMember},
+    quota::Quota,
+    share_link::ShareLink,
+    user::Role,
+};
 
 use super::requests::upload_request::FileAttributes;
 
@@ -23,6 +31,26 @@ pub struct LoginResponse {
     pub token: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct SessionItemResponse {
+    pub id: i32,
+    #[serde(rename = "mid")]
+    pub machine_id: Option<String>,
+    #[serde(rename = "creation")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "lastused")]
+    pub last_used: DateTime<Utc>,
+    #[serde(rename = "expires")]
+    pub expires_at: DateTime<Utc>,
+    pub requests: i64,
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionListResponse {
+    pub sessions: Vec<SessionItemResponse>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct UploadResponse {
     #[serde(rename = "fileID")]
@@ -31,26 +59,61 @@ pub struct UploadResponse {
     pub file_name: String,
     #[serde(rename = "publicFilename")]
     pub public_file_name: Option<String>,
+    #[serde(rename = "publicExpires")]
+    pub public_expires: Option<DateTime<Utc>>,
+    #[serde(rename = "publicMaxDownloads")]
+    pub public_max_downloads: Option<i32>,
+    /// The public link is protected by a password
+    #[serde(rename = "publicProtected")]
+    pub public_protected: bool,
     pub checksum: String,
     #[serde(rename = "size")]
     pub file_size: i64,
     #[serde(rename = "ns")]
     pub namespace: String,
+    /// The stored content is missing or damaged
+    pub corrupted: bool,
+    pub sha256: Option<String>,
+    pub blake3: Option<String>,
 }
 
 impl From<File> for UploadResponse {
     fn from(file: File) -> Self {
         UploadResponse {
             namespace: "".to_string(),
+            corrupted: file.corrupted,
+            sha256: file.sha256,
+            blake3: file.blake3,
             file_size: file.file_size,
             checksum: file.checksum,
-            public_file_name: file.public_filename,
+            public_file_name: None,
+            public_expires: None,
+            public_max_downloads: None,
+            public_protected: false,
             file_name: file.name,
             file_id: file.id,
         }
     }
 }
 
+impl UploadResponse {
+    /// Respond with the public link the file was shared with
+    pub fn set_link(&mut self, link: &ShareLink) {
+        self.public_file_name = Some(link.slug.clone());
+        self.public_expires = link.expires_at;
+        self.public_max_downloads = link.max_downloads;
+        self.public_protected = link.password.is_some();
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadSessionResponse {
+    pub id: String,
+    pub size: i64,
+    pub offset: i64,
+    pub expires: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct BulkPublishResponse {
     pub files: Vec<UploadResponse>,
@@ -65,6 +128,7 @@ pub struct FileItemResponse {
     pub name: String,
     #[serde(rename = "isPub")]
     pub is_public: bool,
+    /// Slug of the oldest public link of the file
     #[serde(rename = "pubname")]
     pub public_name: String,
     #[serde(rename = "attrib")]
@@ -73,6 +137,12 @@ pub struct FileItemResponse {
     pub encryption: i32,
     #[serde(rename = "checksum")]
     pub checksum: String,
+    /// The stored content is missing or damaged
+    pub corrupted: bool,
+    pub sha256: Option<String>,
+    pub blake3: Option<String>,
+    /// Name of the user who uploaded the file
+    pub uploader: Option<String>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -88,9 +158,13 @@ impl From<File> for FileItemResponse {
             creation_date: file.uploaded_at,
             name: file.name,
             is_public: file.is_public,
-            public_name: file.public_filename.unwrap_or_default(),
+            public_name: String::new(), // has to be set manually
             encryption: file.encryption,
             checksum: file.checksum,
+            corrupted: file.corrupted,
+            sha256: file.sha256,
+            blake3: file.blake3,
+            uploader: None, // has to be set manually
             attributes: FileAttributes {
                 groups: None,
                 tags: None,
@@ -100,15 +174,186 @@ impl From<File> for FileItemResponse {
     }
 }
 
+#[derive(Debug, Serialize, Clone)]
+pub struct ShareLinkItemResponse {
+    pub id: i32,
+    pub slug: String,
+    pub label: Option<String>,
+    #[serde(rename = "creation")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "expires")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(rename = "maxdl")]
+    pub max_downloads: Option<i32>,
+    pub downloads: i32,
+    /// The link is protected by a password
+    pub protected: bool,
+}
+
+impl From<ShareLink> for ShareLinkItemResponse {
+    fn from(link: ShareLink) -> Self {
+        ShareLinkItemResponse {
+            id: link.id,
+            slug: link.slug,
+            label: link.label,
+            created_at: link.created_at,
+            expires_at: link.expires_at,
+            max_downloads: link.max_downloads,
+            downloads: link.downloads,
+            protected: link.password.is_some(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ShareLinkListResponse {
+    pub links: Vec<ShareLinkItemResponse>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DropLinkItemResponse {
+    pub id: i32,
+    pub slug: String,
+    pub label: Option<String>,
+    #[serde(rename = "ns")]
+    pub namespace: String,
+    pub tags: Vec<String>,
+    #[serde(rename = "creation")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "expires")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(rename = "maxsize")]
+    pub max_file_size: Option<i64>,
+    #[serde(rename = "maxfiles")]
+    pub max_files: Option<i32>,
+    pub uploads: i32,
+    /// The link is protected by a password
+    pub protected: bool,
+}
+
+impl From<(DropLink, Namespace, String)> for DropLinkItemResponse {
+    fn from((link, namespace, owner): (DropLink, Namespace, String)) -> Self {
+        DropLinkItemResponse {
+            id: link.id,
+            slug: link.slug,
+            label: link.label,
+            namespace: namespace.path_for(link.user_id, &owner),
+            tags: link.tags,
+            created_at: link.created_at,
+            expires_at: link.expires_at,
+            max_file_size: link.max_file_size,
+            max_files: link.max_files,
+            uploads: link.uploads,
+            protected: link.password.is_some(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct NamespaceMemberResponse {
+    pub username: String,
+    pub role: MemberRole,
+    #[serde(rename = "since")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<(NamespaceMember, String)> for NamespaceMemberResponse {
+    fn from((member, username): (NamespaceMember, String)) -> Self {
+        NamespaceMemberResponse {
+            username,
+            role: member.role,
+            created_at: member.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct NamespaceMemberListResponse {
+    pub owner: String,
+    pub members: Vec<NamespaceMemberResponse>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DropLinkListResponse {
+    pub links: Vec<DropLinkItemResponse>,
+}
+
+/// Response to an anonymous upload. It doesn't
+/// reveal anything about the created file
+#[derive(Debug, Serialize, Clone)]
+pub struct DropUploadResponse {
+    #[serde(rename = "filename")]
+    pub file_name: String,
+    pub size: i64,
+    pub checksum: String,
+    pub sha256: Option<String>,
+}
+
+impl From<UploadResponse> for DropUploadResponse {
+    fn from(upload: UploadResponse) -> Self {
+        DropUploadResponse {
+            file_name: upload.file_name,
+            size: upload.file_size,
+            checksum: upload.checksum,
+            sha256: upload.sha256,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TrashItemResponse {
+    pub id: i32,
+    pub name: String,
+    pub size: i64,
+    #[serde(rename = "ns")]
+    pub namespace: String,
+    #[serde(rename = "deleted")]
+    pub deleted_at: DateTime<Utc>,
+    #[serde(rename = "purge")]
+    pub purge_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TrashListResponse {
+    pub files: Vec<TrashItemResponse>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct VersionItemResponse {
+    pub version: i32,
+    pub size: i64,
+    pub checksum: String,
+    pub sha256: Option<String>,
+    pub blake3: Option<String>,
+    #[serde(rename = "creation")]
+    pub creation_date: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct VersionListResponse {
+    pub versions: Vec<VersionItemResponse>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PruneVersionsResponse {
+    pub versions: Vec<i32>,
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct IDsResponse {
     pub ids: Vec<i32>,
 }
 
-#[derive(Debug, Default, Serialize, Clone, Copy)]
+#[derive(Debug, Default, Serialize, Clone)]
 pub struct StatsResponse {
     #[serde(rename = "trafficused")]
     pub traffic_used: i64,
+    #[serde(rename = "trafficupload")]
+    pub traffic_upload: i64,
+    #[serde(rename = "trafficdownload")]
+    pub traffic_download: i64,
+    #[serde(rename = "trafficpreview")]
+    pub traffic_preview: i64,
     #[serde(rename = "filesuploaded")]
     pub files_uploaded: i64,
     #[serde(rename = "totalfilesize")]
@@ -119,4 +364,84 @@ pub struct StatsResponse {
     pub group_count: i64,
     #[serde(rename = "tagcount")]
     pub tag_count: i64,
+    pub quota: QuotaResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespaces: Option<Vec<NamespaceStatsResponse>>,
+}
+
+/// Limits and usage of a quota. Unset limits are unlimited
+#[derive(Debug, Default, Serialize, Clone, Copy)]
+pub struct QuotaResponse {
+    #[serde(rename = "maxbytes")]
+    pub max_bytes: Option<i64>,
+    #[serde(rename = "maxfiles")]
+    pub max_files: Option<i64>,
+    #[serde(rename = "usedbytes")]
+    pub used_bytes: i64,
+    #[serde(rename = "usedfiles")]
+    pub used_files: i64,
+}
+
+impl QuotaResponse {
+    pub fn new(quota: Quota, used_bytes: i64, used_files: i64) -> Self {
+        QuotaResponse {
+            max_bytes: quota.bytes,
+            max_files: quota.files,
+            used_bytes,
+            used_files,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct NamespaceStatsResponse {
+    pub name: String,
+    #[serde(rename = "filecount")]
+    pub file_count: i64,
+    #[serde(rename = "totalfilesize")]
+    pub total_filesize: i64,
+    #[serde(rename = "groupcount")]
+    pub group_count: i64,
+    #[serde(rename = "tagcount")]
+    pub tag_count: i64,
+    #[serde(rename = "trafficused")]
+    pub traffic_used: i64,
+    pub quota: QuotaResponse,
+    #[serde(rename = "largestfiles")]
+    pub largest_files: Vec<StatsFileResponse>,
+    #[serde(rename = "filetypes")]
+    pub file_types: Vec<StatsFileTypeResponse>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct StatsFileResponse {
+    pub id: i32,
+    pub name: String,
+    pub size: i64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct StatsFileTypeResponse {
+    #[serde(rename = "type")]
+    pub file_type: String,
+    pub count: i64,
+    pub size: i64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AdminUserItemResponse {
+    pub id: i32,
+    pub username: String,
+    pub role: Role,
+    pub disabled: bool,
+    #[serde(rename = "namespacecount")]
+    pub namespaces_count: i64,
+    #[serde(rename = "trafficused")]
+    pub traffic_used: i64,
+    pub quota: QuotaResponse,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AdminUserListResponse {
+    pub users: Vec<AdminUserItemResponse>,
 }
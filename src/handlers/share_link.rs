@@ -0,0 +1,85 @@
+use super::{
+    authentication::Authenticateduser,
+    requests::file::{CreateShareLinkRequest, RevokeShareLinkRequest, ShareLinksRequest},
+    response::{ShareLinkItemResponse, ShareLinkListResponse},
+};
+use crate::{
+    config::Config,
+    models::{
+        file::File,
+        namespace_member::MemberRole,
+        share_link::{self, LinkLimits, ShareLink},
+    },
+    response_code::{RestError, Success, SUCCESS},
+    DbPool,
+};
+
+use actix_web::web::{self, Json};
+
+/// Endpoint for listing the public links of a file
+pub async fn ep_list_links(
+    pool: web::Data<DbPool>,
+    request: Json<ShareLinksRequest>,
+    user: Authenticateduser,
+) -> Result<Json<ShareLinkListResponse>, RestError> {
+    let db = pool.get()?;
+
+    let links = web::block(move || -> Result<_, RestError> {
+        let file = File::find_by_id(&db, request.file_id, user.user.id, MemberRole::Editor)?;
+        Ok(ShareLink::list(&db, file.id)?)
+    })
+    .await??
+    .into_iter()
+    .map(ShareLinkItemResponse::from)
+    .collect();
+
+    Ok(Json(ShareLinkListResponse { links }))
+}
+
+/// Endpoint for sharing a file using a new public link
+pub async fn ep_create_link(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    request: Json<CreateShareLinkRequest>,
+    user: Authenticateduser,
+) -> Result<Json<ShareLinkItemResponse>, RestError> {
+    let limits = LinkLimits::new(request.expiry, request.max_downloads)?;
+    let db = pool.get()?;
+    let hashing = config.password_hashing.clone();
+
+    let link = web::block(move || -> Result<_, RestError> {
+        let file = File::find_by_id(&db, request.file_id, user.user.id, MemberRole::Editor)?;
+
+        let password = request.password.as_deref().unwrap_or_default();
+        let password = share_link::hash_link_password(&hashing, password)?;
+
+        file.publish(
+            &db,
+            request.slug.as_deref().unwrap_or_default(),
+            request.label.clone(),
+            limits,
+            password,
+        )
+    })
+    .await??;
+
+    Ok(Json(link.into()))
+}
+
+/// Endpoint for revoking a public link. Files
+/// without any link aren't public anymore
+pub async fn ep_revoke_link(
+    pool: web::Data<DbPool>,
+    request: Json<RevokeShareLinkRequest>,
+    user: Authenticateduser,
+) -> Result<Json<Success>, RestError> {
+    let db = pool.get()?;
+
+    web::block(move || -> Result<(), RestError> {
+        let link = ShareLink::find_by_id(&db, request.id, user.user.id)?;
+        Ok(link.revoke(&db)?)
+    })
+    .await??;
+
+    Ok(SUCCESS)
+}
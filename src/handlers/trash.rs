@@ -0,0 +1,119 @@
+use super::{
+    authentication::Authenticateduser,
+    file_action::delete_objects,
+    requests::file::TrashRequest,
+    response::{IDsResponse, TrashItemResponse, TrashListResponse},
+};
+use crate::{
+    config::Config,
+    models::file::File,
+    response_code::{Origin, RestError},
+    storage::StorageBackend,
+    DbConnection, DbPool,
+};
+
+use actix_web::web::{self, Json};
+use chrono::Utc;
+
+/// Endpoint for listing the files in the trash
+pub async fn ep_list_trash(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    user: Authenticateduser,
+) -> Result<Json<TrashListResponse>, RestError> {
+    let db = pool.get()?;
+    let retention = config.trash_retention();
+
+    let files = web::block(move || File::list_trash(&db, user.user.id))
+        .await??
+        .into_iter()
+        .map(|(file, namespace)| {
+            let deleted_at = file.deleted_at.unwrap_or_else(Utc::now);
+
+            TrashItemResponse {
+                id: file.id,
+                name: file.name,
+                size: file.file_size,
+                namespace: namespace.name,
+                deleted_at,
+                purge_at: deleted_at + retention,
+            }
+        })
+        .collect();
+
+    Ok(Json(TrashListResponse { files }))
+}
+
+/// Endpoint for restoring files from the trash
+pub async fn ep_restore_trash(
+    pool: web::Data<DbPool>,
+    request: Json<TrashRequest>,
+    user: Authenticateduser,
+) -> Result<Json<IDsResponse>, RestError> {
+    let db = pool.get()?;
+
+    let ids = web::block(move || -> Result<Vec<i32>, RestError> {
+        let files = find_trashed(&db, &request, &user)?;
+
+        for file in files.iter() {
+            file.restore(&db)?;
+        }
+
+        Ok(files.iter().map(|i| i.id).collect())
+    })
+    .await??;
+
+    Ok(Json(IDsResponse { ids }))
+}
+
+/// Endpoint for irrevocably deleting files in the trash
+pub async fn ep_purge_trash(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    request: Json<TrashRequest>,
+    user: Authenticateduser,
+) -> Result<Json<IDsResponse>, RestError> {
+    let db = pool.get()?;
+
+    let (ids, orphaned) = web::block(move || -> Result<_, RestError> {
+        let files = find_trashed(&db, &request, &user)?;
+
+        let mut orphaned = vec![];
+        for file in files.iter() {
+            orphaned.extend(file.delete(&db)?);
+        }
+
+        Ok((files.iter().map(|i| i.id).collect(), orphaned))
+    })
+    .await??;
+
+    delete_objects(&**storage, &orphaned).await;
+
+    Ok(Json(IDsResponse { ids }))
+}
+
+/// Find the trashed files selected by the request
+fn find_trashed(
+    db: &DbConnection,
+    request: &TrashRequest,
+    user: &Authenticateduser,
+) -> Result<Vec<File>, RestError> {
+    // Either all files or at least one id has to be selected
+    if !request.all && request.file_ids.is_empty() {
+        return Err(RestError::BadRequest);
+    }
+
+    let ids = if request.all {
+        None
+    } else {
+        Some(request.file_ids.as_slice())
+    };
+
+    let files = File::find_trashed(db, user.user.id, ids)?;
+
+    if files.is_empty() {
+        return Err(RestError::DNotFound(Origin::Files));
+    }
+
+    Ok(files)
+}
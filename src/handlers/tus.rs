@@ -0,0 +1,491 @@
+use std::{collections::HashMap, str::FromStr, time::SystemTime};
+
+use super::{
+    authentication::Authenticateduser,
+    requests::upload_request::{FileAttributes, UploadRequest, UploadType},
+    upload_file::check_quota,
+    upload_session::{self, find_session, write_chunk, SessionLock},
+};
+use crate::{
+    config::Config,
+    models::upload_session::{NewUploadSession, UploadSession},
+    response_code::RestError,
+    storage::StorageBackend,
+    DbPool,
+};
+
+use actix_web::{
+    dev::HttpResponseBuilder,
+    http::{
+        header::{self, HttpDate},
+        HeaderValue, StatusCode,
+    },
+    web::{self, Payload},
+    HttpRequest, HttpResponse, ResponseError,
+};
+use async_std::fs;
+use futures::StreamExt;
+use sha2::{Digest, Sha256, Sha512};
+
+/// The only supported tus protocol version
+pub const TUS_VERSION: &str = "1.0.0";
+
+/// Supported tus protocol extensions
+pub const TUS_EXTENSIONS: &str = "creation,termination,checksum,expiration";
+
+/// Supported algorithms of the checksum extension
+pub const TUS_CHECKSUM_ALGORITHMS: &str = "sha256,sha512,crc32";
+
+/// Status code indicating a checksum mismatch
+const CHECKSUM_MISMATCH: u16 = 460;
+
+/// Endpoint for discovering the servers tus capabilities
+pub async fn ep_tus_options() -> HttpResponse {
+    with_tus_headers(HttpResponse::NoContent())
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", TUS_EXTENSIONS))
+        .insert_header(("Tus-Checksum-Algorithm", TUS_CHECKSUM_ALGORITHMS))
+        .finish()
+}
+
+/// Endpoint for creating a new tus upload
+pub async fn ep_tus_create(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    user: Authenticateduser,
+    request: HttpRequest,
+) -> HttpResponse {
+    respond(tus_create(pool, config, storage, user, request).await)
+}
+
+/// Endpoint for retrieving the offset of a tus upload
+pub async fn ep_tus_head(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    user: Authenticateduser,
+    token: web::Path<String>,
+    request: HttpRequest,
+) -> HttpResponse {
+    respond(
+        async {
+            check_version(&request)?;
+
+            let session = find_session(&pool, &config, token.into_inner(), &user).await?;
+
+            Ok(with_tus_headers(HttpResponse::Ok())
+                .insert_header(("Upload-Offset", session.received))
+                .insert_header(("Upload-Length", session.expected_size))
+                .insert_header(("Upload-Expires", expires_header(&session, &config)))
+                .insert_header((header::CACHE_CONTROL, "no-store"))
+                .finish())
+        }
+        .await,
+    )
+}
+
+/// Endpoint for appending data to a tus upload. The file
+/// gets created as soon as all bytes were received
+pub async fn ep_tus_patch(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    user: Authenticateduser,
+    token: web::Path<String>,
+    payload: Payload,
+    request: HttpRequest,
+) -> HttpResponse {
+    respond(
+        tus_patch(
+            pool,
+            config,
+            storage,
+            user,
+            token.into_inner(),
+            payload,
+            request,
+        )
+        .await,
+    )
+}
+
+/// Endpoint for terminating a tus upload
+pub async fn ep_tus_delete(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    user: Authenticateduser,
+    token: web::Path<String>,
+    request: HttpRequest,
+) -> HttpResponse {
+    respond(
+        async {
+            check_version(&request)?;
+
+            let session = find_session(&pool, &config, token.into_inner(), &user).await?;
+            let _lock = SessionLock::acquire(session.id)?;
+
+            let db = pool.get()?;
+            web::block(move || session.discard(&db, &config)).await??;
+
+            Ok(with_tus_headers(HttpResponse::NoContent()).finish())
+        }
+        .await,
+    )
+}
+
+async fn tus_create(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    user: Authenticateduser,
+    request: HttpRequest,
+) -> Result<HttpResponse, RestError> {
+    check_version(&request)?;
+
+    // Deferred lengths are not supported
+    let size: i64 = get_header(&request, "Upload-Length")
+        .and_then(|i| i.parse().ok())
+        .filter(|i| *i >= 0)
+        .ok_or(RestError::BadRequest)?;
+
+    let metadata = get_header(&request, "Upload-Metadata")
+        .map(parse_metadata)
+        .transpose()?
+        .unwrap_or_default();
+
+    let upload_request = metadata_to_request(&metadata)?;
+    upload_request.validate(&user)?;
+
+    let db = pool.get()?;
+    let user_cloned = user.clone();
+    let session = web::block(move || -> Result<UploadSession, RestError> {
+        // Fail early if the target namespace doesn't exist
+        // or the announced size exceeds the quotas
+        check_quota(&db, &upload_request, &user_cloned, size)?;
+
+        Ok(NewUploadSession::new(user_cloned.user.id, &upload_request, size)?.create(&db)?)
+    })
+    .await??;
+
+    if let Err(err) = fs::File::create(upload_session::local_path(&config, &session)).await {
+        let db = pool.get()?;
+        web::block(move || session.delete(&db)).await??;
+        return Err(err.into());
+    }
+
+    let location = format!(
+        "{}/upload/tus/{}",
+        config.server.external_url.trim_end_matches('/'),
+        session.token
+    );
+
+    let mut response = with_tus_headers(HttpResponse::Created());
+    response
+        .insert_header((header::LOCATION, location))
+        .insert_header(("Upload-Expires", expires_header(&session, &config)));
+
+    // Empty uploads are complete right away
+    if session.is_complete() {
+        let _lock = SessionLock::acquire(session.id)?;
+        let upload =
+            upload_session::finalize(&pool, &config, &**storage, user, session, None).await?;
+        response.insert_header(("X-FileID", upload.file_id));
+    }
+
+    Ok(response.finish())
+}
+
+async fn tus_patch(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    user: Authenticateduser,
+    token: String,
+    payload: Payload,
+    request: HttpRequest,
+) -> Result<HttpResponse, RestError> {
+    check_version(&request)?;
+
+    if get_header(&request, header::CONTENT_TYPE.as_str())
+        != Some("application/offset+octet-stream")
+    {
+        return Ok(with_tus_headers(HttpResponse::UnsupportedMediaType()).finish());
+    }
+
+    let offset: i64 = get_header(&request, "Upload-Offset")
+        .and_then(|i| i.parse().ok())
+        .ok_or(RestError::BadRequest)?;
+
+    let mut checksum = get_header(&request, "Upload-Checksum")
+        .map(ChecksumHasher::from_header)
+        .transpose()?;
+
+    let mut session = find_session(&pool, &config, token, &user).await?;
+    let _lock = SessionLock::acquire(session.id)?;
+
+    if offset != session.received {
+        return Err(RestError::OffsetMismatch);
+    }
+
+    let path = upload_session::local_path(&config, &session);
+    let stream = payload.inspect(|chunk| {
+        if let (Some(checksum), Ok(chunk)) = (checksum.as_mut(), chunk) {
+            checksum.update(chunk);
+        }
+    });
+
+    let (written, result) =
+        write_chunk(stream, &path, offset, session.expected_size - offset).await;
+
+    // Discard the chunk if it can't be verified using the checksum
+    let verified = match checksum {
+        Some(checksum) => result.is_ok() && checksum.verify(),
+        None => true,
+    };
+    let received = if verified { offset + written } else { offset };
+
+    let db = pool.get()?;
+    session = web::block(move || -> Result<UploadSession, RestError> {
+        session.set_received(&db, received)?;
+        Ok(session)
+    })
+    .await??;
+
+    result?;
+
+    if !verified {
+        // The data behind the offset gets truncated by the next chunk
+        return Ok(with_tus_headers(HttpResponse::build(
+            StatusCode::from_u16(CHECKSUM_MISMATCH).unwrap(),
+        ))
+        .finish());
+    }
+
+    let mut response = with_tus_headers(HttpResponse::NoContent());
+    response
+        .insert_header(("Upload-Offset", session.received))
+        .insert_header(("Upload-Expires", expires_header(&session, &config)));
+
+    if session.is_complete() {
+        let upload =
+            upload_session::finalize(&pool, &config, &**storage, user, session, None).await?;
+        response.insert_header(("X-FileID", upload.file_id));
+    }
+
+    Ok(response.finish())
+}
+
+/// Add the Tus-Resumable header to every response, including errors
+fn respond(result: Result<HttpResponse, RestError>) -> HttpResponse {
+    let mut response = match result {
+        Ok(response) => response,
+        Err(RestError::OffsetMismatch) => HttpResponse::Conflict().finish(),
+        Err(RestError::UnsupportedVersion) => HttpResponse::PreconditionFailed()
+            .insert_header(("Tus-Version", TUS_VERSION))
+            .finish(),
+        Err(err) => err.error_response(),
+    };
+
+    response.headers_mut().insert(
+        header::HeaderName::from_static("tus-resumable"),
+        HeaderValue::from_static(TUS_VERSION),
+    );
+
+    response
+}
+
+fn with_tus_headers(mut response: HttpResponseBuilder) -> HttpResponseBuilder {
+    response.insert_header(("Tus-Resumable", TUS_VERSION));
+    response
+}
+
+/// Ensure the client uses a supported protocol version
+fn check_version(request: &HttpRequest) -> Result<(), RestError> {
+    if get_header(request, "Tus-Resumable") != Some(TUS_VERSION) {
+        return Err(RestError::UnsupportedVersion);
+    }
+
+    Ok(())
+}
+
+fn get_header<'a>(request: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    request.headers().get(name).and_then(|i| i.to_str().ok())
+}
+
+fn expires_header(session: &UploadSession, config: &Config) -> String {
+    HttpDate::from(SystemTime::from(session.expires_at(config))).to_string()
+}
+
+/// Parse an Upload-Metadata header. Values are base64 encoded
+/// and optional, pairs are separated by commas
+pub fn parse_metadata(header: &str) -> Result<HashMap<String, String>, RestError> {
+    header
+        .split(',')
+        .map(str::trim)
+        .filter(|i| !i.is_empty())
+        .map(|pair| {
+            let mut split = pair.splitn(2, ' ');
+            let key = split.next().unwrap_or_default().to_owned();
+            let value = match split.next().map(str::trim) {
+                Some(value) => {
+                    String::from_utf8(base64::decode(value).map_err(|_| RestError::BadRequest)?)
+                        .map_err(|_| RestError::BadRequest)?
+                }
+                None => String::new(),
+            };
+
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// Map tus upload metadata onto an UploadRequest
+pub fn metadata_to_request(metadata: &HashMap<String, String>) -> Result<UploadRequest, RestError> {
+    let get = |key: &str| metadata.get(key).filter(|i| !i.is_empty());
+    let list = |key: &str| {
+        get(key).map(|i| {
+            i.split(',')
+                .map(|j| j.trim().to_owned())
+                .filter(|j| !j.is_empty())
+                .collect::<Vec<String>>()
+        })
+    };
+
+    let public = match get("public").map(|i| i.to_lowercase()) {
+        Some(public) => Some(match public.as_str() {
+            "true" | "1" => true,
+            "false" | "0" => false,
+            _ => return Err(RestError::BadRequest),
+        }),
+        None => None,
+    };
+
+    Ok(UploadRequest {
+        upload_type: UploadType::File,
+        name: get("name")
+            .or_else(|| get("filename"))
+            .cloned()
+            .unwrap_or_default(),
+        url: None,
+        public,
+        public_name: get("publicname").cloned(),
+        public_expiry: parse_number(get("publicexpiry"))?,
+        public_max_downloads: parse_number(get("publicmaxdownloads"))?,
+        public_password: get("publicpassword").cloned(),
+        encryption: None,
+        compressed: None,
+        archived: None,
+        replace_file_by_id: None,
+        replace_equal_names: false,
+        all: false,
+        attributes: Some(FileAttributes {
+            namespace: get("namespace")
+                .cloned()
+                .unwrap_or_else(|| "default".to_string()),
+            tags: list("tags"),
+            groups: list("groups"),
+        }),
+        sha256: get("sha256").cloned(),
+        blake3: get("blake3").cloned(),
+    })
+}
+
+fn parse_number<T: FromStr>(value: Option<&String>) -> Result<Option<T>, RestError> {
+    value
+        .map(|i| i.parse().map_err(|_| RestError::BadRequest))
+        .transpose()
+}
+
+/// Hasher for the algorithms of the checksum extension
+enum ChecksumHasher {
+    Sha256(Sha256, Vec<u8>),
+    Sha512(Sha512, Vec<u8>),
+    Crc32(crc32fast::Hasher, Vec<u8>),
+}
+
+impl ChecksumHasher {
+    /// Create a hasher from an Upload-Checksum header
+    fn from_header(header: &str) -> Result<Self, RestError> {
+        let mut split = header.trim().splitn(2, ' ');
+        let algorithm = split.next().unwrap_or_default().to_lowercase();
+        let expected = base64::decode(split.next().unwrap_or_default().trim())
+            .map_err(|_| RestError::BadRequest)?;
+
+        Ok(match algorithm.as_str() {
+            "sha256" => ChecksumHasher::Sha256(Sha256::new(), expected),
+            "sha512" => ChecksumHasher::Sha512(Sha512::new(), expected),
+            "crc32" => ChecksumHasher::Crc32(crc32fast::Hasher::new(), expected),
+            _ => return Err(RestError::BadRequest),
+        })
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            ChecksumHasher::Sha256(hasher, _) => hasher.update(data),
+            ChecksumHasher::Sha512(hasher, _) => hasher.update(data),
+            ChecksumHasher::Crc32(hasher, _) => hasher.update(data),
+        }
+    }
+
+    /// Returns true if the hashed data matches the expected checksum
+    fn verify(self) -> bool {
+        match self {
+            ChecksumHasher::Sha256(hasher, expected) => hasher.finalize().as_slice() == expected,
+            ChecksumHasher::Sha512(hasher, expected) => hasher.finalize().as_slice() == expected,
+            ChecksumHasher::Crc32(hasher, expected) => {
+                hasher.finalize().to_be_bytes().as_ref() == expected
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata =
+            parse_metadata("filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==,is_confidential")
+                .unwrap();
+
+        assert_eq!(metadata["filename"], "world_domination_plan.pdf");
+        assert_eq!(metadata["is_confidential"], "");
+    }
+
+    #[test]
+    fn test_parse_metadata_invalid() {
+        assert!(parse_metadata("filename !!!").is_err());
+    }
+
+    #[test]
+    fn test_metadata_to_request() {
+        let metadata = parse_metadata(&format!(
+            "filename {},namespace {},tags {},public {}",
+            base64::encode("log.txt"),
+            base64::encode("logs"),
+            base64::encode("a, b"),
+            base64::encode("true")
+        ))
+        .unwrap();
+
+        let request = metadata_to_request(&metadata).unwrap();
+        let attributes = request.attributes.unwrap();
+        assert_eq!(request.name, "log.txt");
+        assert_eq!(request.public, Some(true));
+        assert_eq!(attributes.namespace, "logs");
+        assert_eq!(attributes.tags, Some(vec!["a".to_owned(), "b".to_owned()]));
+        assert_eq!(attributes.groups, None);
+    }
+
+    #[test]
+    fn test_checksum_crc32() {
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(b"hello");
+        let header = format!("crc32 {}", base64::encode(crc.finalize().to_be_bytes()));
+        let mut hasher = ChecksumHasher::from_header(&header).unwrap();
+        hasher.update(b"hel");
+        hasher.update(b"lo");
+        assert!(hasher.verify());
+    }
+}
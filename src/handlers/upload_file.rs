@@ -1,22 +1,39 @@
-use std::{cmp, path::Path};
+use std::cmp;
 
 use super::{
-    authentication::Authenticateduser, requests::upload_request::UploadRequest,
-    response::UploadResponse, utils::retrieve_namespace,
+    authentication::Authenticateduser,
+    requests::upload_request::{UploadRequest, UploadType},
+    response::UploadResponse,
+    url_upload::save_url,
+    utils::retrieve_namespace,
 };
 use crate::{
     config::Config,
     models::{
         attribute,
+        blob::{self, Blob},
+        digest::{ContentHasher, Digests},
         file::{File, NewFile},
+        file_version::FileVersion,
         namespace::Namespace,
+        namespace_member::MemberRole,
+        quota::{self, Allowance},
+        share_link::{self, NewShareLink},
+        traffic::{self, TrafficKind},
+        user::User,
+    },
+    response_code::{Origin, RestError},
+    storage::{
+        self,
+        encryption::{DataKey, Keyring},
+        StorageBackend,
     },
-    response_code::RestError,
     utils, DbConnection, DbPool,
 };
 
 use actix_web::{
     dev::Decompress,
+    http::header,
     web::{self, Json, Payload},
     HttpRequest,
 };
@@ -25,7 +42,8 @@ use attribute::{
     AttributeType::{Group, Tag},
     NewAttribute,
 };
-use crc32fast::Hasher;
+use chrono::Utc;
+use diesel::Connection;
 use futures::StreamExt;
 use itertools::Itertools;
 use lazy_static::__Deref;
@@ -34,6 +52,7 @@ use lazy_static::__Deref;
 pub async fn ep_upload(
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
     user: Authenticateduser,
     upload_request: UploadRequest,
     payload: Payload,
@@ -45,8 +64,23 @@ pub async fn ep_upload(
     let db = pool.get()?;
     let request_cloned = upload_request.clone();
     let user_cloned = user.clone();
-    let (file, namespace) =
-        web::block(move || select_file(&request_cloned, &db, user_cloned)).await??;
+    let (file, namespace, allowance) = web::block(move || -> Result<_, RestError> {
+        let (file, namespace) = select_file(&request_cloned, &db, user_cloned.clone())?;
+        let allowance = quota::allowance(&db, &namespace)?;
+        Ok((file, namespace, allowance))
+    })
+    .await??;
+
+    // Reject uploads early which announce to exceed the quotas. The
+    // multipart body contains the content followed by its crc32. The
+    // size of compressed bodies is only known while they get stored
+    let declared_size = match upload_request.upload_type {
+        UploadType::File if !is_compressed(&upload_request, &request) => {
+            content_length(&request).map(|i| i - 8).unwrap_or(0)
+        }
+        _ => 0,
+    };
+    allowance.check(replaced_file(&file), declared_size)?;
 
     let upload = UploadHanler {
         payload,
@@ -54,9 +88,11 @@ pub async fn ep_upload(
         file: file.clone(),
         request,
         pool,
+        storage,
         upload_request,
         user,
         namespace,
+        allowance,
     };
 
     // Continue in out-sourced function
@@ -64,7 +100,7 @@ pub async fn ep_upload(
     let result = upload.handle().await;
     if let Err(err) = result {
         // Delete local file on fail
-        fs::remove_file(Path::new(&config.server.file_output_path).join(&file.local_name))
+        fs::remove_file(storage::staging_path(&config, &file.local_name))
             .await
             .ok();
 
@@ -81,65 +117,332 @@ struct UploadHanler {
     file: File,
     request: HttpRequest,
     pool: web::Data<r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>>,
+    storage: web::Data<dyn StorageBackend>,
     upload_request: UploadRequest,
     user: Authenticateduser,
     namespace: Namespace,
+    allowance: Allowance,
 }
 
 impl UploadHanler {
     /// Moved to extra function to catch error easily
     /// and delete the local file on an error
     async fn handle(mut self) -> Result<Json<UploadResponse>, RestError> {
-        let (crc, size, mime_type) = save_to_file(
-            self.payload,
-            self.config.deref(),
-            &self.file.local_name,
-            self.request,
-        )
-        .await?;
-        self.file.checksum = crc.clone();
+        // Cut off uploads exceeding the quotas
+        let limit = SizeLimit::quota(self.allowance.max_size(replaced_file(&self.file)));
+
+        let blake3 = self.upload_request.wants_blake3(&self.config);
+        let (digests, size, mime_type) = match self.upload_request.upload_type {
+            UploadType::File => {
+                save_to_file(
+                    self.payload,
+                    self.config.deref(),
+                    &self.file.local_name,
+                    self.request,
+                    true,
+                    limit,
+                    blake3,
+                )
+                .await?
+            }
+            UploadType::Url => {
+                let url = self.upload_request.url.as_deref().unwrap_or_default();
+                save_url(
+                    url,
+                    self.config.deref(),
+                    &self.file.local_name,
+                    limit,
+                    blake3,
+                )
+                .await?
+            }
+        };
+
+        self.upload_request.verify_digests(&digests)?;
+
+        // Remember where the content came from. Replacing a
+        // file with a regular upload drops its old source
+        self.file.source_url = match self.upload_request.upload_type {
+            UploadType::File => None,
+            UploadType::Url => self.upload_request.url.clone(),
+        };
+
+        self.file.set_digests(digests);
         self.file.file_size = size;
         self.file.file_type = mime_type;
-        let db = self.pool.get()?;
 
-        self.file.id = {
-            if self.file.id == 0 {
-                let new_file: NewFile = self.file.clone().into();
-                new_file.create(&db)?.id
+        store_upload(
+            &self.pool,
+            &self.config,
+            &**self.storage,
+            self.upload_request,
+            self.file,
+            self.user,
+            self.namespace,
+        )
+        .await
+        .map(Json)
+    }
+}
+
+/// Move the uploaded content of 'file' from the upload directory into the
+/// storage, then insert or update the file. Content which is stored already
+/// gets deduplicated. The previous content of a replaced file is released
+pub async fn store_upload(
+    pool: &web::Data<DbPool>,
+    config: &Config,
+    storage: &dyn StorageBackend,
+    upload_request: UploadRequest,
+    file: File,
+    user: Authenticateduser,
+    namespace: Namespace,
+) -> Result<UploadResponse, RestError> {
+    let local_name = file.local_name.clone();
+    let path = storage::staging_path(config, &local_name);
+    let keyring = Keyring::from_config(config).map_err(|_| RestError::Internal)?;
+
+    // Only move content into the storage which isn't stored yet. The
+    // sha256 digest calculated while receiving is the blobs hash
+    let db = pool.get()?;
+    let hash_path = path.clone();
+    let digest = file.sha256.clone().map(|i| (i, file.file_size));
+    let (hash, size, mut stored) = web::block(move || -> Result<_, RestError> {
+        let (hash, size) = match digest {
+            Some(digest) => digest,
+            None => blob::hash_file(&hash_path)?,
+        };
+        let stored = Blob::find_by_hash(&db, &hash)?.is_some();
+        Ok((hash, size, stored))
+    })
+    .await??;
+
+    let (uid, ns_id) = (user.user.id, namespace.id);
+    let (response, blob) = loop {
+        let mut key = None;
+        if !stored {
+            // New content gets encrypted before it's moved into the storage
+            if keyring.is_some() {
+                let data_key = DataKey::generate();
+                let (encrypt_key, encrypt_path) = (data_key.clone(), path.clone());
+                web::block(move || encrypt_key.encrypt_file(&encrypt_path)).await??;
+                key = Some(data_key);
+            }
+
+            storage.put_file(&local_name, &path).await?;
+        }
+
+        let content = UploadContent {
+            hash: hash.clone(),
+            size,
+            key,
+            stored,
+        };
+        let db = pool.get()?;
+        let hashing = config.password_hashing.clone();
+        let (upload_request, file, namespace) =
+            (upload_request.clone(), file.clone(), namespace.clone());
+        let keyring = keyring.clone();
+        let result = web::block(move || {
+            // New public files get shared using a link
+            let link = if file.id == 0 && file.is_public {
+                let password = upload_request
+                    .public_password
+                    .as_deref()
+                    .unwrap_or_default();
+
+                Some(NewShareLink::new(
+                    0,
+                    upload_request.public_name.as_deref().unwrap_or_default(),
+                    None,
+                    upload_request.link_limits()?,
+                    share_link::hash_link_password(&hashing, password)?,
+                ))
             } else {
-                self.file.save(&db)?;
-                self.file.id
+                None
+            };
+
+            let (mut response, blob) = match store_file(
+                &db,
+                &upload_request,
+                file,
+                link,
+                namespace.clone(),
+                content,
+                keyring.as_ref(),
+            )? {
+                Some(res) => res,
+                None => return Ok(None),
+            };
+
+            // Shared namespaces are addressed by the name of their owner
+            if namespace.user_id != uid {
+                let owner = User::find_by_id(&db, namespace.user_id)?;
+                response.namespace = namespace.path_for(uid, &owner.username);
+            }
+
+            Ok(Some((response, blob)))
+        })
+        .await?;
+
+        match result {
+            Ok(Some(result)) => break result,
+            // The blob found before was deleted in the meantime,
+            // so the content has to be moved into the storage
+            Ok(None) => stored = false,
+            Err(err) => {
+                if !stored {
+                    storage.delete(&local_name).await.ok();
+                }
+                return Err(err);
             }
+        }
+    };
+
+    if blob.local_name != local_name {
+        // Equal content is stored already
+        if stored {
+            fs::remove_file(&path).await.ok();
+        } else {
+            storage.delete(&local_name).await.ok();
+        }
+    }
+
+    // Account the received content, even if it gets deduplicated
+    traffic::record(uid, ns_id, TrafficKind::Upload, size as u64);
+
+    Ok(response)
+}
+
+/// The content of an upload
+pub struct UploadContent {
+    pub hash: String,
+    pub size: i64,
+    /// The key of the content if it was encrypted
+    pub key: Option<DataKey>,
+    /// Whether the content was found in the storage already
+    /// instead of being moved into it
+    pub stored: bool,
+}
+
+/// Insert or update the file and apply the requested attributes. The
+/// file gets linked to the blob of the passed content, which is created
+/// using the files local_name if it doesn't exist. The previous content
+/// of a replaced file is kept as a version. 'link' is created for
+/// new files to share them publicly. Returns the blob, or None without
+/// changing anything if content which was found in the storage before
+/// was deleted in the meantime
+pub fn store_file(
+    db: &DbConnection,
+    upload_request: &UploadRequest,
+    mut file: File,
+    link: Option<NewShareLink>,
+    namespace: Namespace,
+    content: UploadContent,
+    keyring: Option<&Keyring>,
+) -> Result<Option<(UploadResponse, Blob)>, RestError> {
+    let res = db.transaction::<_, RestError, _>(|| {
+        // Lock the current row to swap blobs atomically
+        let current = match file.id {
+            0 => None,
+            id => Some(File::find_for_update(db, id)?),
         };
 
-        handle_attributes(
-            &db,
-            &self.upload_request,
-            &self.file,
-            &self.user,
-            &self.namespace,
+        // The usage might have changed while receiving the content
+        quota::allowance(db, &namespace)?.check(current.as_ref(), content.size)?;
+
+        // Content found in the storage before might have been deleted
+        // in the meantime. Keep it from being deleted until it's linked
+        if content.stored && Blob::find_by_hash_for_update(db, &content.hash)?.is_none() {
+            return Ok(None);
+        }
+
+        let blob = Blob::acquire(
+            db,
+            &content.hash,
+            &file.local_name,
+            content.size,
+            content.key.is_some(),
         )?;
 
-        Ok(Json(UploadResponse {
-            file_size: size,
-            checksum: crc,
-            namespace: self.namespace.name,
-            file_id: self.file.id,
-            file_name: self.file.name,
-            public_file_name: self.file.public_filename,
-        }))
+        file.blob_id = Some(blob.id);
+
+        // Wrap the key of the content with the key of the files owner
+        file.wrapped_key = if blob.encrypted {
+            let keyring = keyring.ok_or_else(|| {
+                warn!("Blob {} is encrypted but no master key is set", blob.id);
+                RestError::Internal
+            })?;
+
+            let key = match content.key {
+                Some(ref key) if blob.local_name == file.local_name => key.clone(),
+                _ => blob.data_key(db, keyring)?,
+            };
+
+            Some(keyring.wrap(&key, file.user_id))
+        } else {
+            None
+        };
+
+        file.local_name = blob.local_name.clone();
+
+        file.id = match current {
+            None => {
+                let new_file: NewFile = file.clone().into();
+                new_file.create(db)?.id
+            }
+            Some(current) => {
+                // The replaced content is kept as a version
+                FileVersion::create_from(db, &current)?;
+
+                // Replacing the content invalidates cached copies
+                file.uploaded_at = Utc::now();
+                file.corrupted = false;
+                file.save(db)?;
+                file.id
+            }
+        };
+
+        let link = link
+            .map(|link| NewShareLink {
+                file_id: file.id,
+                ..link
+            })
+            .map(|link| link.create(db))
+            .transpose()?;
+
+        handle_attributes(db, upload_request, &file, &namespace)?;
+
+        Ok(Some((blob, link)))
+    })?;
+
+    let (blob, link) = match res {
+        Some(res) => res,
+        None => return Ok(None),
+    };
+
+    let mut response = UploadResponse::from(file);
+    response.namespace = namespace.name;
+    if let Some(ref link) = link {
+        response.set_link(link);
     }
+
+    Ok(Some((response, blob)))
 }
 
 /// Create or find a file object, on which the upload function
 /// should be applied. The create_new_file return indicates
 /// whether the file should be updated or inserted
-fn select_file(
+pub fn select_file(
     upload_request: &UploadRequest,
     db: &DbConnection,
     user: Authenticateduser,
 ) -> Result<(File, Namespace), RestError> {
-    let mut target_namespace = retrieve_namespace(db, &upload_request.attributes.as_ref(), &user)?;
+    let mut target_namespace = retrieve_namespace(
+        db,
+        &upload_request.attributes.as_ref(),
+        &user,
+        MemberRole::Uploader,
+    )?;
 
     let mut file: File = File::default();
     let mut replace_file = false;
@@ -150,6 +453,7 @@ fn select_file(
             0 => (), // No file found, continue as usual and create a new one
             1 => {
                 // One file found, replace this one
+                target_namespace.authorize(db, user.user.id, MemberRole::Editor, Origin::File)?;
                 file = File::find_by_name(db, &upload_request.name, target_namespace.id)?;
                 replace_file = true;
             }
@@ -159,50 +463,79 @@ fn select_file(
 
     if let Some(id) = upload_request.replace_file_by_id {
         // Replace file by id
-        file = File::find_by_id(db, id, user.user.id)?;
+        file = File::find_by_id(db, id, user.user.id, MemberRole::Editor)?;
         target_namespace = file.namespace(db)?;
-    } else if !replace_file {
+        replace_file = true;
+    }
+
+    if replace_file {
+        // The current content might be shared with other files and
+        // must be kept until the new one is stored successfully
+        file.local_name = utils::random_string(30);
+    } else {
         // Create a new file
         file = upload_request.clone().into();
         file.namespace_id = target_namespace.id;
-        file.user_id = user.user.id;
-
-        // Make public
-        // TODO check for collisions first
-        if upload_request.public.unwrap_or(false) {
-            file.public_filename = Some(
-                upload_request
-                    .public_name
-                    .as_ref()
-                    .cloned()
-                    .unwrap_or_else(|| utils::random_string(25)),
-            );
-        }
+        file.user_id = target_namespace.user_id;
+        file.uploader_id = Some(user.user.id);
     }
 
     Ok((file, target_namespace))
 }
 
+/// Get the file whose content gets replaced by an upload into 'file'
+pub fn replaced_file(file: &File) -> Option<&File> {
+    Some(file).filter(|i| i.id != 0)
+}
+
+/// Check whether an upload of 'size' bytes fits into the
+/// quotas of the user and the namespace it's uploaded into
+pub fn check_quota(
+    db: &DbConnection,
+    upload_request: &UploadRequest,
+    user: &Authenticateduser,
+    size: i64,
+) -> Result<(), RestError> {
+    let (file, namespace) = select_file(upload_request, db, user.clone())?;
+    quota::allowance(db, &namespace)?.check(replaced_file(&file), size)
+}
+
+/// Get the length of a requests body announced in its headers
+pub fn content_length(request: &HttpRequest) -> Option<i64> {
+    request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|i| i.to_str().ok())
+        .and_then(|i| i.parse().ok())
+}
+
+/// Returns true if the body of the request doesn't
+/// contain the plain content of the uploaded file
+fn is_compressed(upload_request: &UploadRequest, request: &HttpRequest) -> bool {
+    upload_request.compressed.unwrap_or(false)
+        || upload_request.archived.unwrap_or(false)
+        || request.headers().contains_key(header::CONTENT_ENCODING)
+}
+
 /// Creates and adds requested attributes
 /// to the uploaded file
 fn handle_attributes(
     db: &DbConnection,
     upload_request: &UploadRequest,
     file: &File,
-    user: &Authenticateduser,
     namespace: &Namespace,
 ) -> Result<(), RestError> {
     if let Some(ref attributes) = upload_request.attributes {
         // Get and create tags
         let tags = if let Some(ref tags) = attributes.tags {
-            NewAttribute::find_and_create(&db, &tags, Tag, user.user.id, namespace.id)?
+            NewAttribute::find_and_create(&db, &tags, Tag, namespace.user_id, namespace.id)?
         } else {
             vec![]
         };
 
         // Get and create groups
         let groups = if let Some(ref groups) = attributes.groups {
-            NewAttribute::find_and_create(&db, &groups, Group, user.user.id, namespace.id)?
+            NewAttribute::find_and_create(&db, &groups, Group, namespace.user_id, namespace.id)?
         } else {
             vec![]
         };
@@ -214,27 +547,74 @@ fn handle_attributes(
     Ok(())
 }
 
-/// Write a multipart to a given file. Returns
-/// (crc32, size, mimeType)
+/// Limits the size of a stream written by 'save_stream'
+#[derive(Debug, Clone, Copy)]
+pub struct SizeLimit {
+    pub max_size: i64,
+    /// The error returned if the stream exceeds the limit
+    pub error: RestError,
+}
+
+impl SizeLimit {
+    /// Limit a stream to the space left by the quotas
+    pub fn quota(max_size: Option<i64>) -> Option<Self> {
+        max_size.map(|max_size| SizeLimit {
+            max_size,
+            error: RestError::QuotaExceeded,
+        })
+    }
+
+    /// Combine two limits, keeping the lower one
+    pub fn min(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) if b.max_size < a.max_size => Some(b),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Write a request body to a given file. If 'crc_trailer' is set, the body
+/// ends with the crc32 checksum of the content. Content exceeding 'limit'
+/// is cut off. Returns (digests, size, mimeType)
 pub async fn save_to_file(
     body: Payload,
     config: &Config,
     filename: &str,
     request: HttpRequest,
-) -> Result<(String, i64, String), RestError> {
-    // Create new crc32 hasher to calculate the checksum
-    let mut hasher = crc32fast::Hasher::new();
+    crc_trailer: bool,
+    limit: Option<SizeLimit>,
+    blake3: bool,
+) -> Result<(Digests, i64, String), RestError> {
+    // Use header to determine whether the file should be decompressed
+    let stream = Decompress::from_headers(body, request.headers());
+    save_stream(stream, config, filename, crc_trailer, limit, blake3).await
+}
+
+/// Write a stream to a given file. If 'crc_trailer' is set, the last 8 bytes
+/// of the stream are expected to be the crc32 checksum of the content. Streams
+/// exceeding 'limit' are cut off. The blake3 digest is only calculated if
+/// 'blake3' is set. Returns (digests, size, mimeType)
+pub async fn save_stream<S, E>(
+    mut stream: S,
+    config: &Config,
+    filename: &str,
+    crc_trailer: bool,
+    limit: Option<SizeLimit>,
+    blake3: bool,
+) -> Result<(Digests, i64, String), RestError>
+where
+    S: futures::Stream<Item = Result<bytes::Bytes, E>> + Unpin,
+{
+    // Calculate the digests while writing the file
+    let mut hasher = ContentHasher::new(blake3);
 
     // Create a new local file
-    let mut file =
-        fs::File::create(Path::new(&config.server.file_output_path).join(filename)).await?;
+    let mut file = fs::File::create(storage::staging_path(config, filename)).await?;
 
     let mut size: i64 = 0;
     let mut mime_type: Option<String> = None;
 
-    // Use header to determine whether the file should be decompressed
-    let mut stream = Decompress::from_headers(body, request.headers());
-    let mut buf = UploadBuffer::new(8);
+    let mut buf = UploadBuffer::new(if crc_trailer { 8 } else { 0 });
     let mut contains_binary = false;
 
     // Write part
@@ -275,6 +655,12 @@ pub async fn save_to_file(
         .await?;
 
         size += (data.len() + dropped.len()) as i64;
+
+        if let Some(limit) = limit {
+            if size > limit.max_size {
+                return Err(limit.error);
+            }
+        }
     }
 
     file.flush().await?;
@@ -284,22 +670,25 @@ pub async fn save_to_file(
         mime_type = Some(String::from("text/plain"));
     }
 
-    let crc = format!("{:08x}", hasher.finalize()).to_lowercase();
-    let crc_rec = String::from_utf8(buf.get())
-        .map_err(|_| RestError::PartialContent)?
-        .to_lowercase();
+    let digests = hasher.finalize();
+
+    if crc_trailer {
+        let crc_rec = String::from_utf8(buf.get())
+            .map_err(|_| RestError::PartialContent)?
+            .to_lowercase();
 
-    if crc != crc_rec {
-        return Err(RestError::PartialContent);
+        if digests.crc32 != crc_rec {
+            return Err(RestError::PartialContent);
+        }
     }
 
-    Ok((crc, size, mime_type.unwrap_or_default()))
+    Ok((digests, size, mime_type.unwrap_or_default()))
 }
 
 /// Write to file and hasher at the same time
 async fn write(
     file: &mut fs::File,
-    hasher: &mut Hasher,
+    hasher: &mut ContentHasher,
     data: &[u8],
     contains_binary: &mut bool,
     mime_type: &Option<String>,
@@ -402,6 +791,11 @@ impl UploadBuffer {
         self.len
     }
 
+    /// Return true if nothing was written to the buffer yet
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     /// Return the size of the buffer
     pub fn size(&self) -> usize {
         self.size
@@ -0,0 +1,497 @@
+use std::{
+    collections::HashSet,
+    io::{Read, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use super::{
+    authentication::Authenticateduser,
+    requests::upload_request::{FinalizeUploadRequest, UploadRequest, UploadSessionRequest},
+    response::{UploadResponse, UploadSessionResponse},
+    upload_file::{check_quota, select_file, store_upload},
+};
+use crate::{
+    config::Config,
+    models::{
+        digest::{ContentHasher, Digests},
+        upload_session::{NewUploadSession, UploadSession},
+    },
+    response_code::{Origin, RestError, Success, SUCCESS},
+    storage::{self, StorageBackend},
+    DbPool,
+};
+
+use actix_web::{
+    dev::Decompress,
+    web::{self, Json, Payload},
+    HttpRequest,
+};
+use async_std::{fs, io::prelude::*};
+use futures::StreamExt;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// Sessions which are currently receiving data or being finalized
+    static ref ACTIVE_SESSIONS: Mutex<HashSet<i32>> = Mutex::new(HashSet::new());
+}
+
+/// Header containing the offset a chunk should be written to
+pub const OFFSET_HEADER: &str = "X-Offset";
+
+/// Endpoint for creating a new upload session
+pub async fn ep_create_session(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    user: Authenticateduser,
+    upload_request: UploadRequest,
+    request: Json<UploadSessionRequest>,
+) -> Result<Json<UploadSessionResponse>, RestError> {
+    upload_request.validate(&user)?;
+
+    if request.size < 0 {
+        return Err(RestError::BadRequest);
+    }
+
+    let db = pool.get()?;
+    let session = web::block(move || -> Result<UploadSession, RestError> {
+        // Fail early if the target namespace doesn't exist
+        // or the announced size exceeds the quotas
+        check_quota(&db, &upload_request, &user, request.size)?;
+
+        Ok(NewUploadSession::new(user.user.id, &upload_request, request.size)?.create(&db)?)
+    })
+    .await??;
+
+    // Create the (empty) local file
+    if let Err(err) = fs::File::create(local_path(&config, &session)).await {
+        let db = pool.get()?;
+        web::block(move || session.delete(&db)).await??;
+        return Err(err.into());
+    }
+
+    Ok(Json(session_response(&session, &config)))
+}
+
+/// Endpoint for retrieving the state of an upload session
+pub async fn ep_session_status(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    user: Authenticateduser,
+    token: web::Path<String>,
+) -> Result<Json<UploadSessionResponse>, RestError> {
+    let session = find_session(&pool, &config, token.into_inner(), &user).await?;
+    Ok(Json(session_response(&session, &config)))
+}
+
+/// Endpoint for writing a chunk into an upload session.
+/// The chunk is written at the offset passed in the
+/// X-Offset header which must not exceed the amount
+/// of already received bytes
+pub async fn ep_upload_chunk(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    user: Authenticateduser,
+    token: web::Path<String>,
+    payload: Payload,
+    request: HttpRequest,
+) -> Result<Json<UploadSessionResponse>, RestError> {
+    let offset: i64 = request
+        .headers()
+        .get(OFFSET_HEADER)
+        .and_then(|i| i.to_str().ok())
+        .and_then(|i| i.parse().ok())
+        .ok_or(RestError::BadRequest)?;
+
+    let mut session = find_session(&pool, &config, token.into_inner(), &user).await?;
+    let _lock = SessionLock::acquire(session.id)?;
+
+    if offset < 0 || offset > session.received {
+        return Err(RestError::OffsetMismatch);
+    }
+
+    let payload = Decompress::from_headers(payload, request.headers());
+    let path = local_path(&config, &session);
+    let (written, result) =
+        write_chunk(payload, &path, offset, session.expected_size - offset).await;
+
+    // Always store the received offset, even if the
+    // connection dropped, to allow resuming the upload
+    let db = pool.get()?;
+    session = web::block(move || -> Result<UploadSession, RestError> {
+        session.set_received(&db, offset + written)?;
+        Ok(session)
+    })
+    .await??;
+
+    result?;
+
+    Ok(Json(session_response(&session, &config)))
+}
+
+/// Endpoint for finalizing an upload session. The file
+/// gets verified using the passed checksum and is
+/// created or replaced like a regular upload
+pub async fn ep_finalize_session(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    user: Authenticateduser,
+    token: web::Path<String>,
+    request: Json<FinalizeUploadRequest>,
+) -> Result<Json<UploadResponse>, RestError> {
+    let session = find_session(&pool, &config, token.into_inner(), &user).await?;
+    let _lock = SessionLock::acquire(session.id)?;
+
+    Ok(Json(
+        finalize(
+            &pool,
+            &config,
+            &**storage,
+            user,
+            session,
+            Some(&request.checksum),
+        )
+        .await?,
+    ))
+}
+
+/// Verify a complete session and create or replace its file like
+/// a regular upload. The session lock has to be held by the caller
+pub async fn finalize(
+    pool: &web::Data<DbPool>,
+    config: &Config,
+    storage: &dyn StorageBackend,
+    user: Authenticateduser,
+    session: UploadSession,
+    checksum: Option<&str>,
+) -> Result<UploadResponse, RestError> {
+    if !session.is_complete() {
+        return Err(RestError::OffsetMismatch);
+    }
+
+    let upload_request = session.upload_request()?;
+    upload_request.validate(&user)?;
+
+    // Verify the received content
+    let path = local_path(config, &session);
+    let blake3 = upload_request.wants_blake3(config);
+    let (digests, mime_type) = web::block(move || inspect_file(&path, blake3)).await??;
+    if let Some(checksum) = checksum {
+        if digests.crc32 != checksum.to_lowercase() {
+            return Err(RestError::PartialContent);
+        }
+    }
+    upload_request.verify_digests(&digests)?;
+
+    let db = pool.get()?;
+    let request_cloned = upload_request.clone();
+    let user_cloned = user.clone();
+    let (mut file, namespace) =
+        web::block(move || select_file(&request_cloned, &db, user_cloned)).await??;
+
+    file.set_digests(digests);
+    file.file_size = session.received;
+    file.file_type = mime_type;
+    file.source_url = None;
+
+    let target = storage::staging_path(config, &file.local_name);
+    fs::rename(local_path(config, &session), &target).await?;
+
+    let result = store_upload(pool, config, storage, upload_request, file, user, namespace).await;
+    if result.is_err() {
+        // Delete the not stored local file on fail
+        fs::remove_file(&target).await.ok();
+    }
+
+    let db = pool.get()?;
+    web::block(move || session.delete(&db)).await??;
+
+    result
+}
+
+/// Endpoint for aborting an upload session
+pub async fn ep_delete_session(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    user: Authenticateduser,
+    token: web::Path<String>,
+) -> Result<Json<Success>, RestError> {
+    let session = find_session(&pool, &config, token.into_inner(), &user).await?;
+    let _lock = SessionLock::acquire(session.id)?;
+
+    let db = pool.get()?;
+    web::block(move || session.discard(&db, &config)).await??;
+
+    Ok(SUCCESS)
+}
+
+/// Find a session of the user. Expired sessions are discarded
+pub async fn find_session(
+    pool: &web::Data<DbPool>,
+    config: &web::Data<Config>,
+    token: String,
+    user: &Authenticateduser,
+) -> Result<UploadSession, RestError> {
+    let db = pool.get()?;
+    let config = config.clone();
+    let uid = user.user.id;
+
+    web::block(move || -> Result<UploadSession, RestError> {
+        let session = UploadSession::find(&db, &token, uid)?;
+
+        // Sessions receiving data right now get updated once they're done
+        let expired = session.expires_at(&config) < chrono::Utc::now();
+        if expired && !SessionLock::is_held(session.id) {
+            session.discard(&db, &config)?;
+            return Err(RestError::DNotFound(Origin::UploadSession));
+        }
+
+        Ok(session)
+    })
+    .await?
+}
+
+/// Write a chunk into the sessions file at the given offset. Data behind the
+/// offset gets truncated. Returns the amount of bytes which were written
+/// successfully, even if the stream failed afterwards
+pub async fn write_chunk<S, E>(
+    mut stream: S,
+    path: &Path,
+    offset: i64,
+    limit: i64,
+) -> (i64, Result<(), RestError>)
+where
+    S: futures::Stream<Item = Result<bytes::Bytes, E>> + Unpin,
+{
+    let mut written: i64 = 0;
+
+    let mut file = match fs::OpenOptions::new().write(true).open(path).await {
+        Ok(file) => file,
+        Err(err) => return (0, Err(err.into())),
+    };
+
+    let result = async {
+        file.set_len(offset as u64).await?;
+        file.seek(SeekFrom::Start(offset as u64)).await?;
+
+        while let Some(chunk) = stream.next().await {
+            let data = chunk.map_err(|_| RestError::UnknownIo)?;
+
+            if written + data.len() as i64 > limit {
+                return Err(RestError::BadRequest);
+            }
+
+            file.write_all(&data).await?;
+            written += data.len() as i64;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    // Keep what was written so far
+    if let Err(err) = file.flush().await.and(file.sync_all().await) {
+        return (written, Err(err.into()));
+    }
+
+    (written, result)
+}
+
+/// Calculate the digests and mime type of a local file
+pub fn inspect_file(path: &Path, blake3: bool) -> Result<(Digests, String), RestError> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = ContentHasher::new(blake3);
+    let mut buf = vec![0; 65_536];
+
+    let mut mime_type: Option<String> = None;
+    let mut contains_binary = false;
+    let mut first = true;
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+
+        let data = &buf[..n];
+
+        // detect filetype on first chunk
+        if first {
+            mime_type = infer::get(data).map(|i| i.mime_type().to_string());
+            first = false;
+        }
+
+        if mime_type.is_none() && !contains_binary && std::str::from_utf8(data).is_err() {
+            contains_binary = true;
+        }
+
+        hasher.update(data);
+    }
+
+    if !contains_binary && mime_type.is_none() {
+        mime_type = Some(String::from("text/plain"));
+    }
+
+    Ok((hasher.finalize(), mime_type.unwrap_or_default()))
+}
+
+/// Get the path of a sessions partial file
+pub fn local_path(config: &Config, session: &UploadSession) -> PathBuf {
+    storage::staging_path(config, &session.local_name)
+}
+
+fn session_response(session: &UploadSession, config: &Config) -> UploadSessionResponse {
+    UploadSessionResponse {
+        id: session.token.clone(),
+        size: session.expected_size,
+        offset: session.received,
+        expires: session.expires_at(config),
+    }
+}
+
+/// Marks a session as in use until it gets dropped
+pub struct SessionLock(i32);
+
+impl SessionLock {
+    pub fn acquire(id: i32) -> Result<Self, RestError> {
+        let mut sessions = ACTIVE_SESSIONS.lock().map_err(|_| RestError::Internal)?;
+
+        if !sessions.insert(id) {
+            return Err(RestError::UploadInProgress);
+        }
+
+        Ok(SessionLock(id))
+    }
+
+    /// Returns true if the session is in use
+    pub fn is_held(id: i32) -> bool {
+        ACTIVE_SESSIONS
+            .lock()
+            .map(|i| i.contains(&id))
+            .unwrap_or(false)
+    }
+}
+
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        if let Ok(mut sessions) = ACTIVE_SESSIONS.lock() {
+            sessions.remove(&self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::user::User, utils};
+    use chrono::Utc;
+    use diesel::{r2d2::ConnectionManager, PgConnection};
+    use futures::stream;
+
+    fn chunks(data: &[&'static [u8]]) -> impl futures::Stream<Item = Result<bytes::Bytes, ()>> {
+        stream::iter(
+            data.iter()
+                .map(|i| Ok(bytes::Bytes::from_static(i)))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// A session of 'content' which was received completely
+    fn get_session(config: &Config, content: &[u8]) -> UploadSession {
+        let session = UploadSession {
+            id: 0,
+            token: utils::random_string(40),
+            user_id: 0,
+            local_name: utils::random_string(30),
+            request: r#"{"type":0,"name":"file.txt","ren":false,"a":false}"#.to_owned(),
+            expected_size: content.len() as i64,
+            received: content.len() as i64,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        std::fs::write(local_path(config, &session), content).unwrap();
+        session
+    }
+
+    /// Finalize a session. Only verifying its content works without database
+    async fn finalize_session(
+        config: &Config,
+        session: UploadSession,
+        checksum: &str,
+    ) -> Result<UploadResponse, RestError> {
+        let manager = ConnectionManager::<PgConnection>::new("postgres://localhost/none");
+        let pool = web::Data::new(r2d2::Pool::builder().build_unchecked(manager));
+        let storage = storage::local::LocalStorage::new(&config.server.file_output_path);
+        let user = Authenticateduser {
+            default_ns: None,
+            user: User::default(),
+            token: String::new(),
+        };
+
+        finalize(&pool, config, &storage, user, session, Some(checksum)).await
+    }
+
+    #[actix_rt::test]
+    async fn test_write_chunk_truncates() {
+        let config = Config::temporary();
+        let path = Path::new(&config.server.file_output_path).join("chunk");
+        std::fs::File::create(&path).unwrap();
+
+        let (written, res) = write_chunk(chunks(&[b"hello ", b"world"]), &path, 0, 100).await;
+        assert!(res.is_ok());
+        assert_eq!(written, 11);
+
+        // Resending from an earlier offset drops the data behind it
+        let (written, res) = write_chunk(chunks(&[b"there"]), &path, 6, 94).await;
+        assert!(res.is_ok());
+        assert_eq!(written, 5);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello there");
+
+        let (_, res) = write_chunk(chunks(&[]), &path, 5, 95).await;
+        assert!(res.is_ok());
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+    }
+
+    #[actix_rt::test]
+    async fn test_write_chunk_limit() {
+        let config = Config::temporary();
+        let path = Path::new(&config.server.file_output_path).join("chunk");
+        std::fs::File::create(&path).unwrap();
+
+        // Data which was written before exceeding the size is kept
+        let (written, res) = write_chunk(chunks(&[b"hello", b" world"]), &path, 0, 8).await;
+        assert_eq!(res.unwrap_err(), RestError::BadRequest);
+        assert_eq!(written, 5);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+
+        // Failing streams keep the received part as well
+        let failing = stream::iter(vec![Ok(bytes::Bytes::from_static(b"abc")), Err(())]);
+        let (written, res) = write_chunk(failing, &path, 5, 3).await;
+        assert_eq!(res.unwrap_err(), RestError::UnknownIo);
+        assert_eq!(written, 3);
+        assert_eq!(std::fs::read(&path).unwrap(), b"helloabc");
+    }
+
+    #[actix_rt::test]
+    async fn test_finalize_checksum() {
+        let config = Config::temporary();
+
+        let session = get_session(&config, b"hello");
+        let res = finalize_session(&config, session, "00000000").await;
+        assert_eq!(res.unwrap_err(), RestError::PartialContent);
+
+        // Incomplete sessions can't be finalized
+        let mut session = get_session(&config, b"hello");
+        session.expected_size = 10;
+        let res = finalize_session(&config, session, "3610a686").await;
+        assert_eq!(res.unwrap_err(), RestError::OffsetMismatch);
+
+        // The expected digests of the upload request are verified as well
+        let mut session = get_session(&config, b"hello");
+        session.request = session.request.replace("}", r#","sha256":"abcd"}"#);
+        let res = finalize_session(&config, session, "3610A686").await;
+        assert_eq!(res.unwrap_err(), RestError::DigestMismatch);
+    }
+}
@@ -0,0 +1,414 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use crate::{
+    config::{Config, UrlUpload},
+    models::digest::Digests,
+    response_code::RestError,
+};
+
+use actix_web::{
+    http::{header, Uri},
+    rt,
+};
+use async_std::net::ToSocketAddrs;
+use bytes::Bytes;
+use futures::Stream;
+use url::Url;
+
+use super::upload_file::{save_stream, SizeLimit};
+
+/// Default size limit of url uploads (1GB)
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Default time limit of url uploads (5min)
+pub const DEFAULT_TIMEOUT: u64 = 5 * 60;
+
+/// Max amount of redirects followed for a single url upload
+const MAX_REDIRECTS: usize = 5;
+
+/// Fetch the resource behind 'url' and write it to the given local
+/// file. Content exceeding 'limit' or the configured size limit of
+/// url uploads is cut off. Returns (digests, size, mimeType)
+pub async fn save_url(
+    url: &str,
+    config: &Config,
+    filename: &str,
+    limit: Option<SizeLimit>,
+    blake3: bool,
+) -> Result<(Digests, i64, String), RestError> {
+    let limits = &config.url_upload;
+    let timeout = Duration::from_secs(limits.timeout.unwrap_or(DEFAULT_TIMEOUT));
+    let limit = SizeLimit::min(
+        Some(SizeLimit {
+            max_size: limits.max_size.unwrap_or(DEFAULT_MAX_SIZE) as i64,
+            error: RestError::PayloadTooLarge,
+        }),
+        limit,
+    );
+
+    rt::time::timeout(timeout, async {
+        let body = fetch(url, limits, timeout).await?;
+        save_stream(body, config, filename, false, limit, blake3).await
+    })
+    .await
+    .map_err(|_| RestError::RemoteTimeout)?
+}
+
+/// Request the passed url and return the response body. Redirects
+/// are followed while each target is checked against the limits
+pub async fn fetch(
+    url: &str,
+    limits: &UrlUpload,
+    timeout: Duration,
+) -> Result<impl Stream<Item = Result<Bytes, awc::error::PayloadError>>, RestError> {
+    let client = awc::Client::builder()
+        .disable_redirects()
+        .timeout(timeout)
+        .finish();
+
+    let mut uri: Uri = url.parse().map_err(|_| RestError::BadRequest)?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let (host, port) = check_uri(&uri, limits)?;
+        let addr = resolve(&host, port, limits).await?;
+
+        // Connect to the checked address directly to prevent the
+        // host from resolving to a different one in the meantime
+        let request = client
+            .get(&uri)
+            .address(SocketAddr::new(addr, port))
+            .insert_header((header::ACCEPT_ENCODING, "identity"));
+
+        let response = request.send().await.map_err(|err| {
+            debug!("{:?}", err);
+            RestError::RemoteError
+        })?;
+
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|i| i.to_str().ok())
+                .ok_or(RestError::RemoteError)?;
+
+            uri = join_location(&uri, location)?;
+            continue;
+        }
+
+        if !response.status().is_success() {
+            return Err(RestError::RemoteError);
+        }
+
+        // Reject early if the announced size exceeds the limit
+        let max_size = limits.max_size.unwrap_or(DEFAULT_MAX_SIZE);
+        let content_length = response
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|i| i.to_str().ok())
+            .and_then(|i| i.parse::<u64>().ok());
+
+        if content_length.map(|i| i > max_size).unwrap_or(false) {
+            return Err(RestError::PayloadTooLarge);
+        }
+
+        return Ok(response);
+    }
+
+    Err(RestError::RemoteError)
+}
+
+/// Get a file name from the last segment of an urls path
+pub fn file_name(url: &str) -> Option<String> {
+    let uri: Uri = url.parse().ok()?;
+    uri.path()
+        .rsplit('/')
+        .next()
+        .filter(|i| !i.is_empty())
+        .map(String::from)
+}
+
+/// Check the scheme and host of an uri. Returns its host and port
+fn check_uri(uri: &Uri, limits: &UrlUpload) -> Result<(String, u16), RestError> {
+    let port = match uri.scheme_str() {
+        Some("http") => uri.port_u16().unwrap_or(80),
+        Some("https") => uri.port_u16().unwrap_or(443),
+        _ => return Err(RestError::BadRequest),
+    };
+
+    let host = uri
+        .host()
+        .map(|i| {
+            i.trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_lowercase()
+        })
+        .filter(|i| !i.is_empty())
+        .ok_or(RestError::BadRequest)?;
+
+    if !is_host_allowed(&host, limits) {
+        return Err(RestError::Forbidden);
+    }
+
+    Ok((host, port))
+}
+
+/// Returns true if the host is not denied and, if
+/// an allow list is set, on the allow list
+fn is_host_allowed(host: &str, limits: &UrlUpload) -> bool {
+    let matches = |list: &Option<Vec<String>>| {
+        list.as_ref()
+            .map(|list| list.iter().any(|i| host_matches(host, i)))
+            .unwrap_or(false)
+    };
+
+    let allowed = match limits.allowed_hosts {
+        Some(ref list) if !list.is_empty() => matches(&limits.allowed_hosts),
+        _ => true,
+    };
+
+    allowed && !matches(&limits.denied_hosts)
+}
+
+/// Returns true if host equals 'pattern' or is a subdomain of it
+fn host_matches(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim_start_matches('.').to_lowercase();
+    host == pattern || host.ends_with(&format!(".{}", pattern))
+}
+
+/// Resolve a host and return the first address. All resolved
+/// addresses have to be public unless private hosts are allowed
+async fn resolve(host: &str, port: u16, limits: &UrlUpload) -> Result<IpAddr, RestError> {
+    let addrs: Vec<IpAddr> = (host, port)
+        .to_socket_addrs()
+        .await
+        .map_err(|_| RestError::RemoteError)?
+        .map(|i| i.ip())
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(RestError::RemoteError);
+    }
+
+    if !limits.allow_private_hosts && !addrs.iter().all(is_public_ip) {
+        return Err(RestError::Forbidden);
+    }
+
+    Ok(addrs[0])
+}
+
+/// Returns false for loopback, private, link-local
+/// and other not globally reachable addresses
+pub fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4() {
+            // IPv4-mapped and -compatible addresses
+            Some(v4) if !ip.is_loopback() && !ip.is_unspecified() => is_public_ipv4(&v4),
+            _ => is_public_ipv6(ip),
+        },
+    }
+}
+
+fn is_public_ipv4(ip: &Ipv4Addr) -> bool {
+    let octets = ip.octets();
+
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // 0.0.0.0/8
+        || octets[0] == 0
+        // Shared address space 100.64.0.0/10
+        || (octets[0] == 100 && (octets[1] & 0b1100_0000) == 64)
+        // IETF protocol assignments 192.0.0.0/24
+        || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0)
+        // Benchmarking 198.18.0.0/15
+        || (octets[0] == 198 && (octets[1] & 0b1111_1110) == 18)
+        // Reserved 240.0.0.0/4
+        || octets[0] >= 240)
+}
+
+fn is_public_ipv6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local fe80::/10
+        || (first & 0xffc0) == 0xfe80)
+}
+
+/// Resolve the Location header of a redirect against the current uri
+fn join_location(current: &Uri, location: &str) -> Result<Uri, RestError> {
+    let current = Url::parse(&current.to_string()).map_err(|_| RestError::RemoteError)?;
+
+    current
+        .join(location)
+        .map_err(|_| RestError::RemoteError)?
+        .as_str()
+        .parse()
+        .map_err(|_| RestError::RemoteError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    fn get_config(allow_private_hosts: bool) -> Config {
+        Config {
+            url_upload: UrlUpload {
+                allow_private_hosts,
+                max_size: Some(20),
+                ..UrlUpload::default()
+            },
+            ..Config::temporary()
+        }
+    }
+
+    fn stand_in() -> test::TestServer {
+        test::start(|| {
+            App::new()
+                .route(
+                    "/file.txt",
+                    web::get().to(|| HttpResponse::Ok().body("hello world")),
+                )
+                .route(
+                    "/redirect",
+                    web::get().to(|| {
+                        HttpResponse::Found()
+                            .insert_header((header::LOCATION, "/file.txt"))
+                            .finish()
+                    }),
+                )
+                .route(
+                    "/host",
+                    web::get().to(|req: actix_web::HttpRequest| {
+                        let host = req.headers().get(header::HOST).cloned();
+                        HttpResponse::Ok().body(host.unwrap().as_bytes().to_vec())
+                    }),
+                )
+                .route(
+                    "/large",
+                    web::get().to(|| HttpResponse::Ok().body(vec![b'a'; 100])),
+                )
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_save_url() {
+        let server = stand_in();
+        let config = get_config(true);
+
+        let (digests, size, mime) =
+            save_url(&server.url("/redirect"), &config, "file", None, false)
+                .await
+                .unwrap();
+
+        assert_eq!(digests.crc32, "0d4a1185");
+        assert_eq!(
+            digests.sha256,
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        assert_eq!(size, 11);
+        assert_eq!(mime, "text/plain");
+
+        let path = std::path::Path::new(&config.server.file_output_path).join("file");
+        assert_eq!(std::fs::read(path).unwrap(), b"hello world");
+    }
+
+    #[actix_rt::test]
+    async fn test_host_header() {
+        let server = stand_in();
+        let config = get_config(true);
+
+        let url = server.url("/host").replace("://", "://user:pass@");
+        save_url(&url, &config, "file", None, false).await.unwrap();
+
+        let path = std::path::Path::new(&config.server.file_output_path).join("file");
+        let host = String::from_utf8(std::fs::read(path).unwrap()).unwrap();
+        assert_eq!(host, format!("localhost:{}", server.addr().port()));
+    }
+
+    #[actix_rt::test]
+    async fn test_private_host_blocked() {
+        let server = stand_in();
+        let config = get_config(false);
+
+        let res = save_url(&server.url("/file.txt"), &config, "file", None, false).await;
+        assert_eq!(res.unwrap_err(), RestError::Forbidden);
+    }
+
+    #[actix_rt::test]
+    async fn test_denied_host() {
+        let server = stand_in();
+        let mut config = get_config(true);
+        config.url_upload.denied_hosts = Some(vec!["localhost".to_owned()]);
+
+        let url = server.url("/file.txt").replace("127.0.0.1", "localhost");
+        let res = save_url(&url, &config, "file", None, false).await;
+        assert_eq!(res.unwrap_err(), RestError::Forbidden);
+    }
+
+    #[actix_rt::test]
+    async fn test_size_limit() {
+        let server = stand_in();
+        let config = get_config(true);
+
+        let res = save_url(&server.url("/large"), &config, "file", None, false).await;
+        assert_eq!(res.unwrap_err(), RestError::PayloadTooLarge);
+    }
+
+    #[actix_rt::test]
+    async fn test_quota_limit() {
+        let server = stand_in();
+        let config = get_config(true);
+
+        let limit = SizeLimit::quota(Some(5));
+        let res = save_url(&server.url("/file.txt"), &config, "file", limit, false).await;
+        assert_eq!(res.unwrap_err(), RestError::QuotaExceeded);
+    }
+
+    #[test]
+    fn test_is_public_ip() {
+        assert!(is_public_ip(&"1.1.1.1".parse().unwrap()));
+        assert!(is_public_ip(&"2606:4700::1111".parse().unwrap()));
+        assert!(!is_public_ip(&"10.0.0.1".parse().unwrap()));
+        assert!(!is_public_ip(&"100.64.0.1".parse().unwrap()));
+        assert!(!is_public_ip(&"192.0.0.8".parse().unwrap()));
+        assert!(!is_public_ip(&"198.19.255.1".parse().unwrap()));
+        assert!(is_public_ip(&"198.20.0.1".parse().unwrap()));
+        assert!(!is_public_ip(&"169.254.169.254".parse().unwrap()));
+        assert!(!is_public_ip(&"::1".parse().unwrap()));
+        assert!(!is_public_ip(&"::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!is_public_ip(&"fd00::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_join_location() {
+        let current: Uri = "https://example.com/a/b?c=d".parse().unwrap();
+        let join = |location: &str| join_location(&current, location).unwrap().to_string();
+
+        assert_eq!(join("//other.org/x"), "https://other.org/x");
+        assert_eq!(join("/x"), "https://example.com/x");
+        assert_eq!(join("x"), "https://example.com/a/x");
+        assert_eq!(join("http://other.org"), "http://other.org/");
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(
+            file_name("https://example.com/a/b.tar.gz?x=1"),
+            Some("b.tar.gz".to_owned())
+        );
+        assert_eq!(file_name("https://example.com/"), None);
+    }
+}
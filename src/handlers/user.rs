@@ -1,18 +1,28 @@
 use crate::{
     config::Config,
-    models::user::User,
-    response_code::{RestError, Success, SUCCESS},
-    DbPool,
+    models::{
+        file, login_session::LoginSession, namespace::Namespace, namespace_member::MemberRole,
+        traffic, user::User,
+    },
+    response_code::{Origin, RestError, Success, SUCCESS},
+    DbConnection, DbPool,
 };
 
 use actix_web::web::{self, Json};
 
 use super::{
     authentication::Authenticateduser,
-    requests::CredentialsRequest,
-    response::{LoginResponse, StatsResponse},
+    requests::{CredentialsRequest, RevokeSessionRequest, StatsRequest},
+    response::{
+        LoginResponse, NamespaceStatsResponse, QuotaResponse, SessionItemResponse,
+        SessionListResponse, StatsFileResponse, StatsFileTypeResponse, StatsResponse,
+    },
+    utils::retrieve_namespace_by_name,
 };
 
+/// Amount of largest files listed per namespace in the stats
+const STATS_LARGEST_FILES: i64 = 10;
+
 /// Endpoint for registering new users
 pub async fn ep_register(
     pool: web::Data<DbPool>,
@@ -34,7 +44,7 @@ pub async fn ep_register(
 
     let new_user = User::new(req.username, req.password);
     let db = pool.get()?;
-    web::block(move || new_user.create(&db)).await??;
+    web::block(move || new_user.create(&db, &config)).await??;
 
     Ok(SUCCESS)
 }
@@ -42,6 +52,7 @@ pub async fn ep_register(
 /// Endpoint for loggin in users
 pub async fn ep_login(
     pool: web::Data<DbPool>,
+    config: web::Data<Config>,
     req: web::Json<CredentialsRequest>,
 ) -> Result<Json<LoginResponse>, RestError> {
     if req.has_empty() {
@@ -50,24 +61,56 @@ pub async fn ep_login(
 
     let db = pool.get()?;
 
-    let token = web::block(move || User::login(&db, &req.username, &req.password, &req.machine_id))
-        .await??;
+    let token = web::block(move || {
+        User::login(&db, &config, &req.username, &req.password, &req.machine_id)
+    })
+    .await??;
 
     Ok(Json(LoginResponse { token }))
 }
 
-/// Endpoint for loggin in users
+/// Endpoint for retrieving the stats of a user. The stats
+/// can be limited to a namespace or broken down by namespace
 pub async fn ep_stats(
     pool: web::Data<DbPool>,
     user: Authenticateduser,
+    request: Option<Json<StatsRequest>>,
 ) -> Result<Json<StatsResponse>, RestError> {
     let db = pool.get()?;
+    let request = request.map(|i| i.into_inner()).unwrap_or_default();
 
     let res = web::block(move || -> Result<StatsResponse, RestError> {
-        let total_files = user.user.total_filecount(&db)?;
-        let total_filesize = user.user.total_filesize(&db)?;
-        let namespaces_count = user.user.total_namespace_count(&db)?;
-        let (tag_count, group_count) = user.user.total_attribute_count(&db)?;
+        // Include the traffic which wasn't written yet
+        traffic::flush(&db)?;
+
+        // Stats only cover the users own namespaces
+        let namespace = match request.namespace {
+            Some(ref namespace) => {
+                let namespace =
+                    retrieve_namespace_by_name(&db, namespace, &user, MemberRole::Owner)?;
+                if namespace.user_id != user.user.id {
+                    return Err(RestError::DNotFound(Origin::Namespace));
+                }
+                Some(namespace)
+            }
+            None => None,
+        };
+        let ns_id = namespace.as_ref().map(|i| i.id);
+
+        let traffic = traffic::usage(&db, user.user.id, ns_id, request.since, request.until)?;
+        let total_files = user.user.total_filecount(&db, ns_id)?;
+        let total_filesize = user.user.total_filesize(&db, ns_id)?;
+        let (tag_count, group_count) = user.user.total_attribute_count(&db, ns_id)?;
+        let (namespaces_count, quota) = match namespace {
+            Some(ref namespace) => (1, namespace.quota()),
+            None => (user.user.total_namespace_count(&db)?, user.user.quota()),
+        };
+
+        let namespaces = if request.breakdown {
+            Some(namespace_stats(&db, &user, ns_id, &request)?)
+        } else {
+            None
+        };
 
         Ok(StatsResponse {
             files_uploaded: total_files,
@@ -75,10 +118,133 @@ pub async fn ep_stats(
             tag_count,
             group_count,
             total_filesize,
-            ..StatsResponse::default()
+            traffic_used: traffic.total(),
+            traffic_upload: traffic.upload,
+            traffic_download: traffic.download,
+            traffic_preview: traffic.preview,
+            quota: QuotaResponse::new(quota, total_filesize, total_files),
+            namespaces,
         })
     })
     .await??;
 
     Ok(Json(res))
 }
+
+/// Get the stats of each namespace of a user or only of 'namespace'
+fn namespace_stats(
+    db: &DbConnection,
+    user: &Authenticateduser,
+    namespace: Option<i32>,
+    request: &StatsRequest,
+) -> Result<Vec<NamespaceStatsResponse>, RestError> {
+    let mut stats = vec![];
+
+    for ns in Namespace::list(db, &user.user)? {
+        if namespace.map(|i| i != ns.id).unwrap_or(false) {
+            continue;
+        }
+
+        let (tag_count, group_count) = user.user.total_attribute_count(db, Some(ns.id))?;
+        let traffic = traffic::usage(db, user.user.id, Some(ns.id), request.since, request.until)?;
+
+        let largest_files = file::largest(db, ns.id, STATS_LARGEST_FILES)?
+            .into_iter()
+            .map(|i| StatsFileResponse {
+                id: i.id,
+                name: i.name,
+                size: i.file_size,
+            })
+            .collect();
+
+        let file_types = file::type_distribution(db, ns.id)?
+            .into_iter()
+            .map(|(file_type, count, size)| StatsFileTypeResponse {
+                file_type,
+                count,
+                size,
+            })
+            .collect();
+
+        let file_count = user.user.total_filecount(db, Some(ns.id))?;
+        let total_filesize = user.user.total_filesize(db, Some(ns.id))?;
+
+        stats.push(NamespaceStatsResponse {
+            quota: QuotaResponse::new(ns.quota(), total_filesize, file_count),
+            file_count,
+            total_filesize,
+            traffic_used: traffic.total(),
+            name: ns.name,
+            tag_count,
+            group_count,
+            largest_files,
+            file_types,
+        });
+    }
+
+    Ok(stats)
+}
+
+/// Endpoint for logging out
+pub async fn ep_logout(
+    pool: web::Data<DbPool>,
+    user: Authenticateduser,
+) -> Result<Json<Success>, RestError> {
+    let db = pool.get()?;
+
+    web::block(move || LoginSession::delete_by_token(&db, &user.token)).await??;
+
+    Ok(SUCCESS)
+}
+
+/// Endpoint for listing the login sessions of a user
+pub async fn ep_list_sessions(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    user: Authenticateduser,
+) -> Result<Json<SessionListResponse>, RestError> {
+    let db = pool.get()?;
+    let user_id = user.user.id;
+
+    let sessions = web::block(move || LoginSession::list(&db, user_id))
+        .await??
+        .into_iter()
+        .filter(|i| !i.is_expired(&config))
+        .map(|i| SessionItemResponse {
+            current: i.token == user.token,
+            id: i.id,
+            machine_id: i.machine_id,
+            created_at: i.created_at,
+            last_used: i.last_used,
+            expires_at: i.expires_at,
+            requests: i.requests,
+        })
+        .collect();
+
+    Ok(Json(SessionListResponse { sessions }))
+}
+
+/// Endpoint for revoking a single or all other login sessions
+pub async fn ep_revoke_sessions(
+    pool: web::Data<DbPool>,
+    user: Authenticateduser,
+    req: web::Json<RevokeSessionRequest>,
+) -> Result<Json<Success>, RestError> {
+    let db = pool.get()?;
+
+    web::block(move || -> Result<(), RestError> {
+        if req.others {
+            LoginSession::revoke_others(&db, user.user.id, &user.token)?;
+        } else {
+            let id = req.id.ok_or(RestError::BadRequest)?;
+            if !LoginSession::revoke(&db, user.user.id, id)? {
+                return Err(RestError::DNotFound(Origin::Session));
+            }
+        }
+
+        Ok(())
+    })
+    .await??;
+
+    Ok(SUCCESS)
+}
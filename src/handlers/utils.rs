@@ -2,7 +2,7 @@ use actix_web::web;
 
 use super::{authentication::Authenticateduser, requests::upload_request::FileAttributes};
 use crate::{
-    models::namespace::Namespace,
+    models::{namespace::Namespace, namespace_member::MemberRole},
     response_code::{Origin, RestError},
     DbConnection,
 };
@@ -11,8 +11,9 @@ pub async fn retrieve_namespace_by_name_async(
     db: DbConnection,
     namespace: String,
     user: Authenticateduser,
+    required: MemberRole,
 ) -> Result<Namespace, RestError> {
-    web::block(move || retrieve_namespace_by_name(&db, &namespace, &user)).await?
+    web::block(move || retrieve_namespace_by_name(&db, &namespace, &user, required)).await?
 }
 
 /// find a namespace by its name
@@ -20,6 +21,7 @@ pub fn retrieve_namespace_by_name(
     db: &DbConnection,
     namespace: &str,
     user: &Authenticateduser,
+    required: MemberRole,
 ) -> Result<Namespace, RestError> {
     retrieve_namespace(
         db,
@@ -29,15 +31,18 @@ pub fn retrieve_namespace_by_name(
             tags: None,
         }),
         user,
+        required,
     )
 }
 
 /// Try to get the desired namespace. Use the precached
-/// namespace if possible and desired
+/// namespace if possible and desired. The user needs at
+/// least the 'required' role in shared namespaces
 pub fn retrieve_namespace(
     db: &DbConnection,
     attributes: &Option<&FileAttributes>,
     user: &Authenticateduser,
+    required: MemberRole,
 ) -> Result<Namespace, RestError> {
     let ns_name = attributes
         .map(|i| i.namespace.clone())
@@ -50,8 +55,10 @@ pub fn retrieve_namespace(
                 .cloned()
                 .unwrap_or(user.user.get_default_namespace(&db)?)
         } else {
-            Namespace::find_by_name(&db, &ns_name, user.user.id)?
-                .ok_or(RestError::DNotFound(Origin::Namespace))?
+            let ns = Namespace::find_by_path(&db, &ns_name, user.user.id)?
+                .ok_or(RestError::DNotFound(Origin::Namespace))?;
+            ns.authorize(db, user.user.id, required, Origin::Namespace)?;
+            ns
         }
     })
 }
@@ -0,0 +1,171 @@
+use actix_web::{
+    web::{self, Json, Payload},
+    HttpRequest, HttpResponse,
+};
+use async_std::fs;
+
+use crate::{
+    config::Config,
+    handlers::{
+        authentication::Authenticateduser,
+        requests::upload_request::UploadRequest,
+        upload_file::{content_length, save_to_file, select_file, store_upload, SizeLimit},
+    },
+    models::{drop_link::DropLink, namespace::Namespace, quota, user::User},
+    response_code::{diesel_option, Origin, RestError},
+    storage::{self, StorageBackend},
+    templates, utils, DbPool,
+};
+
+use super::{super::response::DropUploadResponse, unlock};
+
+/// Endpoint for the upload page of a drop link
+pub async fn ep_drop_page(
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, RestError> {
+    let db = pool.get()?;
+
+    // Find link
+    let link = match web::block(move || DropLink::find_by_slug(&db, &slug)).await? {
+        Ok(o) => o,
+        Err(err) => match err {
+            diesel::result::Error::NotFound => return Ok(crate::to_home()),
+            _ => return Err(err.into()),
+        },
+    };
+
+    let host = &config.server.external_url;
+    Ok(HttpResponse::Ok().body(render!(templates::drop, host, &link)))
+}
+
+/// Endpoint for anonymously uploading a file through a drop link. The
+/// body is the plain content of the file. Visitors can't access any
+/// file, the uploaded ones are uploaded on behalf of the links creator
+pub async fn ep_drop_upload(
+    path: web::Path<(String, String)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    payload: Payload,
+    request: HttpRequest,
+) -> Result<Json<DropUploadResponse>, RestError> {
+    let (slug, name) = path.into_inner();
+    let db = pool.get()?;
+
+    let (link, owner, ns_path) = web::block(move || -> Result<_, RestError> {
+        let link =
+            DropLink::find_by_slug(&db, &slug).map_err(|i| diesel_option(i, Origin::DropLink))?;
+        let owner = User::find_by_id(&db, link.user_id)?;
+
+        // Links of members point into namespaces of other users
+        let namespace = Namespace::find_by_id(&db, link.namespace_id)?;
+        let ns_owner = User::find_by_id(&db, namespace.user_id)?;
+        let ns_path = namespace.path_for(owner.id, &ns_owner.username);
+
+        Ok((link, owner, ns_path))
+    })
+    .await??;
+
+    // Links of disabled users stop working
+    if owner.disabled {
+        return Err(RestError::DNotFound(Origin::DropLink));
+    }
+
+    if !is_unlocked(&link, &request).await? {
+        return Err(RestError::Unauthorized);
+    }
+
+    let user = Authenticateduser::without_session(owner);
+    let mut upload_request = UploadRequest::new(&name, &ns_path);
+    if let Some(ref mut attributes) = upload_request.attributes {
+        attributes.tags = Some(link.tags.clone()).filter(|i| !i.is_empty());
+    }
+    upload_request.validate(&user)?;
+
+    let declared_size = content_length(&request).unwrap_or(0);
+    if link
+        .max_file_size
+        .map(|i| declared_size > i)
+        .unwrap_or(false)
+    {
+        return Err(RestError::PayloadTooLarge);
+    }
+
+    // Check the quotas of the owner before counting the upload
+    let db = pool.get()?;
+    let request_cloned = upload_request.clone();
+    let user_cloned = user.clone();
+    let link_cloned = link.clone();
+    let (file, namespace, allowance) = web::block(move || -> Result<_, RestError> {
+        let (file, namespace) = select_file(&request_cloned, &db, user_cloned.clone())?;
+        let allowance = quota::allowance(&db, &namespace)?;
+        allowance.check(None, declared_size)?;
+
+        if !link_cloned.claim_upload(&db)? {
+            return Err(RestError::DNotFound(Origin::DropLink));
+        }
+
+        Ok((file, namespace, allowance))
+    })
+    .await??;
+
+    let limit = SizeLimit::min(
+        SizeLimit::quota(allowance.max_size(None)),
+        link.max_file_size.map(|max_size| SizeLimit {
+            max_size,
+            error: RestError::PayloadTooLarge,
+        }),
+    );
+
+    let local_name = file.local_name.clone();
+    let blake3 = upload_request.wants_blake3(&config);
+    let result = async {
+        let (digests, size, mime_type) =
+            save_to_file(payload, &config, &local_name, request, false, limit, blake3).await?;
+
+        let mut file = file;
+        file.set_digests(digests);
+        file.file_size = size;
+        file.file_type = mime_type;
+
+        store_upload(
+            &pool,
+            &config,
+            &**storage,
+            upload_request,
+            file,
+            user,
+            namespace,
+        )
+        .await
+    }
+    .await;
+
+    if result.is_err() {
+        // Delete local file and give back the upload on fail
+        fs::remove_file(storage::staging_path(&config, &local_name))
+            .await
+            .ok();
+
+        let db = pool.get()?;
+        web::block(move || link.release_upload(&db)).await??;
+    }
+
+    result.map(|i| Json(i.into()))
+}
+
+/// Whether the request passed the password of a protected drop link
+async fn is_unlocked(link: &DropLink, request: &HttpRequest) -> Result<bool, RestError> {
+    let hash = match link.password.clone() {
+        Some(hash) => hash,
+        None => return Ok(true),
+    };
+
+    match unlock::request_password(request) {
+        // Argon2 is too slow to run it on the executor
+        Some(password) => Ok(web::block(move || utils::verify_pw(&hash, "", &password)).await?),
+        None => Ok(false),
+    }
+}
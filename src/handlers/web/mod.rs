@@ -1,5 +1,7 @@
 #[macro_use]
 mod actix_ructe;
 
+pub mod drop;
 pub mod preview;
 pub mod raw_file_preview;
+pub mod unlock;
@@ -1,11 +1,19 @@
+use std::sync::Arc;
+
 use actix_web::{http::header::USER_AGENT, web, HttpRequest, HttpResponse};
 use humansize::{file_size_opts as options, FileSize};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 
-use crate::{config::Config, models::file::File, response_code::RestError, templates, DbPool};
+use crate::{
+    config::Config,
+    models::file::{File, Metadata},
+    response_code::RestError,
+    storage::{self, StorageBackend},
+    templates, DbPool,
+};
 
-use super::raw_file_preview;
+use super::{raw_file_preview, unlock};
 
 lazy_static! {
     pub static ref DEFAULT_ACE_THEME: String = String::from("nord_dark");
@@ -26,12 +34,13 @@ pub async fn ep_preview(
     file_id: web::Path<String>,
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
     request: HttpRequest,
 ) -> Result<HttpResponse, RestError> {
     let db = pool.get()?;
 
     // Find file
-    let file = match web::block(move || File::get_public_file(&db, &file_id)).await? {
+    let (file, link) = match web::block(move || File::get_public_file(&db, &file_id)).await? {
         Ok(o) => o,
         Err(err) => match err {
             diesel::result::Error::NotFound => return Ok(crate::to_home()),
@@ -39,6 +48,15 @@ pub async fn ep_preview(
         },
     };
 
+    // Protected links ask browsers for the password
+    if !unlock::is_unlocked(&link, &request).await? {
+        if check_is_raw_agent(&request, &config) {
+            return Err(RestError::Unauthorized);
+        }
+
+        return Ok(unlock::unlock_page(&file, &link, &config));
+    }
+
     let preview_type = get_preview_type(&file);
     let preview_size_limit = config
         .server
@@ -51,7 +69,8 @@ pub async fn ep_preview(
         || check_is_raw_agent(&request, &config)
         || is_raw_preview_file(&file)
     {
-        return raw_file_preview::serve_file(&file, &config).await;
+        let storage = Arc::clone(&storage);
+        return raw_file_preview::serve_file(&pool, &file, &link, &config, storage, &request).await;
     }
 
     let host = &config.server.external_url;
@@ -61,15 +80,35 @@ pub async fn ep_preview(
         .as_ref()
         .unwrap_or(&DEFAULT_ACE_THEME);
 
+    let archive = if preview_type == PreviewType::Archive {
+        archive_metadata(&file, &config, &**storage).await.ok()
+    } else {
+        None
+    };
+
     Ok(HttpResponse::Ok().body(render!(
         templates::preview,
         host,
+        &link.slug,
         &ace_theme,
         &file,
-        &config
+        &archive
     )))
 }
 
+/// Read the metadata of an archive file
+async fn archive_metadata(
+    file: &File,
+    config: &Config,
+    storage: &dyn StorageBackend,
+) -> Result<Metadata, RestError> {
+    let key = file.data_key(config)?;
+    let local_copy = storage::local_copy(storage, config, &file.local_name, key.as_ref()).await?;
+    let file = file.clone();
+
+    web::block(move || file.get_archive_metadata(local_copy.path())).await?
+}
+
 /// Returns the type of preview the frontend should load
 pub fn get_preview_type(file: &File) -> PreviewType {
     let ftype = &file.file_type;
@@ -1,24 +1,32 @@
-use std::{fs, path::Path};
+use std::sync::Arc;
 
-use actix_web::{
-    http::header::{self, CONTENT_LENGTH},
-    web, HttpResponse,
-};
+use actix_web::{dev::SizedStream, http::header, web, HttpRequest, HttpResponse};
 
-use crate::{config::Config, models::file::File, response_code::RestError, DbPool};
+use crate::{
+    config::Config,
+    models::{file::File, share_link::ShareLink, traffic::TrafficKind},
+    response_code::RestError,
+    storage::StorageBackend,
+    DbPool,
+};
 
-use super::super::chunked::ChunkedReadFile;
+use super::{
+    super::{extract, range, range::RangedFile},
+    unlock,
+};
 
 /// Endpoint for registering new users
 pub async fn ep_preview_raw(
     file_id: web::Path<String>,
     pool: web::Data<DbPool>,
     config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, RestError> {
     let db = pool.get()?;
 
     // Find file
-    let file = match web::block(move || File::get_public_file(&db, &file_id)).await? {
+    let (file, link) = match web::block(move || File::get_public_file(&db, &file_id)).await? {
         Ok(o) => o,
         Err(err) => match err {
             diesel::result::Error::NotFound => return Ok(crate::to_home()),
@@ -26,18 +34,85 @@ pub async fn ep_preview_raw(
         },
     };
 
-    serve_file(&file, &config).await
+    serve_file(&pool, &file, &link, &config, Arc::clone(&storage), &request).await
 }
 
-/// Serves the content of the file
-pub async fn serve_file(file: &File, config: &Config) -> Result<HttpResponse, RestError> {
+/// Endpoint for previewing a single member of a public archive file
+pub async fn ep_preview_entry(
+    path: web::Path<(String, String)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn StorageBackend>,
+    request: HttpRequest,
+) -> Result<HttpResponse, RestError> {
+    let db = pool.get()?;
+    let (file_id, entry_path) = path.into_inner();
+
+    // Find file
+    let (file, link) = match web::block(move || File::get_public_file(&db, &file_id)).await? {
+        Ok(o) => o,
+        Err(err) => match err {
+            diesel::result::Error::NotFound => return Ok(crate::to_home()),
+            _ => return Err(err.into()),
+        },
+    };
+
     if !file.is_public {
         return Ok(crate::to_home());
     }
 
+    if !unlock::is_unlocked(&link, &request).await? {
+        return Err(RestError::Unauthorized);
+    }
+
+    if !claim_download(&pool, &link).await? {
+        return Ok(crate::to_home());
+    }
+
+    let (entry, stream) = extract::open_entry(&file, &config, &**storage, &entry_path).await?;
+    let stream = extract::verified(&entry, stream);
+    let stream = range::counted(stream, &file, TrafficKind::Preview);
+
+    let mut response = HttpResponse::Ok();
+
+    if let Some(ref cors_allow) = config.server.cors_allow {
+        for cors_item in cors_allow {
+            response.append_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, cors_item.to_owned()));
+        }
+    }
+
+    Ok(response
+        .insert_header((header::CONTENT_TYPE, extract::content_type(&entry)))
+        .body(SizedStream::new(entry.size, stream)))
+}
+
+/// Serves the content of the file shared using 'link'
+pub async fn serve_file(
+    pool: &DbPool,
+    file: &File,
+    link: &ShareLink,
+    config: &Config,
+    storage: Arc<dyn StorageBackend>,
+    request: &HttpRequest,
+) -> Result<HttpResponse, RestError> {
+    if !file.is_public {
+        return Ok(crate::to_home());
+    }
+
+    if !unlock::is_unlocked(link, request).await? {
+        return Err(RestError::Unauthorized);
+    }
+
+    let ranged =
+        RangedFile::new(file, storage, file.data_key(config)?).count_traffic(TrafficKind::Preview);
+
+    // Every response containing a part of the file counts as download
+    if ranged.sends_content(request)? && !claim_download(pool, link).await? {
+        return Ok(crate::to_home());
+    }
+
     // build response
     let mut response = HttpResponse::Ok();
-    response.insert_header((CONTENT_LENGTH, file.file_size));
 
     if let Some(ref cors_allow) = config.server.cors_allow {
         for cors_item in cors_allow {
@@ -49,8 +124,96 @@ pub async fn serve_file(file: &File, config: &Config) -> Result<HttpResponse, Re
         response.insert_header(("Content-Type", format!("{};charset=UTF-8", file.file_type)));
     }
 
-    let f = fs::File::open(Path::new(&config.server.file_output_path).join(&file.local_name))?;
-    let reader = ChunkedReadFile::new(f.metadata()?.len(), 0, f);
+    ranged.into_response(request, response).await
+}
+
+/// Count a download of a public link. Returns
+/// false if the link expired in the meantime
+async fn claim_download(pool: &DbPool, link: &ShareLink) -> Result<bool, RestError> {
+    let db = pool.get()?;
+    let link = link.clone();
+    Ok(web::block(move || link.claim_download(&db)).await??)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{
+            file::NewFile,
+            namespace::{CreateNamespace, Namespace},
+            share_link::{LinkLimits, NewShareLink},
+        },
+        schema::users,
+        storage::local::LocalStorage,
+    };
+    use actix_web::{http::StatusCode, test::TestRequest};
+    use diesel::prelude::*;
+    use std::path::Path;
 
-    Ok(response.streaming(reader))
+    #[actix_rt::test]
+    #[ignore = "needs a database"]
+    async fn test_serve_file_download_limit() {
+        let pool = crate::db::test_pool();
+        let config = Config::temporary();
+        let dir = &config.server.file_output_path;
+        std::fs::write(Path::new(dir).join("content"), "hello world").unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(LocalStorage::new(dir));
+
+        let (file, link) = {
+            let db = pool.get().unwrap();
+            let uid: i32 = diesel::insert_into(users::table)
+                .values((
+                    users::username.eq("test_serve_file_download_limit"),
+                    users::password.eq(""),
+                ))
+                .returning(users::id)
+                .get_result(&db)
+                .unwrap();
+            CreateNamespace::new("default", uid).create(&db).unwrap();
+            let ns = Namespace::find_by_name(&db, "default", uid)
+                .unwrap()
+                .unwrap();
+
+            let file = NewFile {
+                name: "file".to_owned(),
+                user_id: uid,
+                local_name: "content".to_owned(),
+                file_size: 11,
+                namespace_id: ns.id,
+                ..NewFile::default()
+            }
+            .create(&db)
+            .unwrap();
+
+            let limits = LinkLimits::new(None, Some(2)).unwrap();
+            let link = NewShareLink::new(file.id, "", None, limits, None)
+                .create(&db)
+                .unwrap();
+            let (file, _) = File::get_public_file(&db, &link.slug).unwrap();
+            (file, link)
+        };
+
+        let serve = |range: &'static str| {
+            let request = TestRequest::default()
+                .insert_header((header::RANGE, range))
+                .to_http_request();
+            let (pool, file, link, config) = (&pool, &file, &link, &config);
+            let storage = storage.clone();
+            async move {
+                serve_file(pool, file, link, config, storage, &request)
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        // Requests skipping the start of the file count as well
+        assert_eq!(serve("bytes=0-0").await, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(serve("bytes=1-").await, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(serve("bytes=1-").await, StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(serve("bytes=5-").await, StatusCode::MOVED_PERMANENTLY);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
 }
@@ -0,0 +1,236 @@
+use actix_web::{
+    http::header,
+    web::{self, Form},
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use lazy_static::lazy_static;
+use rand::{rngs::OsRng, RngCore};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{
+    config::Config,
+    models::{file::File, share_link::ShareLink},
+    response_code::RestError,
+    templates, utils, DbPool,
+};
+
+/// Seconds an unlocked link stays accessible without entering the password again
+const UNLOCK_DURATION: i64 = 60 * 60;
+
+/// Header to pass the password of a protected link with
+const PASSWORD_HEADER: &str = "X-Share-Password";
+
+lazy_static! {
+    /// Key for signing unlock cookies. Cookies
+    /// become invalid when the server restarts
+    static ref COOKIE_KEY: [u8; 32] = {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        key
+    };
+}
+
+#[derive(Deserialize)]
+pub struct UnlockForm {
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct PasswordQuery {
+    password: Option<String>,
+}
+
+/// Endpoint for unlocking a password protected public link
+pub async fn ep_unlock(
+    file_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    form: Form<UnlockForm>,
+    request: HttpRequest,
+) -> Result<HttpResponse, RestError> {
+    let db = pool.get()?;
+
+    // Find file
+    let (file, link) = match web::block(move || File::get_public_file(&db, &file_id)).await? {
+        Ok(o) => o,
+        Err(err) => match err {
+            diesel::result::Error::NotFound => return Ok(crate::to_home()),
+            _ => return Err(err.into()),
+        },
+    };
+
+    let password = form.into_inner().password;
+    if !verify_password(&link, password).await? {
+        let host = &config.server.external_url;
+        return Ok(HttpResponse::Forbidden().body(render!(
+            templates::unlock,
+            host,
+            &link.slug,
+            &file,
+            true
+        )));
+    }
+
+    // Show the preview again, now using the cookie
+    Ok(HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, request.path().to_owned()))
+        .insert_header((header::SET_COOKIE, unlock_cookie(&link)))
+        .finish())
+}
+
+/// Render the page asking for the password of a protected link
+pub fn unlock_page(file: &File, link: &ShareLink, config: &Config) -> HttpResponse {
+    let host = &config.server.external_url;
+    HttpResponse::Unauthorized().body(render!(templates::unlock, host, &link.slug, file, false))
+}
+
+/// Whether the request may access the file behind a public link. The password
+/// of a protected link can be passed by header, query parameter or cookie
+pub async fn is_unlocked(link: &ShareLink, request: &HttpRequest) -> Result<bool, RestError> {
+    if link.password.is_none() {
+        return Ok(true);
+    }
+
+    if let Some(cookie) = request.cookie(&cookie_name(link)) {
+        if verify_cookie(link, cookie.value(), Utc::now().timestamp()) {
+            return Ok(true);
+        }
+    }
+
+    match request_password(request) {
+        Some(password) => verify_password(link, password).await,
+        None => Ok(false),
+    }
+}
+
+/// Get the password passed by header or query parameter
+pub fn request_password(request: &HttpRequest) -> Option<String> {
+    let header = request
+        .headers()
+        .get(PASSWORD_HEADER)
+        .and_then(|i| i.to_str().ok())
+        .map(|i| i.to_owned());
+
+    match header {
+        Some(password) => Some(password),
+        None => web::Query::<PasswordQuery>::from_query(request.query_string())
+            .ok()
+            .and_then(|i| i.into_inner().password),
+    }
+}
+
+/// Check a password against the one of a public link
+async fn verify_password(link: &ShareLink, password: String) -> Result<bool, RestError> {
+    let hash = match link.password.clone() {
+        Some(hash) => hash,
+        None => return Ok(true),
+    };
+
+    // Argon2 is too slow to run it on the executor
+    Ok(web::block(move || utils::verify_pw(&hash, "", &password)).await?)
+}
+
+fn cookie_name(link: &ShareLink) -> String {
+    format!("dv_unlock_{}", link.id)
+}
+
+/// Create the cookie unlocking a public link. It is
+/// invalidated by changing the password of the link
+fn unlock_cookie(link: &ShareLink) -> String {
+    let expires = Utc::now().timestamp() + UNLOCK_DURATION;
+
+    format!(
+        "{}={}.{}; Max-Age={}; Path=/preview; HttpOnly; SameSite=Lax",
+        cookie_name(link),
+        expires,
+        hex::encode(sign(link, expires)),
+        UNLOCK_DURATION
+    )
+}
+
+/// Check the value of an unlock cookie of 'link'
+fn verify_cookie(link: &ShareLink, value: &str, now: i64) -> bool {
+    let (expires, signature) = match value.split_once('.') {
+        Some(split) => split,
+        None => return false,
+    };
+
+    let expires = match expires.parse::<i64>() {
+        Ok(expires) if expires > now => expires,
+        _ => return false,
+    };
+
+    let signature = match hex::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    mac(link, expires).verify(&signature).is_ok()
+}
+
+fn sign(link: &ShareLink, expires: i64) -> Vec<u8> {
+    mac(link, expires).finalize().into_bytes().to_vec()
+}
+
+fn mac(link: &ShareLink, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(&*COOKIE_KEY).unwrap();
+    mac.update(link.id.to_string().as_bytes());
+    mac.update(b":");
+    mac.update(expires.to_string().as_bytes());
+    mac.update(b":");
+    mac.update(link.password.as_deref().unwrap_or_default().as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_cookie() {
+        let link = ShareLink {
+            id: 3,
+            file_id: 1,
+            slug: "slug".to_string(),
+            label: None,
+            created_at: Utc::now(),
+            expires_at: None,
+            password: Some("hash".to_string()),
+            max_downloads: None,
+            downloads: 0,
+        };
+
+        let now = Utc::now().timestamp();
+        let expires = now + 60;
+        let value = format!("{}.{}", expires, hex::encode(sign(&link, expires)));
+        assert!(verify_cookie(&link, &value, now));
+
+        // Expired
+        assert!(!verify_cookie(&link, &value, expires));
+
+        // Extended expiry
+        let forged = format!("{}.{}", expires + 60, hex::encode(sign(&link, expires)));
+        assert!(!verify_cookie(&link, &forged, now));
+
+        // Changed password
+        let changed = ShareLink {
+            password: Some("other".to_string()),
+            ..link.clone()
+        };
+        assert!(!verify_cookie(&changed, &value, now));
+
+        // Other link of the same file
+        let other = ShareLink {
+            id: 4,
+            ..link.clone()
+        };
+        assert!(!verify_cookie(&other, &value, now));
+
+        assert!(!verify_cookie(&link, "garbage", now));
+        assert!(!verify_cookie(&link, &format!("{}.abc", expires), now));
+        assert!(!verify_cookie(&link, &format!("{}.zz", expires), now));
+    }
+}
@@ -0,0 +1,194 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{rt, web};
+
+use crate::{
+    config::Config,
+    handlers::file_action::delete_objects,
+    models::{file, login_session, share_link, traffic, upload_session},
+    storage::{
+        scrub::{self, ScrubOptions},
+        StorageBackend,
+    },
+    DbPool,
+};
+
+/// Interval in which expired upload sessions get cleaned up
+const UPLOAD_SESSION_CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Interval in which expired login sessions get deleted
+const LOGIN_SESSION_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Interval in which recorded traffic gets written to the database
+const TRAFFIC_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval in which expired files get purged from the trash
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Interval in which expired public links get deleted
+const PUBLIC_LINK_CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Start all background jobs
+pub fn start(config: &Config, pool: &DbPool, storage: &Arc<dyn StorageBackend>) {
+    rt::spawn(cleanup_upload_sessions(config.clone(), pool.clone()));
+    rt::spawn(purge_trash(config.clone(), pool.clone(), storage.clone()));
+    rt::spawn(cleanup_login_sessions(config.clone(), pool.clone()));
+    rt::spawn(delete_expired_links(pool.clone()));
+    rt::spawn(flush_traffic(pool.clone()));
+
+    if let Some(hours) = config.scrub.interval.filter(|i| *i > 0) {
+        let interval = Duration::from_secs(hours * 60 * 60);
+        rt::spawn(scrub_storage(
+            config.clone(),
+            pool.clone(),
+            storage.clone(),
+            interval,
+        ));
+    }
+}
+
+/// Periodically delete expired upload sessions and their partial files
+async fn cleanup_upload_sessions(config: Config, pool: DbPool) {
+    let mut interval = rt::time::interval(UPLOAD_SESSION_CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let pool = pool.clone();
+        let config = config.clone();
+        let res = web::block(move || -> Result<usize, String> {
+            let db = pool.get().map_err(|e| e.to_string())?;
+            upload_session::delete_expired(&db, &config).map_err(|e| e.to_string())
+        })
+        .await;
+
+        match res {
+            Ok(Ok(n)) if n > 0 => info!("Deleted {} expired upload session(s)", n),
+            Ok(Err(err)) => warn!("Couldn't clean up upload sessions: {}", err),
+            _ => (),
+        }
+    }
+}
+
+/// Periodically delete files which have been in the trash for too long
+async fn purge_trash(config: Config, pool: DbPool, storage: Arc<dyn StorageBackend>) {
+    let mut interval = rt::time::interval(TRASH_PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let pool = pool.clone();
+        let config = config.clone();
+        let res = web::block(move || -> Result<Vec<String>, String> {
+            let db = pool.get().map_err(|e| e.to_string())?;
+            file::purge_expired(&db, &config).map_err(|e| e.to_string())
+        })
+        .await;
+
+        match res {
+            Ok(Ok(orphaned)) => delete_objects(&*storage, &orphaned).await,
+            Ok(Err(err)) => warn!("Couldn't purge trash: {}", err),
+            _ => (),
+        }
+    }
+}
+
+/// Periodically delete expired login sessions
+async fn cleanup_login_sessions(config: Config, pool: DbPool) {
+    let mut interval = rt::time::interval(LOGIN_SESSION_CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let pool = pool.clone();
+        let config = config.clone();
+        let res = web::block(move || -> Result<usize, String> {
+            let db = pool.get().map_err(|e| e.to_string())?;
+            login_session::delete_expired(&db, &config).map_err(|e| e.to_string())
+        })
+        .await;
+
+        match res {
+            Ok(Ok(n)) if n > 0 => info!("Deleted {} expired login session(s)", n),
+            Ok(Err(err)) => warn!("Couldn't clean up login sessions: {}", err),
+            _ => (),
+        }
+    }
+}
+
+/// Periodically delete expired public links. Files without any remaining
+/// link get unpublished. Expired links are rejected when accessed in the meantime
+async fn delete_expired_links(pool: DbPool) {
+    let mut interval = rt::time::interval(PUBLIC_LINK_CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let pool = pool.clone();
+        let res = web::block(move || -> Result<usize, String> {
+            let db = pool.get().map_err(|e| e.to_string())?;
+            share_link::delete_expired(&db).map_err(|e| e.to_string())
+        })
+        .await;
+
+        match res {
+            Ok(Ok(n)) if n > 0 => info!("Deleted {} expired public link(s)", n),
+            Ok(Err(err)) => warn!("Couldn't delete expired links: {}", err),
+            _ => (),
+        }
+    }
+}
+
+/// Periodically check the stored files for missing or damaged content
+async fn scrub_storage(
+    config: Config,
+    pool: DbPool,
+    storage: Arc<dyn StorageBackend>,
+    period: Duration,
+) {
+    let mut interval = rt::time::interval(period);
+    let options = ScrubOptions::from(&config.scrub);
+
+    loop {
+        interval.tick().await;
+
+        match scrub::scrub(&pool, &config, &*storage, options).await {
+            Ok(report) => {
+                for problem in report.problems.iter() {
+                    warn!("Scrub: {:?}", problem);
+                }
+
+                info!(
+                    "Scrubbed {} object(s), found {} problem(s)",
+                    report.objects,
+                    report.problems.len()
+                );
+            }
+            Err(err) => warn!("Couldn't scrub storage: {}", err),
+        }
+    }
+}
+
+/// Periodically write the recorded traffic to the database
+async fn flush_traffic(pool: DbPool) {
+    let mut interval = rt::time::interval(TRAFFIC_FLUSH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        write_traffic(&pool).await;
+    }
+}
+
+/// Write the recorded traffic to the database
+pub async fn write_traffic(pool: &DbPool) {
+    let pool = pool.clone();
+    let res = web::block(move || -> Result<usize, String> {
+        let db = pool.get().map_err(|e| e.to_string())?;
+        traffic::flush(&db).map_err(|e| e.to_string())
+    })
+    .await;
+
+    if let Ok(Err(err)) = res {
+        warn!("Couldn't write traffic: {}", err);
+    }
+}
@@ -0,0 +1,37 @@
+#![allow(clippy::new_ret_no_self)]
+
+include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate diesel;
+extern crate dotenv;
+
+pub mod config;
+pub mod db;
+pub mod handlers;
+pub mod jobs;
+pub mod models;
+pub mod response_code;
+pub mod schema;
+pub mod storage;
+pub mod utils;
+
+use actix_web::{
+    http::{header::LOCATION, HeaderValue},
+    HttpResponse,
+};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+pub type DbConnection = PooledConnection<ConnectionManager<PgConnection>>;
+
+/// Redirect to the index page
+pub fn to_home() -> HttpResponse {
+    HttpResponse::MovedPermanently()
+        .insert_header((LOCATION, HeaderValue::from_static("/")))
+        .finish()
+}
@@ -1,36 +1,24 @@
-#![allow(clippy::new_ret_no_self)]
-
-include!(concat!(env!("OUT_DIR"), "/templates.rs"));
-
 #[macro_use]
 extern crate log;
 
-#[macro_use]
-extern crate diesel;
-extern crate dotenv;
-
-pub mod config;
-mod db;
-mod handlers;
-pub mod models;
-mod response_code;
-mod schema;
-pub mod utils;
-
 use std::path::Path;
 
 use actix_files::NamedFile;
 use actix_web::{
-    http::{header::LOCATION, HeaderValue},
+    http::Method,
     middleware::{self},
-    web, App, HttpResponse, HttpServer,
+    web, App, HttpServer,
+};
+use dv_server::{
+    config, db,
+    handlers::{
+        self, admin, attributes, drop_link, extract, file_version, namespace, share_link, trash,
+        tus, upload_session,
+    },
+    jobs, models, response_code, storage,
+    storage::encryption::Keyring,
+    DbPool,
 };
-use diesel::prelude::*;
-use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
-use handlers::{attributes, namespace};
-
-pub type DbPool = Pool<ConnectionManager<PgConnection>>;
-pub type DbConnection = PooledConnection<ConnectionManager<PgConnection>>;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -57,13 +45,37 @@ async fn main() -> std::io::Result<()> {
 
     let db = db::connect();
     let listen_address = config.server.listen_address.clone();
+    let storage = storage::from_config(&config).expect("Couldn't set up storage");
+    // Fail early instead of on the first upload
+    Keyring::from_config(&config).expect("Invalid master key");
+
+    // Files stored before deduplication have to be linked to their content first
+    let unlinked = unlinked_file_count(&db).await;
+    if unlinked > 0 {
+        panic!(
+            "{} file(s) aren't linked to their content yet. Run 'dv_admin migrations run' first",
+            unlinked
+        );
+    }
+
+    let legacy_passwords = legacy_password_count(&db).await;
+    if legacy_passwords > 0 {
+        info!(
+            "{} user(s) still have legacy password hashes. They get upgraded on their next login",
+            legacy_passwords
+        );
+    }
+
+    jobs::start(&config, &db, &storage);
+    let pool = db.clone();
 
-    HttpServer::new(move || {
+    let res = HttpServer::new(move || {
         App::new()
             // Data
             .data(config.clone())
             .data(db.clone())
             .app_data(db.clone())
+            .app_data(web::Data::from(storage.clone()))
             // Middlewares
             .wrap(middleware::Logger::default())
             // Static files
@@ -76,41 +88,136 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/preview/raw/{fileID}")
                     .to(handlers::web::raw_file_preview::ep_preview_raw),
             )
-            .service(web::resource("/preview/{fileID}").to(handlers::web::preview::ep_preview))
+            .service(
+                web::resource("/preview/{fileID}/entry/{path:.*}")
+                    .to(handlers::web::raw_file_preview::ep_preview_entry),
+            )
+            .service(
+                web::resource("/preview/{fileID}")
+                    .route(web::post().to(handlers::web::unlock::ep_unlock))
+                    .to(handlers::web::preview::ep_preview),
+            )
             // API endpoints
+            .service(
+                web::resource("/drop/{slug}")
+                    .route(web::get().to(handlers::web::drop::ep_drop_page)),
+            )
+            .service(
+                web::resource("/drop/{slug}/{name}")
+                    .route(web::put().to(handlers::web::drop::ep_drop_upload)),
+            )
             .service(web::resource("/user/register").to(handlers::user::ep_register))
             .service(web::resource("/user/login").to(handlers::user::ep_login))
             .service(web::resource("/user/stats").to(handlers::user::ep_stats))
+            .service(web::resource("/user/logout").to(handlers::user::ep_logout))
+            .service(web::resource("/user/sessions").to(handlers::user::ep_list_sessions))
+            .service(web::resource("/user/sessions/revoke").to(handlers::user::ep_revoke_sessions))
+            // Admin
+            .service(web::resource("/admin/users").to(admin::ep_list_users))
+            .service(web::resource("/admin/users/create").to(admin::ep_create_user))
+            .service(web::resource("/admin/users/disable").to(admin::ep_disable_user))
+            .service(web::resource("/admin/users/enable").to(admin::ep_enable_user))
+            .service(web::resource("/admin/users/password").to(admin::ep_set_password))
+            .service(web::resource("/admin/users/quota").to(admin::ep_set_quota))
+            .service(web::resource("/admin/users/role").to(admin::ep_set_role))
+            .service(web::resource("/admin/users/delete").to(admin::ep_delete_user))
             .service(web::resource("/files").to(handlers::list_file::ep_list_files))
             .service(web::resource("/download/file").to(handlers::file_action::ep_file_download))
+            .service(web::resource("/download/archive").to(handlers::archive::ep_download_archive))
             .service(web::resource("/file/publish").to(handlers::file_action::ep_publish_file))
+            .service(web::resource("/file/versions").to(file_version::ep_list_versions))
+            .service(web::resource("/file/versions/download").to(file_version::ep_download_version))
+            .service(web::resource("/file/versions/restore").to(file_version::ep_restore_version))
+            .service(web::resource("/file/versions/prune").to(file_version::ep_prune_versions))
+            .service(web::resource("/file/archive/extract").to(extract::ep_extract_entry))
+            .service(web::resource("/file/archive/import").to(extract::ep_import_entry))
+            .service(web::resource("/file/links").to(share_link::ep_list_links))
+            .service(web::resource("/file/links/create").to(share_link::ep_create_link))
+            .service(web::resource("/file/links/revoke").to(share_link::ep_revoke_link))
             .service(web::resource("/file/{action}").to(handlers::file_action::ep_file_action))
             .service(web::resource("/attribute/{type}/get").to(attributes::ep_list_attributes))
             .service(
                 web::resource("/attribute/{type}/{action}").to(attributes::ep_attribute_action),
             )
+            .service(web::resource("/droplinks").to(drop_link::ep_list_drop_links))
+            .service(web::resource("/droplinks/create").to(drop_link::ep_create_drop_link))
+            .service(web::resource("/droplinks/revoke").to(drop_link::ep_revoke_drop_link))
             .service(web::resource("/ping").to(handlers::ping::ep_ping))
             .service(web::resource("/namespace/create").to(namespace::ep_create_namespace))
             .service(web::resource("/namespaces").to(namespace::ep_list_namespace))
             .service(web::resource("/namespace/update").to(namespace::ep_rename_namespace))
+            .service(web::resource("/namespace/quota").to(namespace::ep_namespace_quota))
+            .service(web::resource("/namespace/members").to(namespace::ep_list_members))
+            .service(web::resource("/namespace/members/add").to(namespace::ep_add_member))
+            .service(web::resource("/namespace/members/remove").to(namespace::ep_remove_member))
             .service(web::resource("/upload/file").to(handlers::upload_file::ep_upload))
+            .service(
+                web::resource("/upload/session")
+                    .route(web::post().to(upload_session::ep_create_session)),
+            )
+            .service(
+                web::resource("/upload/session/{id}")
+                    .route(web::get().to(upload_session::ep_session_status))
+                    .route(web::put().to(upload_session::ep_upload_chunk))
+                    .route(web::patch().to(upload_session::ep_upload_chunk))
+                    .route(web::delete().to(upload_session::ep_delete_session)),
+            )
+            .service(
+                web::resource("/upload/tus")
+                    .route(web::method(Method::OPTIONS).to(tus::ep_tus_options))
+                    .route(web::post().to(tus::ep_tus_create)),
+            )
+            .service(
+                web::resource("/upload/tus/{id}")
+                    .route(web::method(Method::OPTIONS).to(tus::ep_tus_options))
+                    .route(web::head().to(tus::ep_tus_head))
+                    .route(web::patch().to(tus::ep_tus_patch))
+                    .route(web::delete().to(tus::ep_tus_delete)),
+            )
+            .service(
+                web::resource("/upload/session/{id}/finalize")
+                    .route(web::post().to(upload_session::ep_finalize_session)),
+            )
             .service(web::resource("/namespace/delete").to(namespace::ep_delete_namespace))
+            .service(web::resource("/trash").to(trash::ep_list_trash))
+            .service(web::resource("/trash/restore").to(trash::ep_restore_trash))
+            .service(web::resource("/trash/purge").to(trash::ep_purge_trash))
             // Other
-            .default_service(web::route().to(to_home))
+            .default_service(web::route().to(dv_server::to_home))
     })
     .bind(listen_address)?
     .run()
+    .await;
+
+    // Don't lose the traffic recorded since the last flush
+    jobs::write_traffic(&pool).await;
+
+    res
+}
+
+/// Get the amount of files which aren't linked to a blob
+async fn unlinked_file_count(db: &DbPool) -> i64 {
+    let db = db.clone();
+    web::block(move || -> Result<i64, response_code::RestError> {
+        Ok(models::blob::count_unlinked_files(&db.get()?)?)
+    })
     .await
+    .expect("Couldn't count unlinked files")
+    .expect("Couldn't count unlinked files")
 }
 
-/// Serve index file
-async fn index() -> actix_web::Result<NamedFile> {
-    Ok(NamedFile::open(Path::new("html/index.html"))?)
+/// Get the amount of users whose password is hashed using the legacy scheme
+async fn legacy_password_count(db: &DbPool) -> i64 {
+    let db = db.clone();
+    web::block(move || -> Result<i64, response_code::RestError> {
+        Ok(models::user::User::count_legacy_passwords(&db.get()?)?)
+    })
+    .await
+    .expect("Couldn't count legacy passwords")
+    .expect("Couldn't count legacy passwords")
 }
 
 /// Serve index file
-fn to_home() -> HttpResponse {
-    HttpResponse::MovedPermanently()
-        .insert_header((LOCATION, HeaderValue::from_static("/")))
-        .finish()
+async fn index() -> actix_web::Result<NamedFile> {
+    Ok(NamedFile::open(Path::new("html/index.html"))?)
 }
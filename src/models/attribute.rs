@@ -184,6 +184,23 @@ impl NewAttribute {
         Ok(res)
     }
 
+    /// Find all attributes of type 'typ' matching one
+    /// of the names within the passed namespaces
+    pub fn find_in_namespaces(
+        db: &DbConnection,
+        items: &[String],
+        typ: AttributeType,
+        ns_ids: &[i32],
+    ) -> Result<Vec<Attribute>, DieselErr> {
+        use crate::schema::attributes::dsl::*;
+
+        attributes
+            .filter(name.eq_any(items))
+            .filter(type_.eq(typ))
+            .filter(namespace_id.eq_any(ns_ids))
+            .load(db)
+    }
+
     /// Create all missing attributes of type type_
     pub fn find_and_create(
         db: &DbConnection,
@@ -240,6 +257,22 @@ pub fn delete(db: &DbConnection, attr_id: i32) -> Result<(), DieselErr> {
     Ok(())
 }
 
+/// Delete all attributes of a namespace
+pub fn delete_in_namespace(db: &DbConnection, ns_id: i32) -> Result<(), DieselErr> {
+    use crate::schema::{attributes::dsl::*, file_attributes};
+
+    let ids = attributes.filter(namespace_id.eq(ns_id)).select(id);
+
+    diesel::delete(file_attributes::table)
+        .filter(file_attributes::attribute_id.eq_any(ids))
+        .execute(db)?;
+
+    diesel::delete(attributes)
+        .filter(namespace_id.eq(ns_id))
+        .execute(db)?;
+    Ok(())
+}
+
 impl<DB: Backend> ToSql<SmallInt, DB> for AttributeType
 where
     i16: ToSql<SmallInt, DB>,
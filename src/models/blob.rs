@@ -0,0 +1,452 @@
+use crate::{
+    config::Config,
+    models::file::File,
+    response_code::RestError,
+    schema::{blobs, file_versions, files},
+    storage::encryption::{DataKey, Keyring},
+    DbConnection,
+};
+use chrono::prelude::*;
+use diesel::{prelude::*, result::Error as DieselErr};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+/// The stored content of one or more files. Files with
+/// equal content share the same blob and local file
+#[derive(Identifiable, Queryable, Debug, AsChangeset, Clone)]
+pub struct Blob {
+    pub id: i32,
+    pub hash: String,
+    pub local_name: String,
+    pub size: i64,
+    pub ref_count: i32,
+    pub created_at: DateTime<Utc>,
+    pub encrypted: bool,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "blobs"]
+pub struct NewBlob<'a> {
+    pub hash: &'a str,
+    pub local_name: &'a str,
+    pub size: i64,
+    pub ref_count: i32,
+    pub encrypted: bool,
+}
+
+impl Blob {
+    /// Add a reference to the blob with the given hash. If no such
+    /// blob exists, a new one is created using 'local_name'
+    pub fn acquire(
+        db: &DbConnection,
+        q_hash: &str,
+        q_local_name: &str,
+        q_size: i64,
+        q_encrypted: bool,
+    ) -> Result<Blob, DieselErr> {
+        use crate::schema::blobs::dsl::*;
+
+        diesel::insert_into(blobs)
+            .values(&NewBlob {
+                hash: q_hash,
+                local_name: q_local_name,
+                size: q_size,
+                ref_count: 1,
+                encrypted: q_encrypted,
+            })
+            .on_conflict(hash)
+            .do_update()
+            .set(ref_count.eq(ref_count + 1))
+            .get_result(db)
+    }
+
+    /// Add a reference to an existing blob
+    pub fn retain(db: &DbConnection, blob_id: i32) -> Result<Blob, DieselErr> {
+        use crate::schema::blobs::dsl::*;
+
+        diesel::update(blobs.find(blob_id))
+            .set(ref_count.eq(ref_count + 1))
+            .get_result(db)
+    }
+
+    /// Find the blob with the given content hash
+    pub fn find_by_hash(db: &DbConnection, q_hash: &str) -> Result<Option<Blob>, DieselErr> {
+        use crate::schema::blobs::dsl::*;
+
+        blobs.filter(hash.eq(q_hash)).first(db).optional()
+    }
+
+    /// Find the blob with the given content hash and
+    /// lock its row until the current transaction ends
+    pub fn find_by_hash_for_update(
+        db: &DbConnection,
+        q_hash: &str,
+    ) -> Result<Option<Blob>, DieselErr> {
+        use crate::schema::blobs::dsl::*;
+
+        blobs
+            .filter(hash.eq(q_hash))
+            .for_update()
+            .first(db)
+            .optional()
+    }
+
+    /// Remove a reference from a blob. Returns the blob
+    /// if it was deleted since it isn't referenced anymore.
+    /// Its local file has to be removed by the caller
+    pub fn release(db: &DbConnection, blob_id: i32) -> Result<Option<Blob>, DieselErr> {
+        use crate::schema::blobs::dsl::*;
+
+        let blob: Blob = diesel::update(blobs.find(blob_id))
+            .set(ref_count.eq(ref_count - 1))
+            .get_result(db)?;
+
+        if blob.ref_count > 0 {
+            return Ok(None);
+        }
+
+        // Only delete if it wasn't acquired in the meantime
+        let deleted =
+            diesel::delete(blobs.filter(id.eq(blob_id).and(ref_count.le(0)))).execute(db)?;
+
+        Ok(if deleted > 0 { Some(blob) } else { None })
+    }
+
+    /// Get the key of an encrypted blob by unwrapping
+    /// it from a file or file version referencing it
+    pub fn data_key(&self, db: &DbConnection, keyring: &Keyring) -> Result<DataKey, RestError> {
+        let wrapped: Option<(i32, Option<String>)> = files::table
+            .filter(files::blob_id.eq(self.id))
+            .filter(files::wrapped_key.is_not_null())
+            .select((files::user_id, files::wrapped_key))
+            .first(db)
+            .optional()?;
+
+        // Versions are owned by the owner of their file
+        let (owner, wrapped_key) = match wrapped {
+            Some(wrapped) => wrapped,
+            None => file_versions::table
+                .inner_join(files::table)
+                .filter(file_versions::blob_id.eq(self.id))
+                .filter(file_versions::wrapped_key.is_not_null())
+                .select((files::user_id, file_versions::wrapped_key))
+                .first(db)?,
+        };
+
+        let wrapped_key = wrapped_key.ok_or(RestError::Internal)?;
+        Ok(keyring.unwrap(&wrapped_key, owner)?.0)
+    }
+}
+
+/// Hash the local file 'local_name' and reference the blob with
+/// the same content. Creates a new blob owning the local file if
+/// no such blob exists. Returns the blob and whether the local file
+/// is a duplicate, which should be removed once the current
+/// transaction was committed
+pub fn store(
+    db: &DbConnection,
+    config: &Config,
+    local_name: &str,
+) -> Result<(Blob, bool), RestError> {
+    let path = Path::new(&config.server.file_output_path).join(local_name);
+    let (hash, size) = hash_file(&path)?;
+
+    let blob = Blob::acquire(db, &hash, local_name, size, false)?;
+    let duplicate = blob.local_name != local_name;
+    Ok((blob, duplicate))
+}
+
+/// Calculate the sha256 hash and the size of a local file
+pub fn hash_file(path: &Path) -> io::Result<(String, i64)> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 65_536];
+    let mut size: i64 = 0;
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..n]);
+        size += n as i64;
+    }
+
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+/// Count the files which aren't linked to a blob yet
+pub fn count_unlinked_files(db: &DbConnection) -> Result<i64, DieselErr> {
+    files::table
+        .filter(files::blob_id.is_null())
+        .count()
+        .get_result(db)
+}
+
+/// Link all files which don't have a blob yet, to the blob of
+/// their content. Local files of duplicates get removed. Returns
+/// the amount of linked files
+pub fn link_unlinked_files(db: &DbConnection, config: &Config) -> Result<usize, RestError> {
+    let unlinked: Vec<File> = files::table.filter(files::blob_id.is_null()).load(db)?;
+    let mut linked = 0;
+
+    for file in unlinked {
+        let res = db.transaction::<_, RestError, _>(|| {
+            let (blob, duplicate) = store(db, config, &file.local_name)?;
+
+            diesel::update(files::table.find(file.id))
+                .set((
+                    files::blob_id.eq(blob.id),
+                    files::local_name.eq(&blob.local_name),
+                ))
+                .execute(db)?;
+
+            Ok(duplicate)
+        });
+
+        match res {
+            Ok(duplicate) => {
+                if duplicate {
+                    fs::remove_file(
+                        Path::new(&config.server.file_output_path).join(&file.local_name),
+                    )
+                    .ok();
+                }
+
+                linked += 1;
+            }
+            Err(err) => warn!("Couldn't link file {}: {:?}", file.id, err),
+        }
+    }
+
+    Ok(linked)
+}
+
+/// Set the reference count of every blob to the amount of files and
+/// versions actually referencing it. Blobs which aren't referenced at
+/// all are deleted. Returns the amount of fixed blobs and the deleted
+/// ones, whose local files have to be removed by the caller
+pub fn repair_ref_counts(db: &DbConnection) -> Result<(usize, Vec<Blob>), RestError> {
+    db.transaction::<_, RestError, _>(|| {
+        let mut refs: HashMap<i32, i32> = HashMap::new();
+
+        let file_refs: Vec<Option<i32>> = files::table
+            .filter(files::blob_id.is_not_null())
+            .select(files::blob_id)
+            .load(db)?;
+
+        let version_refs: Vec<Option<i32>> = file_versions::table
+            .filter(file_versions::blob_id.is_not_null())
+            .select(file_versions::blob_id)
+            .load(db)?;
+
+        for blob_id in file_refs.into_iter().chain(version_refs).flatten() {
+            *refs.entry(blob_id).or_default() += 1;
+        }
+
+        let mut fixed = 0;
+        let mut deleted = vec![];
+
+        for blob in blobs::table.for_update().load::<Blob>(db)? {
+            let ref_count = refs.get(&blob.id).copied().unwrap_or(0);
+            if ref_count == blob.ref_count {
+                continue;
+            }
+
+            if ref_count == 0 {
+                diesel::delete(&blob).execute(db)?;
+                deleted.push(blob);
+            } else {
+                diesel::update(&blob)
+                    .set(blobs::ref_count.eq(ref_count))
+                    .execute(db)?;
+            }
+
+            fixed += 1;
+        }
+
+        Ok((fixed, deleted))
+    })
+}
+
+/// Wrap the keys of all encrypted files and file versions with
+/// the current master key. Returns the amount of rewrapped keys
+pub fn rotate_keys(db: &DbConnection, keyring: &Keyring) -> Result<usize, RestError> {
+    let wrapped: Vec<(i32, i32, Option<String>)> = files::table
+        .filter(files::wrapped_key.is_not_null())
+        .select((files::id, files::user_id, files::wrapped_key))
+        .load(db)?;
+
+    let mut rotated = 0;
+
+    for (file_id, owner, wrapped_key) in wrapped {
+        let wrapped_key = match rewrap(keyring, wrapped_key, owner) {
+            Ok(Some(wrapped_key)) => wrapped_key,
+            Ok(None) => continue,
+            Err(_) => {
+                warn!("Couldn't unwrap key of file {}", file_id);
+                continue;
+            }
+        };
+
+        diesel::update(files::table.find(file_id))
+            .set(files::wrapped_key.eq(wrapped_key))
+            .execute(db)?;
+
+        rotated += 1;
+    }
+
+    // Versions are owned by the owner of their file
+    let wrapped: Vec<(i32, i32, Option<String>)> = file_versions::table
+        .inner_join(files::table)
+        .filter(file_versions::wrapped_key.is_not_null())
+        .select((
+            file_versions::id,
+            files::user_id,
+            file_versions::wrapped_key,
+        ))
+        .load(db)?;
+
+    for (version_id, owner, wrapped_key) in wrapped {
+        let wrapped_key = match rewrap(keyring, wrapped_key, owner) {
+            Ok(Some(wrapped_key)) => wrapped_key,
+            Ok(None) => continue,
+            Err(_) => {
+                warn!("Couldn't unwrap key of file version {}", version_id);
+                continue;
+            }
+        };
+
+        diesel::update(file_versions::table.find(version_id))
+            .set(file_versions::wrapped_key.eq(wrapped_key))
+            .execute(db)?;
+
+        rotated += 1;
+    }
+
+    Ok(rotated)
+}
+
+/// Wrap a key of 'owner' with the current master key. Returns
+/// None if it's wrapped with the current master key already
+fn rewrap(
+    keyring: &Keyring,
+    wrapped_key: Option<String>,
+    owner: i32,
+) -> Result<Option<String>, RestError> {
+    let wrapped_key = wrapped_key.ok_or(RestError::Internal)?;
+    let (key, current) = keyring.unwrap(&wrapped_key, owner)?;
+
+    Ok(if current {
+        None
+    } else {
+        Some(keyring.wrap(&key, owner))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        file::NewFile,
+        file_version::FileVersion,
+        namespace::{CreateNamespace, Namespace},
+    };
+    use crate::schema::users;
+
+    const OLD_KEY: &str = "0123456789abcdef0123456789abcdef";
+    const NEW_KEY: &str = "fedcba9876543210fedcba9876543210";
+
+    fn config(master_key: &str, previous_master_keys: &[&str]) -> Config {
+        let mut config = Config::default();
+        config.encryption.master_key = Some(base64::encode(master_key));
+        config.encryption.previous_master_keys =
+            previous_master_keys.iter().map(base64::encode).collect();
+        config
+    }
+
+    fn keyring(config: &Config) -> Keyring {
+        Keyring::from_config(config).unwrap().unwrap()
+    }
+
+    /// Create a file of a new user whose key is wrapped using 'keyring'
+    fn encrypted_file(db: &DbConnection, keyring: &Keyring) -> File {
+        let uid: i32 = diesel::insert_into(users::table)
+            .values((users::username.eq("test_blob"), users::password.eq("")))
+            .returning(users::id)
+            .get_result(db)
+            .unwrap();
+        CreateNamespace::new("default", uid).create(db).unwrap();
+        let ns = Namespace::find_by_name(db, "default", uid)
+            .unwrap()
+            .unwrap();
+
+        NewFile {
+            name: "file".to_owned(),
+            user_id: uid,
+            local_name: "test_blob".to_owned(),
+            namespace_id: ns.id,
+            wrapped_key: Some(keyring.wrap(&DataKey::generate(), uid)),
+            ..NewFile::default()
+        }
+        .create(db)
+        .unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs a database"]
+    fn test_rotate_keys() {
+        let old = keyring(&config(OLD_KEY, &[]));
+        let new = keyring(&config(NEW_KEY, &[OLD_KEY]));
+        let rotated = config(NEW_KEY, &[]);
+
+        let db = crate::db::test_connection();
+        db.test_transaction::<_, DieselErr, _>(|| {
+            let file = encrypted_file(&db, &old);
+            let version = FileVersion::create_from(&db, &file)?;
+
+            // Keys of other files might be wrapped with the old master key as well
+            assert!(rotate_keys(&db, &new).unwrap() >= 2);
+            assert_eq!(rotate_keys(&db, &new).unwrap(), 0);
+
+            // The old master key isn't needed anymore
+            let file: File = files::table.find(file.id).get_result(&db)?;
+            assert!(file.data_key(&rotated).is_ok());
+            let version = FileVersion::find(&db, file.id, version.version).unwrap();
+            assert!(version.as_file(&file).data_key(&rotated).is_ok());
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a database"]
+    fn test_data_key_of_version() {
+        let config = config(OLD_KEY, &[]);
+        let keyring = keyring(&config);
+
+        let db = crate::db::test_connection();
+        db.test_transaction::<_, DieselErr, _>(|| {
+            let mut file = encrypted_file(&db, &keyring);
+            let blob = Blob::acquire(&db, "test_data_key_of_version", "test_blob", 0, true)?;
+            file.blob_id = Some(blob.id);
+            FileVersion::create_from(&db, &file)?;
+
+            // The file itself got replaced with other content
+            diesel::update(files::table.find(file.id))
+                .set((
+                    files::blob_id.eq(None::<i32>),
+                    files::wrapped_key.eq(None::<String>),
+                ))
+                .execute(&db)?;
+
+            assert!(blob.data_key(&db, &keyring).is_ok());
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,118 @@
+use crate::response_code::RestError;
+use sha2::{Digest, Sha256};
+
+/// Hex encoded digests of a content
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Digests {
+    pub crc32: String,
+    pub sha256: String,
+    pub blake3: Option<String>,
+}
+
+impl Digests {
+    /// Check the digests against the ones a client expects. Unset
+    /// expectations are ignored, the case of hex digits doesn't matter
+    pub fn verify(&self, sha256: Option<&str>, blake3: Option<&str>) -> Result<(), RestError> {
+        let matches = |actual: Option<&str>, expected: Option<&str>| match expected {
+            Some(expected) => actual
+                .map(|i| i.eq_ignore_ascii_case(expected.trim()))
+                .unwrap_or(false),
+            None => true,
+        };
+
+        if matches(Some(&self.sha256), sha256) && matches(self.blake3.as_deref(), blake3) {
+            Ok(())
+        } else {
+            Err(RestError::DigestMismatch)
+        }
+    }
+}
+
+/// Calculates the crc32, sha256 and optionally
+/// the blake3 digest of a content in a single pass
+pub struct ContentHasher {
+    crc32: crc32fast::Hasher,
+    sha256: Sha256,
+    blake3: Option<blake3::Hasher>,
+}
+
+impl ContentHasher {
+    pub fn new(blake3: bool) -> Self {
+        ContentHasher {
+            crc32: crc32fast::Hasher::new(),
+            sha256: Sha256::new(),
+            blake3: if blake3 {
+                Some(blake3::Hasher::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc32.update(data);
+        self.sha256.update(data);
+
+        if let Some(ref mut blake3) = self.blake3 {
+            blake3.update(data);
+        }
+    }
+
+    pub fn finalize(self) -> Digests {
+        Digests {
+            crc32: format!("{:08x}", self.crc32.finalize()),
+            sha256: format!("{:x}", self.sha256.finalize()),
+            blake3: self.blake3.map(|i| i.finalize().to_hex().to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_hasher() {
+        let mut hasher = ContentHasher::new(true);
+        hasher.update(b"hel");
+        hasher.update(b"lo");
+        let digests = hasher.finalize();
+
+        assert_eq!(digests.crc32, "3610a686");
+        assert_eq!(
+            digests.sha256,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(
+            digests.blake3.as_deref(),
+            Some("ea8f163db38682925e4491c5e58d4bb3506ef8c14eb78a86e908c5624a67200f")
+        );
+
+        assert!(ContentHasher::new(false).finalize().blake3.is_none());
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut hasher = ContentHasher::new(false);
+        hasher.update(b"hello");
+        let digests = hasher.finalize();
+
+        assert!(digests.verify(None, None).is_ok());
+        assert!(digests
+            .verify(
+                Some("2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824"),
+                None
+            )
+            .is_ok());
+        assert_eq!(
+            digests.verify(Some("00"), None),
+            Err(RestError::DigestMismatch)
+        );
+
+        // A digest which wasn't calculated can't match
+        assert_eq!(
+            digests.verify(None, Some("ea8f")),
+            Err(RestError::DigestMismatch)
+        );
+    }
+}
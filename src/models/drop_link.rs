@@ -0,0 +1,189 @@
+use crate::{
+    models::{namespace::Namespace, user::User},
+    response_code::{diesel_option, Origin, RestError},
+    schema::{drop_links, namespaces, users},
+    DbConnection,
+};
+use chrono::prelude::*;
+use diesel::{
+    dsl::sql,
+    prelude::*,
+    result::{DatabaseErrorKind, Error as DieselErr},
+    sql_types::Bool,
+};
+
+/// An upload-only link. Anonymous visitors can upload
+/// files into a namespace of the links owner through it
+#[derive(Identifiable, Queryable, Associations, Debug, Clone)]
+#[belongs_to(User)]
+#[belongs_to(Namespace)]
+pub struct DropLink {
+    pub id: i32,
+    pub user_id: i32,
+    pub namespace_id: i32,
+    pub slug: String,
+    pub label: Option<String>,
+    /// Tags added to each uploaded file
+    pub tags: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Hash of the password protecting the link
+    pub password: Option<String>,
+    pub max_file_size: Option<i64>,
+    pub max_files: Option<i32>,
+    pub uploads: i32,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "drop_links"]
+pub struct NewDropLink {
+    pub user_id: i32,
+    pub namespace_id: i32,
+    pub slug: String,
+    pub label: Option<String>,
+    pub tags: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub password: Option<String>,
+    pub max_file_size: Option<i64>,
+    pub max_files: Option<i32>,
+}
+
+impl NewDropLink {
+    /// Insert the link. Fails if the slug is taken already
+    pub fn create(self, db: &DbConnection) -> Result<DropLink, RestError> {
+        if self.max_file_size.map(|i| i <= 0).unwrap_or(false)
+            || self.max_files.map(|i| i <= 0).unwrap_or(false)
+        {
+            return Err(RestError::BadRequest);
+        }
+
+        diesel::insert_into(drop_links::table)
+            .values(&self)
+            .get_result(db)
+            .map_err(|err| match err {
+                DieselErr::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                    RestError::AlreadyExists
+                }
+                _ => err.into(),
+            })
+    }
+}
+
+impl DropLink {
+    /// Find a usable link by its slug. Expired links
+    /// and links reaching their file limit aren't found
+    pub fn find_by_slug(db: &DbConnection, link_slug: &str) -> Result<DropLink, DieselErr> {
+        use crate::schema::drop_links::dsl::*;
+
+        let link: DropLink = drop_links.filter(slug.eq(link_slug)).first(db)?;
+        if link.is_expired() {
+            return Err(DieselErr::NotFound);
+        }
+
+        Ok(link)
+    }
+
+    /// Find a link owned by 'uid'
+    pub fn find_by_id(db: &DbConnection, link_id: i32, uid: i32) -> Result<DropLink, RestError> {
+        use crate::schema::drop_links::dsl::*;
+
+        drop_links
+            .filter(id.eq(link_id))
+            .filter(user_id.eq(uid))
+            .first(db)
+            .map_err(|i| diesel_option(i, Origin::DropLink))
+    }
+
+    /// List all links of a user with their namespaces and
+    /// the names of the namespaces owners, the oldest first
+    pub fn list(
+        db: &DbConnection,
+        uid: i32,
+    ) -> Result<Vec<(DropLink, Namespace, String)>, DieselErr> {
+        drop_links::table
+            .inner_join(namespaces::table.inner_join(users::table))
+            .filter(drop_links::user_id.eq(uid))
+            .order((drop_links::created_at.asc(), drop_links::id.asc()))
+            .select((
+                drop_links::all_columns,
+                namespaces::all_columns,
+                users::username,
+            ))
+            .load(db)
+    }
+
+    /// Whether the link reached its expiry time or file limit
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.map(|i| i <= Utc::now()).unwrap_or(false)
+            || self.max_files.map(|i| self.uploads >= i).unwrap_or(false)
+    }
+
+    /// Count an upload through the link. Returns false if
+    /// the link expired or reached its file limit
+    pub fn claim_upload(&self, db: &DbConnection) -> Result<bool, DieselErr> {
+        use crate::schema::drop_links::dsl::*;
+
+        // Checked within the update, so concurrent
+        // uploads can't exceed the file limit
+        let claimed = diesel::update(
+            drop_links
+                .filter(id.eq(self.id))
+                .filter(expires_at.is_null().or(expires_at.gt(Utc::now())))
+                .filter(sql::<Bool>("(max_files IS NULL OR uploads < max_files)")),
+        )
+        .set(uploads.eq(uploads + 1))
+        .execute(db)?;
+
+        Ok(claimed > 0)
+    }
+
+    /// Give back an upload claimed for a failed upload
+    pub fn release_upload(&self, db: &DbConnection) -> Result<(), DieselErr> {
+        use crate::schema::drop_links::dsl::*;
+
+        diesel::update(drop_links.filter(id.eq(self.id)).filter(uploads.gt(0)))
+            .set(uploads.eq(uploads - 1))
+            .execute(db)?;
+
+        Ok(())
+    }
+
+    /// Delete the link. Files uploaded through it are kept
+    pub fn revoke(&self, db: &DbConnection) -> Result<(), DieselErr> {
+        diesel::delete(self).execute(db)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_expired() {
+        let mut link = DropLink {
+            id: 1,
+            user_id: 1,
+            namespace_id: 1,
+            slug: "logs".to_string(),
+            label: None,
+            tags: vec![],
+            created_at: Utc::now(),
+            expires_at: None,
+            password: None,
+            max_file_size: None,
+            max_files: Some(2),
+            uploads: 1,
+        };
+        assert!(!link.is_expired());
+
+        link.uploads = 2;
+        assert!(link.is_expired());
+
+        link.max_files = None;
+        assert!(!link.is_expired());
+
+        link.expires_at = Some(Utc::now() - chrono::Duration::seconds(1));
+        assert!(link.is_expired());
+    }
+}
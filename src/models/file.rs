@@ -4,23 +4,38 @@ use crate::{
     models::{self, namespace::Namespace, user::User},
     response_code::{diesel_option, Origin, RestError},
     schema::{self, files},
-    utils::random_string,
+    storage::encryption::{DataKey, Keyring},
     DbConnection,
 };
+use bigdecimal::{BigDecimal, ToPrimitive};
 use chrono::prelude::*;
 use diesel::{
-    dsl::count_star, pg::Pg, prelude::*, result::Error as DieselErr, PgTextExpressionMethods,
+    dsl::{count_star, sql},
+    pg::Pg,
+    prelude::*,
+    result::Error as DieselErr,
+    sql_types::{BigInt, Nullable, Numeric, Text},
+    PgTextExpressionMethods,
 };
+use flate2::read::GzDecoder;
 use humansize::{file_size_opts, FileSize};
+use itertools::Itertools;
 use models::attribute::{
     AttributeType::{Group, Tag},
     NewAttribute,
 };
-use std::{fmt::Display, fs, path::Path};
+use std::{collections::HashMap, fmt::Display, io::Read, path::Path};
 use tar::EntryType;
-use zip::result::ZipError;
-
-use super::attribute::Attribute;
+use zip::{result::ZipError, CompressionMethod};
+
+use super::{
+    attribute::Attribute,
+    blob::Blob,
+    digest::Digests,
+    file_version::FileVersion,
+    namespace_member::MemberRole,
+    share_link::{LinkLimits, NewShareLink, ShareLink},
+};
 
 #[derive(Identifiable, Queryable, Associations, Debug, AsChangeset, Clone)]
 #[belongs_to(User)]
@@ -35,10 +50,19 @@ pub struct File {
     pub file_size: i64,
     pub file_type: String,
     pub is_public: bool,
-    pub public_filename: Option<String>,
     pub namespace_id: i32,
     pub encryption: i32,
     pub checksum: String,
+    pub source_url: Option<String>,
+    pub blob_id: Option<i32>,
+    pub wrapped_key: Option<String>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub corrupted: bool,
+    pub sha256: Option<String>,
+    pub blake3: Option<String>,
+    /// The user who uploaded the file. Files are owned by
+    /// the owner of their namespace, which can differ
+    pub uploader_id: Option<i32>,
 }
 
 impl Display for File {
@@ -58,10 +82,17 @@ impl Default for File {
             file_size: i64::default(),
             file_type: String::default(),
             is_public: bool::default(),
-            public_filename: None,
             namespace_id: i32::default(),
             encryption: i32::default(),
             checksum: String::default(),
+            source_url: None,
+            blob_id: None,
+            wrapped_key: None,
+            deleted_at: None,
+            corrupted: false,
+            sha256: None,
+            blake3: None,
+            uploader_id: None,
         }
     }
 }
@@ -75,10 +106,15 @@ pub struct NewFile {
     pub file_size: i64,
     pub file_type: String,
     pub is_public: bool,
-    pub public_filename: Option<String>,
     pub namespace_id: i32,
     pub encryption: i32,
     pub checksum: String,
+    pub source_url: Option<String>,
+    pub blob_id: Option<i32>,
+    pub wrapped_key: Option<String>,
+    pub sha256: Option<String>,
+    pub blake3: Option<String>,
+    pub uploader_id: Option<i32>,
 }
 
 impl NewFile {
@@ -98,27 +134,41 @@ impl NewFile {
             checksum: self.checksum,
             namespace_id: self.namespace_id,
             encryption: self.encryption,
-            public_filename: self.public_filename,
             is_public: self.is_public,
             file_type: self.file_type,
             file_size: self.file_size,
             local_name: self.local_name,
             uploaded_at: nuploaded_at,
+            source_url: self.source_url,
+            blob_id: self.blob_id,
+            wrapped_key: self.wrapped_key,
+            deleted_at: None,
+            corrupted: false,
+            sha256: self.sha256,
+            blake3: self.blake3,
+            uploader_id: self.uploader_id,
         })
     }
 }
 
 impl File {
-    /// Find a file by its id.
-    /// The user owner has to be passed as well, in order
-    /// To prevent unauthorized access to files
-    pub fn find_by_id(db: &DbConnection, idd: i32, uid: i32) -> Result<File, RestError> {
+    /// Find a file by its id. The user has to have
+    /// at least the 'required' role in its namespace
+    pub fn find_by_id(
+        db: &DbConnection,
+        idd: i32,
+        uid: i32,
+        required: MemberRole,
+    ) -> Result<File, RestError> {
         use crate::schema::files::dsl::*;
-        files
+        let file = files
             .find(idd)
-            .filter(user_id.eq(uid))
+            .filter(deleted_at.is_null())
             .first::<File>(db)
-            .map_err(|i| diesel_option(i, Origin::File))
+            .map_err(|i| diesel_option(i, Origin::File))?;
+
+        file.authorize(db, uid, required, Origin::File)?;
+        Ok(file)
     }
 
     /// Get the count of files which can
@@ -128,6 +178,7 @@ impl File {
 
         files
             .filter(name.eq(f_name).and(namespace_id.eq(ns)))
+            .filter(deleted_at.is_null())
             .select(count_star())
             .first(db)
             .map_err(|i| i.into())
@@ -138,9 +189,17 @@ impl File {
         use crate::schema::files::dsl::*;
         files
             .filter(name.eq(f_name).and(namespace_id.eq(ns)))
+            .filter(deleted_at.is_null())
             .first::<File>(db)
     }
 
+    /// Find a file by its id and lock its row
+    /// until the current transaction ends
+    pub fn find_for_update(db: &DbConnection, idd: i32) -> Result<File, DieselErr> {
+        use crate::schema::files::dsl::*;
+        files.find(idd).for_update().first::<File>(db)
+    }
+
     /// Saves an existing file
     pub fn save(&self, db: &DbConnection) -> Result<(), diesel::result::Error> {
         use crate::schema::files::dsl::*;
@@ -151,28 +210,131 @@ impl File {
         Ok(())
     }
 
+    /// Take over the digests of new content
+    pub fn set_digests(&mut self, digests: Digests) {
+        self.checksum = digests.crc32;
+        self.sha256 = Some(digests.sha256);
+        self.blake3 = digests.blake3;
+    }
+
+    /// Ensure the user has at least the 'required' role in the
+    /// namespace of the file. See 'Namespace::authorize'
+    pub fn authorize(
+        &self,
+        db: &DbConnection,
+        uid: i32,
+        required: MemberRole,
+        origin: Origin,
+    ) -> Result<(), RestError> {
+        // Files are owned by the owner of their namespace
+        if self.user_id != uid {
+            self.namespace(db)?.authorize(db, uid, required, origin)?;
+        }
+
+        Ok(())
+    }
+
     /// Get the namespace of the file
     pub fn namespace(&self, db: &DbConnection) -> Result<Namespace, RestError> {
         Namespace::find_by_id(db, self.namespace_id)
     }
 
-    /// Delete the file
-    pub fn delete(&self, db: &DbConnection, config: &Config) -> Result<(), RestError> {
-        // TODO shredder file
+    /// Get the key of the files content. Returns
+    /// None if the content isn't encrypted
+    pub fn data_key(&self, config: &Config) -> Result<Option<DataKey>, RestError> {
+        let wrapped_key = match self.wrapped_key {
+            Some(ref wrapped_key) => wrapped_key,
+            None => return Ok(None),
+        };
 
-        // We need to delete associations first
-        // otherwise db relations errors will occur
-        attributes::delete_file_associations(db, self.id)?;
+        let keyring = match Keyring::from_config(config) {
+            Ok(Some(keyring)) => keyring,
+            _ => {
+                warn!("File {} is encrypted but no master key is set", self.id);
+                return Err(RestError::Internal);
+            }
+        };
 
-        // rm from DB
-        diesel::delete(self).execute(db)?;
+        Ok(Some(keyring.unwrap(wrapped_key, self.user_id)?.0))
+    }
 
-        // Delete local file. Ignore errors
-        fs::remove_file(Path::new(&config.server.file_output_path).join(&self.local_name)).ok();
+    /// Move the file into the trash
+    pub fn trash(&self, db: &DbConnection) -> Result<(), DieselErr> {
+        use crate::schema::files::dsl::*;
+        diesel::update(files.find(self.id))
+            .set(deleted_at.eq(Some(Utc::now())))
+            .execute(db)?;
+        Ok(())
+    }
 
+    /// Restore the file from the trash
+    pub fn restore(&self, db: &DbConnection) -> Result<(), DieselErr> {
+        use crate::schema::files::dsl::*;
+        diesel::update(files.find(self.id))
+            .set(deleted_at.eq(None::<DateTime<Utc>>))
+            .execute(db)?;
         Ok(())
     }
 
+    /// List the files in the trash of a user along with their namespaces
+    pub fn list_trash(db: &DbConnection, uid: i32) -> Result<Vec<(File, Namespace)>, DieselErr> {
+        use crate::schema::files::dsl::*;
+        files
+            .inner_join(schema::namespaces::table)
+            .filter(user_id.eq(uid))
+            .filter(deleted_at.is_not_null())
+            .order(deleted_at.desc())
+            .load(db)
+    }
+
+    /// Find files in the trash of a user. All
+    /// trashed files are returned if 'ids' is None
+    pub fn find_trashed(
+        db: &DbConnection,
+        uid: i32,
+        ids: Option<&[i32]>,
+    ) -> Result<Vec<File>, DieselErr> {
+        use crate::schema::files::dsl::*;
+        let mut query = files
+            .filter(user_id.eq(uid))
+            .filter(deleted_at.is_not_null())
+            .into_boxed();
+
+        if let Some(ids) = ids {
+            query = query.filter(id.eq_any(ids));
+        }
+
+        query.load(db)
+    }
+
+    /// Delete the file along with its versions. Returns the names of stored
+    /// objects which aren't referenced anymore and have to be deleted by the caller
+    pub fn delete(&self, db: &DbConnection) -> Result<Vec<String>, RestError> {
+        // TODO shredder file
+
+        db.transaction::<_, RestError, _>(|| {
+            // We need to delete associations first
+            // otherwise db relations errors will occur
+            attributes::delete_file_associations(db, self.id)?;
+
+            let mut orphaned = vec![];
+            for version in FileVersion::list(db, self.id)? {
+                orphaned.extend(version.delete(db)?);
+            }
+
+            // rm from DB
+            diesel::delete(self).execute(db)?;
+
+            // The content might still be used by other files
+            orphaned.extend(match self.blob_id {
+                Some(blob_id) => Blob::release(db, blob_id)?.map(|i| i.local_name),
+                None => Some(self.local_name.clone()),
+            });
+
+            Ok(orphaned)
+        })
+    }
+
     /// Search for a file. Unset values are ignored
     pub fn find(&self, db: &DbConnection, ignore_ns: bool) -> Result<Vec<File>, RestError> {
         use crate::schema::files::dsl::*;
@@ -182,7 +344,8 @@ impl File {
             } else {
                 files.filter(name.ilike(&self.name)).into_boxed()
             }
-        };
+        }
+        .filter(deleted_at.is_null());
 
         // Ensure only to select files which
         // are in some way associated with a user
@@ -195,30 +358,18 @@ impl File {
         query.load::<File>(db).map_err(|i| i.into())
     }
 
-    /// Make a file public. If an empty name is provided, a random
-    /// one will be generated. The file must be mutable in order
-    /// to set the public_filename field to the new public name
-    pub fn publish(&mut self, db: &DbConnection, pub_name: &str) -> Result<(), RestError> {
-        // check whether the public name already exists
-        use crate::schema::files::dsl::*;
-        if files
-            .filter(public_filename.eq(pub_name))
-            .first::<File>(db)
-            .is_ok()
-        {
-            return Err(RestError::AlreadyExists);
-        }
-
-        // Select proper public name
-        self.public_filename = if pub_name.is_empty() {
-            Some(random_string(25))
-        } else {
-            Some(pub_name.to_string())
-        };
-
-        self.is_public = true;
-        self.save(db)?;
-        Ok(())
+    /// Share the file using a new public link. If an empty slug is
+    /// provided, a random one will be generated. 'password' is the
+    /// hash of the password protecting the link
+    pub fn publish(
+        &self,
+        db: &DbConnection,
+        slug: &str,
+        label: Option<String>,
+        limits: LinkLimits,
+        password: Option<String>,
+    ) -> Result<ShareLink, RestError> {
+        NewShareLink::new(self.id, slug, label, limits, password).create(db)
     }
 
     /// Add a set of attributes to the file.
@@ -268,6 +419,17 @@ impl File {
     ) -> Result<Vec<(File, Namespace, Vec<Attribute>)>, RestError> {
         use crate::schema::files::dsl::*;
 
+        // Always filter by the namespaces the user can access
+        let ns_ids = if filter.all_namespaces {
+            Namespace::accessible_ids(db, user.id, MemberRole::Viewer)?
+        } else {
+            let ns = Namespace::find_by_path(db, &filter.attributes.namespace, user.id)?
+                .ok_or(RestError::DNotFound(Origin::Namespace))?;
+            ns.authorize(db, user.id, MemberRole::Viewer, Origin::Namespace)?;
+
+            vec![ns.id]
+        };
+
         let mut query = files
             // Join namespaces
             .left_join(schema::file_attributes::table)
@@ -276,29 +438,15 @@ impl File {
                     .on(schema::attributes::id.eq(schema::file_attributes::attribute_id)),
             )
             .inner_join(schema::namespaces::table)
-            // Always filter by user_id
-            .filter(user_id.eq(user.id))
+            .filter(namespace_id.eq_any(&ns_ids))
+            .filter(deleted_at.is_null())
             .into_boxed::<Pg>();
 
-        let mut ns_id: Option<i32> = None;
-
-        // Apply namespace filter
-        if !filter.all_namespaces {
-            let ns = Namespace::find_by_name(db, &filter.attributes.namespace, user.id)?
-                .ok_or(RestError::DNotFound(Origin::Namespace))?;
-
-            ns_id = Some(ns.id);
-
-            query = query.filter(namespace_id.eq(ns.id));
-        }
-
         // Apply name filter
         if !filter.name.is_empty() {
             query = query.filter(name.ilike(&filter.name));
         }
 
-        use itertools::Itertools;
-
         let result = query.load::<(
             File,
             Option<models::file::attributes::FileAttribute>,
@@ -307,27 +455,26 @@ impl File {
         )>(db)?;
 
         // Apply attribute filter
-        let attrs_to_filter = if filter.attributes.groups.is_some()
-            || filter.attributes.tags.is_some()
-        {
-            let tags = if let Some(ref tags) = filter.attributes.tags {
-                NewAttribute::find_multi_by_name(&db, &tags, Tag, user.id, ns_id.unwrap_or(-1))?
-            } else {
-                vec![]
-            };
+        let attrs_to_filter =
+            if filter.attributes.groups.is_some() || filter.attributes.tags.is_some() {
+                let tags = if let Some(ref tags) = filter.attributes.tags {
+                    NewAttribute::find_in_namespaces(&db, &tags, Tag, &ns_ids)?
+                } else {
+                    vec![]
+                };
 
-            // Get and create groups
-            let groups = if let Some(ref groups) = filter.attributes.groups {
-                NewAttribute::find_multi_by_name(&db, &groups, Group, user.id, ns_id.unwrap_or(-1))?
+                // Get and create groups
+                let groups = if let Some(ref groups) = filter.attributes.groups {
+                    NewAttribute::find_in_namespaces(&db, &groups, Group, &ns_ids)?
+                } else {
+                    vec![]
+                };
+
+                [tags, groups].concat().iter().map(|i| i.id).collect_vec()
             } else {
                 vec![]
             };
 
-            [tags, groups].concat().iter().map(|i| i.id).collect_vec()
-        } else {
-            vec![]
-        };
-
         // Collect multiple files with same ID into one, with multiple all attributes
         let res: Vec<(File, Namespace, Vec<Attribute>)> = result
             .into_iter()
@@ -365,35 +512,108 @@ impl File {
         Ok(res)
     }
 
-    /// Get a public file
-    pub fn get_public_file(db: &DbConnection, public_name: &str) -> Result<File, DieselErr> {
-        use schema::files::dsl::*;
-        files
-            .filter(public_filename.eq(public_name).and(is_public))
-            .limit(1)
-            .get_result(db)
+    /// Get the files a user can access which aren't in the trash, along with their
+    /// namespace and attributes. Only files with one of 'ids' or within
+    /// 'namespace' are returned if set
+    pub fn list_with_attributes(
+        db: &DbConnection,
+        uid: i32,
+        ids: Option<&[i32]>,
+        namespace: Option<i32>,
+    ) -> Result<Vec<(File, Namespace, Vec<Attribute>)>, DieselErr> {
+        let ns_ids = Namespace::accessible_ids(db, uid, MemberRole::Viewer)?;
+        let mut query = files::table
+            .inner_join(schema::namespaces::table)
+            .filter(files::namespace_id.eq_any(ns_ids))
+            .filter(files::deleted_at.is_null())
+            .order(files::id)
+            .into_boxed::<Pg>();
+
+        if let Some(ids) = ids {
+            query = query.filter(files::id.eq_any(ids));
+        }
+
+        if let Some(namespace) = namespace {
+            query = query.filter(files::namespace_id.eq(namespace));
+        }
+
+        let found: Vec<(File, Namespace)> = query.load(db)?;
+        let file_ids: Vec<i32> = found.iter().map(|i| i.0.id).collect();
+
+        let mut attributes: HashMap<i32, Vec<Attribute>> = HashMap::new();
+        schema::file_attributes::table
+            .inner_join(schema::attributes::table)
+            .filter(schema::file_attributes::file_id.eq_any(&file_ids))
+            .select((
+                schema::file_attributes::file_id,
+                schema::attributes::all_columns,
+            ))
+            .load::<(i32, Attribute)>(db)?
+            .into_iter()
+            .for_each(|(fid, attribute)| attributes.entry(fid).or_default().push(attribute));
+
+        Ok(found
+            .into_iter()
+            .map(|(file, namespace)| {
+                let attributes = attributes.remove(&file.id).unwrap_or_default();
+                (file, namespace, attributes)
+            })
+            .collect())
     }
 
-    /// If the provided file is in a suppoted archive format, retrieve its archive metadata
-    pub fn get_archive_metadata(&self, config: &Config) -> Result<Metadata, RestError> {
+    /// Get a public file by the slug of one of its links. Expired
+    /// links and links of unpublished files are treated as not found
+    pub fn get_public_file(db: &DbConnection, slug: &str) -> Result<(File, ShareLink), DieselErr> {
+        let (link, file): (ShareLink, File) = schema::share_links::table
+            .inner_join(schema::files::table)
+            .filter(schema::share_links::slug.eq(slug))
+            .filter(schema::files::is_public)
+            .filter(schema::files::deleted_at.is_null())
+            .get_result(db)?;
+
+        if link.is_expired() {
+            return Err(DieselErr::NotFound);
+        }
+
+        Ok((file, link))
+    }
+
+    /// If the provided file is in a suppoted archive format, retrieve its archive
+    /// metadata. 'path' has to point to a local copy of the files content
+    pub fn get_archive_metadata(&self, path: &Path) -> Result<Metadata, RestError> {
         let content_type = self.file_type.as_str();
         Ok(match content_type {
             "application/x-tar" => Metadata {
                 archive_type: String::from("Tar"),
-                files: self.get_tar_metadata(config)?,
+                files: self.get_tar_metadata(path)?,
             },
             "application/zip" => Metadata {
                 archive_type: String::from("Zip"),
-                files: self.get_zip_metadata(config)?,
+                files: self.get_zip_metadata(path)?,
             },
             _ => return Err(RestError::NotAllowed),
         })
     }
 
+    /// Find the member 'name' of an archive file. 'path' has
+    /// to point to a local copy of the files content
+    pub fn find_archive_entry(&self, path: &Path, name: &str) -> Result<ArchiveEntry, RestError> {
+        let name = normalize_entry_path(name);
+
+        match self.file_type.as_str() {
+            "application/x-tar" => find_tar_entry(std::fs::File::open(path)?, &name, false),
+            "application/gzip" => {
+                let decoder = GzDecoder::new(std::fs::File::open(path)?);
+                find_tar_entry(decoder, &name, true)
+            }
+            "application/zip" => find_zip_entry(path, &name),
+            _ => Err(RestError::NotAllowed),
+        }
+    }
+
     /// Get a tar files archives metadata
-    fn get_tar_metadata(&self, config: &Config) -> Result<Vec<ArchiveFile>, RestError> {
-        let file =
-            std::fs::File::open(Path::new(&config.server.file_output_path).join(&self.local_name))?;
+    fn get_tar_metadata(&self, path: &Path) -> Result<Vec<ArchiveFile>, RestError> {
+        let file = std::fs::File::open(path)?;
         let mut t = tar::Archive::new(file);
 
         let e = t
@@ -419,9 +639,8 @@ impl File {
     }
 
     /// Get a zip files archives metadata
-    fn get_zip_metadata(&self, config: &Config) -> Result<Vec<ArchiveFile>, RestError> {
-        let file =
-            std::fs::File::open(Path::new(&config.server.file_output_path).join(&self.local_name))?;
+    fn get_zip_metadata(&self, path: &Path) -> Result<Vec<ArchiveFile>, RestError> {
+        let file = std::fs::File::open(path)?;
         let mut z = zip::ZipArchive::new(file)?;
 
         let e = (0..z.len())
@@ -468,11 +687,193 @@ impl ArchiveFile {
     }
 }
 
+/// Location of an archive member within the archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    pub name: String,
+    /// Position of the members data
+    pub offset: u64,
+    /// Length of the members data within the archive
+    pub length: u64,
+    /// Size of the decompressed content
+    pub size: u64,
+    /// The data is deflate compressed
+    pub deflated: bool,
+    /// The offset refers to the decompressed content of a gzipped archive
+    pub gzipped: bool,
+    pub crc32: Option<u32>,
+}
+
+impl ArchiveEntry {
+    /// The last component of the members path
+    pub fn file_name(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or_default()
+    }
+}
+
+/// Remove empty and '.' components from the path of an archive member
+fn normalize_entry_path(path: &str) -> String {
+    path.split('/')
+        .filter(|i| !i.is_empty() && *i != ".")
+        .join("/")
+}
+
+fn find_tar_entry<R: Read>(
+    reader: R,
+    name: &str,
+    gzipped: bool,
+) -> Result<ArchiveEntry, RestError> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let entry_name = match entry.path()?.to_str() {
+            Some(entry_name) => normalize_entry_path(entry_name),
+            None => continue,
+        };
+
+        if entry_name != name {
+            continue;
+        }
+
+        let header = entry.header();
+        if !matches!(
+            header.entry_type(),
+            EntryType::Regular | EntryType::Continuous
+        ) {
+            return Err(RestError::IllegalOperation);
+        }
+
+        return Ok(ArchiveEntry {
+            name: entry_name,
+            offset: entry.raw_file_position(),
+            length: header.size()?,
+            size: header.size()?,
+            deflated: false,
+            gzipped,
+            crc32: None,
+        });
+    }
+
+    Err(RestError::DNotFound(Origin::File))
+}
+
+fn find_zip_entry(path: &Path, name: &str) -> Result<ArchiveEntry, RestError> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+
+    let raw_name = archive
+        .file_names()
+        .find(|i| normalize_entry_path(i) == name)
+        .map(|i| i.to_string())
+        .ok_or(RestError::DNotFound(Origin::File))?;
+
+    // Fails for encrypted members
+    let entry = archive.by_name(&raw_name)?;
+    if entry.is_dir() {
+        return Err(RestError::IllegalOperation);
+    }
+
+    let deflated = match entry.compression() {
+        CompressionMethod::Stored => false,
+        CompressionMethod::Deflated => true,
+        _ => return Err(RestError::NotAllowed),
+    };
+
+    Ok(ArchiveEntry {
+        name: name.to_string(),
+        offset: entry.data_start(),
+        length: entry.compressed_size(),
+        size: entry.size(),
+        deflated,
+        gzipped: false,
+        crc32: Some(entry.crc32()),
+    })
+}
+
 /// Get a files size human readabe
 pub fn file_size_humanized(size: u64) -> String {
     size.file_size(file_size_opts::CONVENTIONAL).unwrap()
 }
 
+/// Delete all files which have been in the trash longer than the retention
+/// period. Returns the names of stored objects which aren't referenced anymore
+pub fn purge_expired(db: &DbConnection, config: &Config) -> Result<Vec<String>, RestError> {
+    use crate::schema::files::dsl::*;
+
+    let expired: Vec<File> = files
+        .filter(deleted_at.lt(Utc::now() - config.trash_retention()))
+        .load(db)?;
+
+    let mut orphaned = vec![];
+    for file in expired {
+        orphaned.extend(file.delete(db)?);
+    }
+
+    Ok(orphaned)
+}
+
+/// Mark files as corrupted or intact. Files are given by their id and the
+/// object they were checked with, so files whose content was replaced in
+/// the meantime aren't changed. Returns the amount of changed files
+pub fn set_corrupted(
+    db: &DbConnection,
+    checked: &[(i32, String)],
+    value: bool,
+) -> Result<usize, DieselErr> {
+    use crate::schema::files::dsl::*;
+
+    db.transaction(|| {
+        let mut changed = 0;
+
+        for (file_id, object) in checked {
+            changed += diesel::update(
+                files.filter(
+                    id.eq(file_id)
+                        .and(local_name.eq(object))
+                        .and(corrupted.ne(value)),
+                ),
+            )
+            .set(corrupted.eq(value))
+            .execute(db)?;
+        }
+
+        Ok(changed)
+    })
+}
+
+/// Get the largest files of a namespace, not including files in the trash
+pub fn largest(db: &DbConnection, ns: i32, limit: i64) -> Result<Vec<File>, DieselErr> {
+    use crate::schema::files::dsl::*;
+
+    files
+        .filter(namespace_id.eq(ns))
+        .filter(deleted_at.is_null())
+        .order((file_size.desc(), id))
+        .limit(limit)
+        .load(db)
+}
+
+/// Get the amount and total size of the files of
+/// a namespace grouped by their mime type, the
+/// most used type first
+pub fn type_distribution(db: &DbConnection, ns: i32) -> Result<Vec<(String, i64, i64)>, DieselErr> {
+    use crate::schema::files::dsl::*;
+
+    let res: Vec<(String, i64, Option<BigDecimal>)> = files
+        .select(sql::<(Text, BigInt, Nullable<Numeric>)>(
+            "file_type, COUNT(*), SUM(file_size)",
+        ))
+        .filter(namespace_id.eq(ns))
+        .group_by(file_type)
+        .order(sql::<BigInt>("COUNT(*) DESC"))
+        .load(db)?;
+
+    Ok(res
+        .into_iter()
+        .map(|(mime, count, size)| (mime, count, size.and_then(|i| i.to_i64()).unwrap_or(0)))
+        .collect())
+}
+
 #[allow(clippy::from_over_into)]
 impl Into<NewFile> for File {
     fn into(self) -> NewFile {
@@ -483,10 +884,15 @@ impl Into<NewFile> for File {
             file_size: self.file_size,
             file_type: self.file_type,
             is_public: self.is_public,
-            public_filename: self.public_filename,
             namespace_id: self.namespace_id,
             encryption: self.encryption,
             checksum: self.checksum,
+            source_url: self.source_url,
+            blob_id: self.blob_id,
+            wrapped_key: self.wrapped_key,
+            sha256: self.sha256,
+            blake3: self.blake3,
+            uploader_id: self.uploader_id,
         }
     }
 }
@@ -607,3 +1013,88 @@ pub mod attributes {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    fn read_range(path: &Path, entry: &ArchiveEntry) -> Vec<u8> {
+        let mut file = std::fs::File::open(path).unwrap();
+        file.seek(SeekFrom::Start(entry.offset)).unwrap();
+        let mut data = vec![0; entry.length as usize];
+        file.read_exact(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_find_archive_entry() {
+        let dir = std::env::temp_dir().join(crate::utils::random_string(10));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Zip with a stored member in a nested directory
+        let zip_path = dir.join("test.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        let options =
+            zip::write::FileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.add_directory("a/b/", options).unwrap();
+        writer.start_file("a/b/c.txt", options).unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.finish().unwrap();
+
+        let mut file = File {
+            file_type: "application/zip".to_string(),
+            ..File::default()
+        };
+
+        let entry = file.find_archive_entry(&zip_path, "/a/./b/c.txt").unwrap();
+        assert_eq!(entry.file_name(), "c.txt");
+        assert_eq!(entry.size, 5);
+        assert!(!entry.deflated);
+        assert_eq!(read_range(&zip_path, &entry), b"hello");
+
+        assert_eq!(
+            file.find_archive_entry(&zip_path, "a/b"),
+            Err(RestError::IllegalOperation)
+        );
+        assert_eq!(
+            file.find_archive_entry(&zip_path, "c.txt"),
+            Err(RestError::DNotFound(Origin::File))
+        );
+
+        // Tar with the same layout
+        let tar_path = dir.join("test.tar");
+        let mut builder = tar::Builder::new(std::fs::File::create(&tar_path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "a/b/c.txt", &b"hello"[..])
+            .unwrap();
+        builder.finish().unwrap();
+
+        file.file_type = "application/x-tar".to_string();
+        let entry = file.find_archive_entry(&tar_path, "a/b/c.txt").unwrap();
+        assert_eq!(read_range(&tar_path, &entry), b"hello");
+
+        // Offsets of gzipped tar members refer to the decompressed archive
+        let tar_gz_path = dir.join("test.tar.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&tar_gz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder
+            .write_all(&std::fs::read(&tar_path).unwrap())
+            .unwrap();
+        encoder.finish().unwrap();
+
+        file.file_type = "application/gzip".to_string();
+        let gz_entry = file.find_archive_entry(&tar_gz_path, "a/b/c.txt").unwrap();
+        assert!(gz_entry.gzipped);
+        assert_eq!(gz_entry.offset, entry.offset);
+        assert_eq!(gz_entry.length, 5);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{
+    models::{blob::Blob, file::File},
+    response_code::{diesel_option, Origin, RestError},
+    schema::file_versions,
+    DbConnection,
+};
+use chrono::prelude::*;
+use diesel::{dsl, prelude::*, result::Error as DieselErr};
+
+/// A previous content of a file. Versions
+/// are created whenever a file gets replaced
+#[derive(Identifiable, Queryable, Associations, Debug, Clone)]
+#[belongs_to(File)]
+pub struct FileVersion {
+    pub id: i32,
+    pub file_id: i32,
+    pub version: i32,
+    pub blob_id: Option<i32>,
+    pub local_name: String,
+    pub file_size: i64,
+    pub file_type: String,
+    pub checksum: String,
+    pub wrapped_key: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub sha256: Option<String>,
+    pub blake3: Option<String>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "file_versions"]
+pub struct NewFileVersion<'a> {
+    pub file_id: i32,
+    pub version: i32,
+    pub blob_id: Option<i32>,
+    pub local_name: &'a str,
+    pub file_size: i64,
+    pub file_type: &'a str,
+    pub checksum: &'a str,
+    pub wrapped_key: Option<&'a str>,
+    pub created_at: DateTime<Utc>,
+    pub sha256: Option<&'a str>,
+    pub blake3: Option<&'a str>,
+}
+
+impl FileVersion {
+    /// Keep the current content of a file as its next version. The
+    /// reference of the file to its blob is taken over by the version
+    pub fn create_from(db: &DbConnection, file: &File) -> Result<FileVersion, DieselErr> {
+        use crate::schema::file_versions::dsl::*;
+
+        let latest: Option<i32> = file_versions
+            .filter(file_id.eq(file.id))
+            .select(dsl::max(version))
+            .first(db)?;
+
+        diesel::insert_into(file_versions)
+            .values(&NewFileVersion {
+                file_id: file.id,
+                version: latest.unwrap_or_default() + 1,
+                blob_id: file.blob_id,
+                local_name: &file.local_name,
+                file_size: file.file_size,
+                file_type: &file.file_type,
+                checksum: &file.checksum,
+                wrapped_key: file.wrapped_key.as_deref(),
+                created_at: file.uploaded_at,
+                sha256: file.sha256.as_deref(),
+                blake3: file.blake3.as_deref(),
+            })
+            .get_result(db)
+    }
+
+    /// List all versions of a file, the latest first
+    pub fn list(db: &DbConnection, fid: i32) -> Result<Vec<FileVersion>, DieselErr> {
+        use crate::schema::file_versions::dsl::*;
+
+        file_versions
+            .filter(file_id.eq(fid))
+            .order(version.desc())
+            .load(db)
+    }
+
+    /// Find a version of a file
+    pub fn find(db: &DbConnection, fid: i32, q_version: i32) -> Result<FileVersion, RestError> {
+        use crate::schema::file_versions::dsl::*;
+
+        file_versions
+            .filter(file_id.eq(fid).and(version.eq(q_version)))
+            .first(db)
+            .map_err(|i| diesel_option(i, Origin::FileVersion))
+    }
+
+    /// Delete the version. Returns the name of its stored object if
+    /// it isn't referenced anymore and has to be deleted by the caller
+    pub fn delete(&self, db: &DbConnection) -> Result<Option<String>, DieselErr> {
+        diesel::delete(self).execute(db)?;
+
+        Ok(match self.blob_id {
+            Some(blob_id) => Blob::release(db, blob_id)?.map(|i| i.local_name),
+            None => Some(self.local_name.clone()),
+        })
+    }
+
+    /// Get the file with the content of this version
+    pub fn as_file(&self, file: &File) -> File {
+        File {
+            local_name: self.local_name.clone(),
+            file_size: self.file_size,
+            file_type: self.file_type.clone(),
+            checksum: self.checksum.clone(),
+            blob_id: self.blob_id,
+            wrapped_key: self.wrapped_key.clone(),
+            uploaded_at: self.created_at,
+            sha256: self.sha256.clone(),
+            blake3: self.blake3.clone(),
+            ..file.clone()
+        }
+    }
+}
+
+/// Make the content of a version the current content of its
+/// file. The replaced content is kept as a new version
+pub fn restore(db: &DbConnection, file: &File, q_version: i32) -> Result<File, RestError> {
+    db.transaction::<_, RestError, _>(|| {
+        let current = File::find_for_update(db, file.id)?;
+        let version = FileVersion::find(db, file.id, q_version)?;
+
+        // The file needs its own reference to the content
+        let blob_id = version.blob_id.ok_or(RestError::IllegalOperation)?;
+        Blob::retain(db, blob_id)?;
+
+        FileVersion::create_from(db, &current)?;
+
+        let mut restored = version.as_file(&current);
+        // Restoring changes the content and invalidates cached copies
+        restored.uploaded_at = Utc::now();
+        restored.corrupted = false;
+        restored.save(db)?;
+
+        Ok(restored)
+    })
+}
+
+/// Delete versions of a file exceeding the latest 'keep' ones or being
+/// older than 'before'. Returns the deleted version numbers and the names
+/// of stored objects which aren't referenced anymore
+pub fn prune(
+    db: &DbConnection,
+    fid: i32,
+    keep: Option<usize>,
+    before: Option<DateTime<Utc>>,
+) -> Result<(Vec<i32>, Vec<String>), RestError> {
+    db.transaction::<_, RestError, _>(|| {
+        let mut pruned = vec![];
+        let mut orphaned = vec![];
+
+        for (i, version) in FileVersion::list(db, fid)?.into_iter().enumerate() {
+            let exceeds_count = keep.map(|keep| i >= keep).unwrap_or(false);
+            let too_old = before.map(|i| version.created_at < i).unwrap_or(false);
+
+            if exceeds_count || too_old {
+                orphaned.extend(version.delete(db)?);
+                pruned.push(version.version);
+            }
+        }
+
+        Ok((pruned, orphaned))
+    })
+}
@@ -1,14 +1,19 @@
 use crate::schema::*;
-use crate::{models::user::User, schema::users, DbConnection};
+use crate::{config::Config, models::user::User, schema::users, DbConnection};
+use chrono::prelude::*;
 use diesel::{prelude::*, result::Error};
 
-#[derive(Queryable)]
+#[derive(Identifiable, Queryable, Associations, Debug, Clone)]
+#[belongs_to(User)]
 pub struct LoginSession {
     pub id: i32,
     pub user_id: i32,
-    pub token: i32,
+    pub token: String,
     pub requests: i64,
     pub machine_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
 }
 
 #[derive(Insertable)]
@@ -17,21 +22,77 @@ pub struct NewLoginSession {
     pub user_id: i32,
     pub token: String,
     pub machine_id: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl LoginSession {
+    /// Returns true if the session can't be used anymore
+    pub fn is_expired(&self, config: &Config) -> bool {
+        let now = Utc::now();
+        self.expires_at < now || self.last_used + config.session_idle_timeout() < now
+    }
+
+    /// List all sessions of a user, the last used first
+    pub fn list(db: &DbConnection, uid: i32) -> Result<Vec<LoginSession>, Error> {
+        use crate::schema::login_sessions::dsl::*;
+
+        login_sessions
+            .filter(user_id.eq(uid))
+            .order(last_used.desc())
+            .load(db)
+    }
+
+    /// Delete the session with the given token
+    pub fn delete_by_token(db: &DbConnection, q_token: &str) -> Result<(), Error> {
+        use crate::schema::login_sessions::dsl::*;
+
+        diesel::delete(login_sessions.filter(token.eq(q_token))).execute(db)?;
+        Ok(())
+    }
+
+    /// Delete a session of a user. Returns
+    /// false if no such session exists
+    pub fn revoke(db: &DbConnection, uid: i32, session_id: i32) -> Result<bool, Error> {
+        use crate::schema::login_sessions::dsl::*;
+
+        let deleted = diesel::delete(login_sessions.filter(id.eq(session_id).and(user_id.eq(uid))))
+            .execute(db)?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Delete all sessions of a user. Returns the amount of deleted sessions
+    pub fn revoke_all(db: &DbConnection, uid: i32) -> Result<usize, Error> {
+        use crate::schema::login_sessions::dsl::*;
+
+        diesel::delete(login_sessions.filter(user_id.eq(uid))).execute(db)
+    }
+
+    /// Delete all sessions of a user except the one with the
+    /// given token. Returns the amount of deleted sessions
+    pub fn revoke_others(db: &DbConnection, uid: i32, q_token: &str) -> Result<usize, Error> {
+        use crate::schema::login_sessions::dsl::*;
+
+        diesel::delete(login_sessions.filter(user_id.eq(uid).and(token.ne(q_token)))).execute(db)
+    }
 }
 
-/// Check whether a session exists and retrieve the user
-pub fn find_session(db: &DbConnection, q_token: &str) -> Result<Option<User>, Error> {
+/// Check whether a valid session exists and retrieve the user. Expired
+/// sessions get deleted, valid ones are marked as used
+pub fn find_session(
+    db: &DbConnection,
+    config: &Config,
+    q_token: &str,
+) -> Result<Option<User>, Error> {
     use crate::schema::login_sessions::dsl::*;
 
     // Join login_sessions with users
-    let user: User = match login_sessions
+    let (session, user): (LoginSession, User) = match login_sessions
         .inner_join(users::table)
         .filter(token.eq(q_token))
-        // Select user only
-        .select((users::id, users::username, users::password, users::disabled))
         .first(db)
     {
-        Ok(user) => user,
+        Ok(res) => res,
         Err(dberr) => match dberr {
             // NotFound error means authentication failed
             Error::NotFound => return Ok(None),
@@ -40,5 +101,29 @@ pub fn find_session(db: &DbConnection, q_token: &str) -> Result<Option<User>, Er
         },
     };
 
+    if session.is_expired(config) {
+        diesel::delete(&session).execute(db)?;
+        return Ok(None);
+    }
+
+    diesel::update(&session)
+        .set((last_used.eq(Utc::now()), requests.eq(requests + 1)))
+        .execute(db)?;
+
     Ok(Some(user))
 }
+
+/// Delete all expired sessions and return the amount of removed sessions
+pub fn delete_expired(db: &DbConnection, config: &Config) -> Result<usize, Error> {
+    use crate::schema::login_sessions::dsl::*;
+
+    let now = Utc::now();
+    diesel::delete(
+        login_sessions.filter(
+            expires_at
+                .lt(now)
+                .or(last_used.lt(now - config.session_idle_timeout())),
+        ),
+    )
+    .execute(db)
+}
@@ -1,5 +1,14 @@
 pub mod attribute;
+pub mod blob;
+pub mod digest;
+pub mod drop_link;
 pub mod file;
+pub mod file_version;
 pub mod login_session;
 pub mod namespace;
+pub mod namespace_member;
+pub mod quota;
+pub mod share_link;
+pub mod traffic;
+pub mod upload_session;
 pub mod user;
@@ -1,15 +1,20 @@
 use crate::{
-    config::Config,
     models::user::User,
     response_code::{self, RestError},
 };
 use crate::{schema::*, DbConnection};
+use chrono::Utc;
 use diesel::prelude::*;
 use diesel::result::{Error as DieselErr, Error::NotFound};
 use response_code::Origin;
 use serde::Serialize;
 
-use super::file::File;
+use super::{
+    attribute,
+    file::{attributes, File},
+    namespace_member::{MemberRole, NamespaceMember},
+    quota::Quota,
+};
 
 /// A namespace represents a abstraction between multiple files.
 /// Each namespace, identified by its per user unique name can
@@ -20,6 +25,8 @@ pub struct Namespace {
     pub id: i32,
     pub name: String,
     pub user_id: i32,
+    pub quota_bytes: Option<i64>,
+    pub quota_files: Option<i64>,
 }
 
 #[derive(Insertable)]
@@ -59,6 +66,25 @@ impl Namespace {
         ns_name.to_lowercase() == "default"
     }
 
+    /// The quota of the namespace
+    pub fn quota(&self) -> Quota {
+        Quota {
+            bytes: self.quota_bytes,
+            files: self.quota_files,
+        }
+    }
+
+    /// Set the quota of the namespace
+    pub fn set_quota(&self, db: &DbConnection, quota: Quota) -> Result<(), DieselErr> {
+        use crate::schema::namespaces::dsl::*;
+
+        diesel::update(self)
+            .set((quota_bytes.eq(quota.bytes), quota_files.eq(quota.files)))
+            .execute(db)?;
+
+        Ok(())
+    }
+
     /// Returns true if the namespace is a default namespace
     pub fn is_default(&self) -> bool {
         Namespace::is_default_name(&self.name)
@@ -95,6 +121,100 @@ impl Namespace {
         res.map(Some)
     }
 
+    /// Find a namespace the user has access to. Namespaces
+    /// shared by other users are addressed as 'owner/name'
+    pub fn find_by_path(
+        db: &DbConnection,
+        path: &str,
+        uid: i32,
+    ) -> Result<Option<Namespace>, DieselErr> {
+        if let Some(ns) = Namespace::find_by_name(db, path, uid)? {
+            return Ok(Some(ns));
+        }
+
+        let (owner, ns_name) = match path.split_once('/') {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+
+        namespaces::table
+            .inner_join(users::table)
+            .filter(users::username.eq(owner))
+            .filter(namespaces::name.eq(ns_name))
+            .select(namespaces::all_columns)
+            .first(db)
+            .optional()
+    }
+
+    /// Get the role of a user in the namespace. Returns
+    /// None if the namespace isn't shared with the user
+    pub fn role_of(&self, db: &DbConnection, uid: i32) -> Result<Option<MemberRole>, DieselErr> {
+        if self.user_id == uid {
+            return Ok(Some(MemberRole::Owner));
+        }
+
+        NamespaceMember::find_role(db, self.id, uid)
+    }
+
+    /// Ensure the user has at least the 'required' role in the namespace.
+    /// Users without access get a not found error for 'origin'
+    pub fn authorize(
+        &self,
+        db: &DbConnection,
+        uid: i32,
+        required: MemberRole,
+        origin: Origin,
+    ) -> Result<MemberRole, RestError> {
+        match self.role_of(db, uid)? {
+            Some(role) if role >= required => Ok(role),
+            Some(_) => Err(RestError::Forbidden),
+            None => Err(RestError::DNotFound(origin)),
+        }
+    }
+
+    /// Get the IDs of all namespaces the user
+    /// has at least the 'required' role in
+    pub fn accessible_ids(
+        db: &DbConnection,
+        uid: i32,
+        required: MemberRole,
+    ) -> Result<Vec<i32>, DieselErr> {
+        let mut ids: Vec<i32> = namespaces::table
+            .filter(namespaces::user_id.eq(uid))
+            .select(namespaces::id)
+            .load(db)?;
+
+        ids.extend(
+            namespace_members::table
+                .filter(namespace_members::user_id.eq(uid))
+                .filter(namespace_members::role.ge(required))
+                .select(namespace_members::namespace_id)
+                .load::<i32>(db)?,
+        );
+
+        Ok(ids)
+    }
+
+    /// List the namespaces shared with a user along with the names of their owners
+    pub fn list_shared(db: &DbConnection, uid: i32) -> Result<Vec<(Namespace, String)>, DieselErr> {
+        namespace_members::table
+            .inner_join(namespaces::table.inner_join(users::table))
+            .filter(namespace_members::user_id.eq(uid))
+            .order((users::username, namespaces::name))
+            .select((namespaces::all_columns, users::username))
+            .load(db)
+    }
+
+    /// The name the user addresses the namespace with. 'owner'
+    /// is the name of the user owning the namespace
+    pub fn path_for(&self, uid: i32, owner: &str) -> String {
+        if self.user_id == uid {
+            self.name.clone()
+        } else {
+            format!("{}/{}", owner, self.name)
+        }
+    }
+
     /// List all namespaces of a user
     pub fn list(db: &DbConnection, user: &User) -> Result<Vec<Namespace>, RestError> {
         Namespace::belonging_to(user)
@@ -102,8 +222,9 @@ impl Namespace {
             .map_err(|i| response_code::diesel_option(i, Origin::Namespace))
     }
 
-    /// Delete a namespace
-    pub fn delete(&self, db: &DbConnection, config: &Config) -> Result<(), RestError> {
+    /// Delete a namespace. Its files are moved into the
+    /// trash and get restored into the default namespace
+    pub fn delete(&self, db: &DbConnection) -> Result<(), RestError> {
         use crate::schema::namespaces::dsl::*;
 
         // Don't allow deleting 'default' namespace
@@ -111,17 +232,32 @@ impl Namespace {
             return Err(RestError::IllegalOperation);
         }
 
-        // Delete all namespace assigned files
-        for file in self.files(db)? {
-            file.delete(db, config)?;
-        }
+        let default_ns = Namespace::find_by_name(db, "default", self.user_id)?
+            .ok_or(RestError::DNotFound(Origin::Namespace))?;
 
-        // Delet namespace from database
-        diesel::delete(namespaces)
-            .filter(id.eq(self.id))
-            .execute(db)?;
+        db.transaction::<_, RestError, _>(|| {
+            for file in self.files(db)? {
+                // Attributes only exist within their namespace
+                attributes::delete_file_associations(db, file.id)?;
 
-        Ok(())
+                diesel::update(files::table.find(file.id))
+                    .set((
+                        files::namespace_id.eq(default_ns.id),
+                        files::deleted_at.eq(file.deleted_at.unwrap_or_else(Utc::now)),
+                    ))
+                    .execute(db)?;
+            }
+
+            // Tags and groups can't exist without their namespace
+            attribute::delete_in_namespace(db, self.id)?;
+
+            // Delet namespace from database
+            diesel::delete(namespaces)
+                .filter(id.eq(self.id))
+                .execute(db)?;
+
+            Ok(())
+        })
     }
 
     /// Get a list of all files in the current namespace
@@ -142,3 +278,66 @@ impl Namespace {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        attribute::{AttributeType, NewAttribute},
+        file::NewFile,
+    };
+
+    #[test]
+    #[ignore = "needs a database"]
+    fn test_delete_tagged() {
+        let db = crate::db::test_connection();
+        db.test_transaction::<_, DieselErr, _>(|| {
+            let uid: i32 = diesel::insert_into(users::table)
+                .values((
+                    users::username.eq("test_delete_tagged"),
+                    users::password.eq(""),
+                ))
+                .returning(users::id)
+                .get_result(&db)
+                .unwrap();
+            CreateNamespace::new("default", uid).create(&db).unwrap();
+            CreateNamespace::new("tagged", uid).create(&db).unwrap();
+            let ns = Namespace::find_by_name(&db, "tagged", uid)
+                .unwrap()
+                .unwrap();
+
+            let file = NewFile {
+                name: "file".to_owned(),
+                user_id: uid,
+                local_name: "test_delete_tagged".to_owned(),
+                namespace_id: ns.id,
+                ..NewFile::default()
+            }
+            .create(&db)
+            .unwrap();
+
+            let tag = NewAttribute::new("tag", AttributeType::Tag, uid, ns.id)
+                .create(&db)
+                .unwrap();
+            file.add_attributes(&db, vec![tag]).unwrap();
+
+            // Groups can exist without files
+            NewAttribute::new("group", AttributeType::Group, uid, ns.id)
+                .create(&db)
+                .unwrap();
+
+            ns.delete(&db).unwrap();
+
+            assert!(Namespace::find_by_name(&db, "tagged", uid)
+                .unwrap()
+                .is_none());
+            let left: i64 = crate::schema::attributes::table
+                .filter(crate::schema::attributes::namespace_id.eq(ns.id))
+                .count()
+                .get_result(&db)
+                .unwrap();
+            assert_eq!(left, 0);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{
+    models::{namespace::Namespace, user::User},
+    schema::{namespace_members, users},
+    DbConnection,
+};
+use chrono::prelude::*;
+use diesel::{
+    backend::Backend,
+    deserialize::{self, FromSql},
+    prelude::*,
+    result::Error as DieselErr,
+    serialize::{self, Output, ToSql},
+    sql_types::SmallInt,
+};
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// A user a namespace is shared with
+#[derive(Identifiable, Queryable, Associations, Debug, Clone)]
+#[belongs_to(Namespace)]
+#[belongs_to(User)]
+pub struct NamespaceMember {
+    pub id: i32,
+    pub namespace_id: i32,
+    pub user_id: i32,
+    pub role: MemberRole,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "namespace_members"]
+pub struct NewNamespaceMember {
+    pub namespace_id: i32,
+    pub user_id: i32,
+    pub role: MemberRole,
+}
+
+/// The role of a user within a namespace. Each
+/// role includes the permissions of the lower ones
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[sql_type = "SmallInt"]
+#[serde(rename_all = "lowercase")]
+pub enum MemberRole {
+    /// List and download files
+    Viewer,
+    /// Upload new files
+    Uploader,
+    /// Replace, modify, share and delete files
+    Editor,
+    /// Manage the namespace and its members
+    Owner,
+}
+
+impl NewNamespaceMember {
+    /// Add the member or change the role of an existing one
+    pub fn save(&self, db: &DbConnection) -> Result<NamespaceMember, DieselErr> {
+        use crate::schema::namespace_members::dsl::*;
+
+        diesel::insert_into(namespace_members)
+            .values(self)
+            .on_conflict((namespace_id, user_id))
+            .do_update()
+            .set(role.eq(self.role))
+            .get_result(db)
+    }
+}
+
+impl NamespaceMember {
+    /// List the members of a namespace with
+    /// their names, the oldest members first
+    pub fn list(
+        db: &DbConnection,
+        ns_id: i32,
+    ) -> Result<Vec<(NamespaceMember, String)>, DieselErr> {
+        namespace_members::table
+            .inner_join(users::table)
+            .filter(namespace_members::namespace_id.eq(ns_id))
+            .order((
+                namespace_members::created_at.asc(),
+                namespace_members::id.asc(),
+            ))
+            .select((namespace_members::all_columns, users::username))
+            .load(db)
+    }
+
+    /// Get the role of a user in a namespace. Returns
+    /// None if the user isn't a member of it
+    pub fn find_role(
+        db: &DbConnection,
+        ns_id: i32,
+        uid: i32,
+    ) -> Result<Option<MemberRole>, DieselErr> {
+        use crate::schema::namespace_members::dsl::*;
+
+        namespace_members
+            .filter(namespace_id.eq(ns_id))
+            .filter(user_id.eq(uid))
+            .select(role)
+            .first(db)
+            .optional()
+    }
+
+    /// Remove a member from a namespace. Returns
+    /// false if the user wasn't a member of it
+    pub fn remove(db: &DbConnection, ns_id: i32, uid: i32) -> Result<bool, DieselErr> {
+        use crate::schema::namespace_members::dsl::*;
+
+        let removed = diesel::delete(
+            namespace_members
+                .filter(namespace_id.eq(ns_id))
+                .filter(user_id.eq(uid)),
+        )
+        .execute(db)?;
+
+        Ok(removed > 0)
+    }
+}
+
+impl<DB: Backend> ToSql<SmallInt, DB> for MemberRole
+where
+    i16: ToSql<SmallInt, DB>,
+{
+    fn to_sql<W>(&self, out: &mut Output<W, DB>) -> serialize::Result
+    where
+        W: io::Write,
+    {
+        // The order of the values is used for comparing roles
+        match *self {
+            MemberRole::Viewer => 0,
+            MemberRole::Uploader => 1,
+            MemberRole::Editor => 2,
+            MemberRole::Owner => 3,
+        }
+        .to_sql(out)
+    }
+}
+
+impl<DB: Backend> FromSql<SmallInt, DB> for MemberRole
+where
+    i16: FromSql<SmallInt, DB>,
+{
+    fn from_sql(bytes: Option<&DB::RawValue>) -> deserialize::Result<Self> {
+        let v = i16::from_sql(bytes)?;
+        Ok(match v {
+            0 => MemberRole::Viewer,
+            1 => MemberRole::Uploader,
+            2 => MemberRole::Editor,
+            3 => MemberRole::Owner,
+            _ => return Err("Invalid MemberRole".into()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_order() {
+        assert!(MemberRole::Viewer < MemberRole::Uploader);
+        assert!(MemberRole::Uploader < MemberRole::Editor);
+        assert!(MemberRole::Editor < MemberRole::Owner);
+
+        let role: MemberRole = serde_json::from_str("\"uploader\"").unwrap();
+        assert_eq!(role, MemberRole::Uploader);
+        assert!(serde_json::from_str::<MemberRole>("\"admin\"").is_err());
+    }
+}
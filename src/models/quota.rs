@@ -0,0 +1,160 @@
+use crate::{
+    models::{file::File, namespace::Namespace, user::User},
+    response_code::RestError,
+    schema::users,
+    DbConnection,
+};
+use diesel::{prelude::*, result::Error as DieselErr};
+
+/// Storage limits of a user or namespace. None means unlimited
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub bytes: Option<i64>,
+    pub files: Option<i64>,
+}
+
+/// The space left for uploads into a namespace. Values can be
+/// negative if a quota was lowered below the current usage
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Allowance {
+    pub bytes: Option<i64>,
+    pub files: Option<i64>,
+}
+
+impl Allowance {
+    /// Get the allowance left by a quota with the given usage
+    fn new(quota: Quota, used_bytes: i64, used_files: i64) -> Self {
+        Allowance {
+            bytes: quota.bytes.map(|i| i - used_bytes),
+            files: quota.files.map(|i| i - used_files),
+        }
+    }
+
+    /// Combine two allowances, keeping the lower limits
+    fn min(self, other: Allowance) -> Self {
+        let min = |a: Option<i64>, b: Option<i64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        Allowance {
+            bytes: min(self.bytes, other.bytes),
+            files: min(self.files, other.files),
+        }
+    }
+
+    /// The largest content which can be uploaded. The content
+    /// of a file being replaced doesn't count
+    pub fn max_size(&self, replaced: Option<&File>) -> Option<i64> {
+        let replaced_size = replaced.map(|i| i.file_size).unwrap_or(0);
+        self.bytes.map(|i| i + replaced_size)
+    }
+
+    /// Check whether content of 'size' bytes can be uploaded as new
+    /// file or as new content of the 'replaced' file
+    pub fn check(&self, replaced: Option<&File>, size: i64) -> Result<(), RestError> {
+        if replaced.is_none() && self.files.map(|i| i < 1).unwrap_or(false) {
+            return Err(RestError::QuotaExceeded);
+        }
+
+        // Shrinking a file is always allowed
+        if replaced.map(|i| size <= i.file_size).unwrap_or(false) {
+            return Ok(());
+        }
+
+        match self.max_size(replaced) {
+            Some(max_size) if size > max_size => Err(RestError::QuotaExceeded),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Get the space left for uploads into a namespace. The quotas of its
+/// owner apply to uploads of members too. Files in the trash count
+pub fn allowance(db: &DbConnection, namespace: &Namespace) -> Result<Allowance, DieselErr> {
+    let user: User = users::table.find(namespace.user_id).first(db)?;
+    let mut allowance = Allowance::default();
+
+    let quota = user.quota();
+    if quota != Quota::default() {
+        allowance = Allowance::new(
+            quota,
+            user.total_filesize(db, None)?,
+            user.total_filecount(db, None)?,
+        );
+    }
+
+    let quota = namespace.quota();
+    if quota != Quota::default() {
+        allowance = allowance.min(Allowance::new(
+            quota,
+            user.total_filesize(db, Some(namespace.id))?,
+            user.total_filecount(db, Some(namespace.id))?,
+        ));
+    }
+
+    Ok(allowance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(size: i64) -> File {
+        File {
+            id: 1,
+            file_size: size,
+            ..File::default()
+        }
+    }
+
+    #[test]
+    fn test_allowance_min() {
+        let user = Allowance::new(
+            Quota {
+                bytes: Some(100),
+                files: None,
+            },
+            40,
+            3,
+        );
+        let namespace = Allowance::new(
+            Quota {
+                bytes: Some(80),
+                files: Some(5),
+            },
+            30,
+            3,
+        );
+
+        let allowance = user.min(namespace);
+        assert_eq!(allowance.bytes, Some(50));
+        assert_eq!(allowance.files, Some(2));
+    }
+
+    #[test]
+    fn test_allowance_check() {
+        let allowance = Allowance {
+            bytes: Some(10),
+            files: Some(0),
+        };
+
+        // No new files allowed
+        assert_eq!(allowance.check(None, 1), Err(RestError::QuotaExceeded));
+
+        // The replaced content is freed
+        assert!(allowance.check(Some(&file(5)), 15).is_ok());
+        assert_eq!(
+            allowance.check(Some(&file(5)), 16),
+            Err(RestError::QuotaExceeded)
+        );
+
+        // Shrinking is allowed even if the quota is exceeded
+        let exceeded = Allowance {
+            bytes: Some(-20),
+            files: None,
+        };
+        assert!(exceeded.check(Some(&file(10)), 5).is_ok());
+        assert!(exceeded.check(None, 0).is_err());
+    }
+}
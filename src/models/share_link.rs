@@ -0,0 +1,285 @@
+use crate::{
+    config::PasswordHashing,
+    models::{file::File, namespace_member::MemberRole},
+    response_code::{diesel_option, Origin, RestError},
+    schema::{files, share_links},
+    utils::{self, random_string},
+    DbConnection,
+};
+use chrono::prelude::*;
+use diesel::{
+    dsl::{exists, not, sql},
+    prelude::*,
+    result::{DatabaseErrorKind, Error as DieselErr},
+    sql_types::Bool,
+};
+use std::collections::HashMap;
+
+/// A public link of a file. A file can be
+/// shared using multiple independent links
+#[derive(Identifiable, Queryable, Associations, Debug, AsChangeset, Clone)]
+#[belongs_to(File)]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct ShareLink {
+    pub id: i32,
+    pub file_id: i32,
+    pub slug: String,
+    pub label: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Hash of the password protecting the link
+    pub password: Option<String>,
+    pub max_downloads: Option<i32>,
+    pub downloads: i32,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "share_links"]
+pub struct NewShareLink {
+    pub file_id: i32,
+    pub slug: String,
+    pub label: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub password: Option<String>,
+    pub max_downloads: Option<i32>,
+}
+
+impl NewShareLink {
+    /// Create a new link. If an empty slug is
+    /// provided, a random one will be generated.
+    /// 'password' is the hash of the links password
+    pub fn new(
+        file_id: i32,
+        slug: &str,
+        label: Option<String>,
+        limits: LinkLimits,
+        password: Option<String>,
+    ) -> Self {
+        NewShareLink {
+            file_id,
+            slug: if slug.is_empty() {
+                random_string(25)
+            } else {
+                slug.to_string()
+            },
+            label: label.filter(|i| !i.is_empty()),
+            expires_at: limits.expires_at,
+            password,
+            max_downloads: limits.max_downloads,
+        }
+    }
+
+    /// Insert the link and make its file public
+    pub fn create(self, db: &DbConnection) -> Result<ShareLink, RestError> {
+        db.transaction(|| {
+            let link: ShareLink = diesel::insert_into(share_links::table)
+                .values(&self)
+                .get_result(db)
+                .map_err(|err| match err {
+                    DieselErr::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                        RestError::AlreadyExists
+                    }
+                    _ => err.into(),
+                })?;
+
+            diesel::update(files::table.find(self.file_id))
+                .set(files::is_public.eq(true))
+                .execute(db)?;
+
+            Ok(link)
+        })
+    }
+}
+
+impl ShareLink {
+    /// Find a link of a file the user can edit
+    pub fn find_by_id(db: &DbConnection, link_id: i32, uid: i32) -> Result<ShareLink, RestError> {
+        let (link, file): (ShareLink, File) = share_links::table
+            .inner_join(files::table)
+            .filter(share_links::id.eq(link_id))
+            .filter(files::deleted_at.is_null())
+            .get_result(db)
+            .map_err(|i| diesel_option(i, Origin::ShareLink))?;
+
+        file.authorize(db, uid, MemberRole::Editor, Origin::ShareLink)?;
+        Ok(link)
+    }
+
+    /// List all links of a file, the oldest first
+    pub fn list(db: &DbConnection, fid: i32) -> Result<Vec<ShareLink>, DieselErr> {
+        use crate::schema::share_links::dsl::*;
+
+        share_links
+            .filter(file_id.eq(fid))
+            .order((created_at.asc(), id.asc()))
+            .load(db)
+    }
+
+    /// Whether the link reached its expiry time or download limit
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.map(|i| i <= Utc::now()).unwrap_or(false)
+            || self
+                .max_downloads
+                .map(|i| self.downloads >= i)
+                .unwrap_or(false)
+    }
+
+    /// Count a download of the link. Returns false if the file
+    /// isn't public anymore or the link reached one of its limits
+    pub fn claim_download(&self, db: &DbConnection) -> Result<bool, DieselErr> {
+        use crate::schema::share_links::dsl::*;
+
+        let public_files = files::table.filter(files::is_public).select(files::id);
+
+        // Checked within the update, so concurrent
+        // downloads can't exceed the download limit
+        let claimed = diesel::update(
+            share_links
+                .filter(id.eq(self.id))
+                .filter(file_id.eq_any(public_files))
+                .filter(expires_at.is_null().or(expires_at.gt(Utc::now())))
+                .filter(sql::<Bool>(
+                    "(max_downloads IS NULL OR downloads < max_downloads)",
+                )),
+        )
+        .set(downloads.eq(downloads + 1))
+        .execute(db)?;
+
+        Ok(claimed > 0)
+    }
+
+    /// Delete the link. Files without any
+    /// remaining link aren't public anymore
+    pub fn revoke(&self, db: &DbConnection) -> Result<(), DieselErr> {
+        db.transaction(|| {
+            diesel::delete(self).execute(db)?;
+            unpublish_unlinked(db)?;
+            Ok(())
+        })
+    }
+}
+
+/// Get the oldest link of each of the given files
+pub fn first_slugs(db: &DbConnection, fids: &[i32]) -> Result<HashMap<i32, String>, DieselErr> {
+    use crate::schema::share_links::dsl::*;
+
+    let links: Vec<(i32, String)> = share_links
+        .filter(file_id.eq_any(fids))
+        .order((created_at.desc(), id.desc()))
+        .select((file_id, slug))
+        .load(db)?;
+
+    // Older links overwrite newer ones
+    Ok(links.into_iter().collect())
+}
+
+/// Delete all links which expired or reached their download limit.
+/// Returns the amount of deleted links
+pub fn delete_expired(db: &DbConnection) -> Result<usize, DieselErr> {
+    use crate::schema::share_links::dsl::*;
+
+    db.transaction(|| {
+        let deleted = diesel::delete(
+            share_links.filter(
+                expires_at
+                    .le(Utc::now())
+                    .or(sql::<Bool>("downloads >= max_downloads")),
+            ),
+        )
+        .execute(db)?;
+
+        if deleted > 0 {
+            unpublish_unlinked(db)?;
+        }
+
+        Ok(deleted)
+    })
+}
+
+/// Unpublish all public files without any link
+fn unpublish_unlinked(db: &DbConnection) -> Result<usize, DieselErr> {
+    diesel::update(files::table.filter(files::is_public).filter(not(exists(
+        share_links::table.filter(share_links::file_id.eq(files::id)),
+    ))))
+    .set(files::is_public.eq(false))
+    .execute(db)
+}
+
+/// Limits of a public link
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkLimits {
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_downloads: Option<i32>,
+}
+
+impl LinkLimits {
+    /// Create limits from the seconds until a link
+    /// expires and the amount of allowed downloads
+    pub fn new(expires_in: Option<i64>, max_downloads: Option<i32>) -> Result<Self, RestError> {
+        if expires_in.map(|i| i <= 0).unwrap_or(false)
+            || max_downloads.map(|i| i <= 0).unwrap_or(false)
+        {
+            return Err(RestError::BadRequest);
+        }
+
+        Ok(LinkLimits {
+            expires_at: expires_in.map(|i| Utc::now() + chrono::Duration::seconds(i)),
+            max_downloads,
+        })
+    }
+}
+
+/// Hash the password protecting a public link.
+/// Empty passwords don't protect the link
+pub fn hash_link_password(
+    hashing: &PasswordHashing,
+    password: &str,
+) -> Result<Option<String>, RestError> {
+    if password.is_empty() {
+        return Ok(None);
+    }
+
+    utils::hash_pw(hashing, password).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(limits: LinkLimits) -> ShareLink {
+        let new = NewShareLink::new(1, "", None, limits, None);
+        ShareLink {
+            id: 1,
+            file_id: new.file_id,
+            slug: new.slug,
+            label: new.label,
+            created_at: Utc::now(),
+            expires_at: new.expires_at,
+            password: new.password,
+            max_downloads: new.max_downloads,
+            downloads: 0,
+        }
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let mut link = link(LinkLimits::new(Some(60), Some(2)).unwrap());
+        assert_eq!(link.slug.len(), 25);
+        assert!(!link.is_expired());
+
+        link.downloads = 1;
+        assert!(!link.is_expired());
+
+        link.downloads = 2;
+        assert!(link.is_expired());
+
+        let mut link = self::link(LinkLimits::default());
+        assert!(!link.is_expired());
+
+        link.expires_at = Some(Utc::now() - chrono::Duration::seconds(1));
+        assert!(link.is_expired());
+
+        assert_eq!(LinkLimits::new(Some(0), None), Err(RestError::BadRequest));
+        assert_eq!(LinkLimits::new(None, Some(-1)), Err(RestError::BadRequest));
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use crate::{schema::traffic, DbConnection};
+use bigdecimal::{BigDecimal, ToPrimitive};
+use chrono::prelude::*;
+use diesel::{
+    dsl::sql,
+    pg::upsert::excluded,
+    prelude::*,
+    result::Error,
+    sql_types::{BigInt, Integer, Nullable, Numeric, Timestamptz},
+};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// Traffic which wasn't written to the database yet
+    static ref PENDING: Mutex<HashMap<TrafficKey, Usage>> = Mutex::new(HashMap::new());
+}
+
+/// Length of the periods traffic gets aggregated in (1h)
+const PERIOD_SECS: i64 = 60 * 60;
+
+/// The kind of a transfer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrafficKind {
+    Upload,
+    Download,
+    Preview,
+}
+
+/// Amount of transferred bytes
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub upload: i64,
+    pub download: i64,
+    pub preview: i64,
+}
+
+impl Usage {
+    /// The total amount of transferred bytes
+    pub fn total(&self) -> i64 {
+        self.upload + self.download + self.preview
+    }
+
+    fn add(&mut self, kind: TrafficKind, bytes: i64) {
+        match kind {
+            TrafficKind::Upload => self.upload += bytes,
+            TrafficKind::Download => self.download += bytes,
+            TrafficKind::Preview => self.preview += bytes,
+        }
+    }
+
+    fn merge(&mut self, other: &Usage) {
+        self.upload += other.upload;
+        self.download += other.download;
+        self.preview += other.preview;
+    }
+}
+
+/// User, namespace and period traffic is accounted for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct TrafficKey {
+    user_id: i32,
+    namespace_id: i32,
+    period: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "traffic"]
+struct NewTraffic {
+    user_id: i32,
+    namespace_id: Option<i32>,
+    period: DateTime<Utc>,
+    upload: i64,
+    download: i64,
+    preview: i64,
+}
+
+/// Account transferred bytes to a user and one of their namespaces. The
+/// traffic is kept in memory until the next call of 'flush'
+pub fn record(user_id: i32, namespace_id: i32, kind: TrafficKind, bytes: u64) {
+    if bytes == 0 {
+        return;
+    }
+
+    let now = Utc::now().timestamp();
+    let key = TrafficKey {
+        user_id,
+        namespace_id,
+        period: now - now.rem_euclid(PERIOD_SECS),
+    };
+
+    if let Ok(mut pending) = PENDING.lock() {
+        pending.entry(key).or_default().add(kind, bytes as i64);
+    }
+}
+
+/// Write all recorded traffic into the database. Returns the
+/// amount of written rows. On failure the traffic is kept
+pub fn flush(db: &DbConnection) -> Result<usize, Error> {
+    let pending = match PENDING.lock() {
+        Ok(mut pending) => std::mem::take(&mut *pending),
+        Err(_) => return Ok(0),
+    };
+
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    write(db, &pending).map_err(|e| requeue(&pending, e))
+}
+
+/// Add the traffic in 'pending' to the one stored in the database
+fn write(db: &DbConnection, pending: &HashMap<TrafficKey, Usage>) -> Result<usize, Error> {
+    // Namespaces or users might have been deleted since the traffic was
+    // recorded. Keep such traffic without its namespace and drop the one
+    // of deleted users, otherwise the rows would be rejected forever
+    let (user_ids, ns_ids) = {
+        use crate::schema::{namespaces, users};
+
+        let uids: Vec<i32> = pending.keys().map(|k| k.user_id).collect();
+        let nids: Vec<i32> = pending.keys().map(|k| k.namespace_id).collect();
+
+        let user_ids: HashSet<i32> = users::table
+            .filter(users::id.eq_any(uids))
+            .select(users::id)
+            .load::<i32>(db)?
+            .into_iter()
+            .collect();
+
+        let ns_ids: HashSet<i32> = namespaces::table
+            .filter(namespaces::id.eq_any(nids))
+            .select(namespaces::id)
+            .load::<i32>(db)?
+            .into_iter()
+            .collect();
+
+        (user_ids, ns_ids)
+    };
+
+    let mut merged: HashMap<(i32, Option<i32>, i64), Usage> = HashMap::new();
+    for (key, usage) in pending
+        .iter()
+        .filter(|(k, _)| user_ids.contains(&k.user_id))
+    {
+        let ns = Some(key.namespace_id).filter(|i| ns_ids.contains(i));
+        merged
+            .entry((key.user_id, ns, key.period))
+            .or_default()
+            .merge(usage);
+    }
+
+    if merged.is_empty() {
+        return Ok(0);
+    }
+
+    let (orphaned, rows): (Vec<NewTraffic>, Vec<NewTraffic>) = merged
+        .iter()
+        .map(|((uid, ns, p), usage)| NewTraffic {
+            user_id: *uid,
+            namespace_id: *ns,
+            period: Utc.timestamp(*p, 0),
+            upload: usage.upload,
+            download: usage.download,
+            preview: usage.preview,
+        })
+        .partition(|i| i.namespace_id.is_none());
+
+    db.transaction(|| {
+        use crate::schema::traffic::dsl::*;
+
+        let mut written = diesel::insert_into(traffic)
+            .values(&rows)
+            .on_conflict((user_id, namespace_id, period))
+            .do_update()
+            .set((
+                upload.eq(upload + excluded(upload)),
+                download.eq(download + excluded(download)),
+                preview.eq(preview + excluded(preview)),
+            ))
+            .execute(db)?;
+
+        // NULLs never conflict, so traffic without namespace has its
+        // own partial index. Diesel can't use those as conflict target
+        for row in orphaned {
+            written += diesel::sql_query(
+                "INSERT INTO traffic (user_id, period, upload, download, preview) \
+                 VALUES ($1, $2, $3, $4, $5) \
+                 ON CONFLICT (user_id, period) WHERE namespace_id IS NULL DO UPDATE SET \
+                 upload = traffic.upload + excluded.upload, \
+                 download = traffic.download + excluded.download, \
+                 preview = traffic.preview + excluded.preview",
+            )
+            .bind::<Integer, _>(row.user_id)
+            .bind::<Timestamptz, _>(row.period)
+            .bind::<BigInt, _>(row.upload)
+            .bind::<BigInt, _>(row.download)
+            .bind::<BigInt, _>(row.preview)
+            .execute(db)?;
+        }
+
+        Ok(written)
+    })
+}
+
+/// Put traffic which couldn't be written back
+/// to be retried with the next flush
+fn requeue(pending: &HashMap<TrafficKey, Usage>, err: Error) -> Error {
+    if let Ok(mut current) = PENDING.lock() {
+        for (key, usage) in pending.iter() {
+            current.entry(*key).or_default().merge(usage);
+        }
+    }
+
+    err
+}
+
+/// Get the traffic of a user within an optional time window, optionally
+/// only of one namespace. The window is applied to the hourly periods
+/// traffic is aggregated in
+pub fn usage(
+    db: &DbConnection,
+    uid: i32,
+    namespace: Option<i32>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+) -> Result<Usage, Error> {
+    use crate::schema::traffic::dsl::*;
+
+    let mut query = traffic
+        .filter(user_id.eq(uid))
+        // Diesel can't select multiple aggregates at once
+        .select(sql::<(
+            Nullable<Numeric>,
+            Nullable<Numeric>,
+            Nullable<Numeric>,
+        )>("SUM(upload), SUM(download), SUM(preview)"))
+        .into_boxed();
+
+    if let Some(namespace) = namespace {
+        query = query.filter(namespace_id.eq(namespace));
+    }
+
+    if let Some(since) = since {
+        query = query.filter(period.ge(since));
+    }
+
+    if let Some(until) = until {
+        query = query.filter(period.lt(until));
+    }
+
+    let (up, down, prev): (Option<BigDecimal>, Option<BigDecimal>, Option<BigDecimal>) =
+        query.first(db)?;
+    let to_i64 = |i: Option<BigDecimal>| i.and_then(|i| i.to_i64()).unwrap_or(0);
+
+    Ok(Usage {
+        upload: to_i64(up),
+        download: to_i64(down),
+        preview: to_i64(prev),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        // Use a user id no other test records traffic for
+        record(-1, 1, TrafficKind::Download, 100);
+        record(-1, 1, TrafficKind::Download, 20);
+        record(-1, 1, TrafficKind::Preview, 5);
+        record(-1, 2, TrafficKind::Upload, 0);
+
+        let pending = PENDING.lock().unwrap();
+        let mut usage = Usage::default();
+        for (key, i) in pending.iter().filter(|(key, _)| key.user_id == -1) {
+            // Empty transfers aren't recorded
+            assert_eq!(key.namespace_id, 1);
+            usage.merge(i);
+        }
+
+        assert_eq!(usage.download, 120);
+        assert_eq!(usage.total(), 125);
+    }
+
+    #[test]
+    #[ignore = "needs a database"]
+    fn test_write_deleted_namespace() {
+        use crate::schema::users;
+
+        let db = crate::db::test_connection();
+        db.test_transaction::<_, Error, _>(|| {
+            let uid: i32 = diesel::insert_into(users::table)
+                .values((
+                    users::username.eq("test_write_deleted_namespace"),
+                    users::password.eq(""),
+                ))
+                .returning(users::id)
+                .get_result(&db)?;
+
+            // The namespace doesn't exist (anymore)
+            let key = TrafficKey {
+                user_id: uid,
+                namespace_id: -1,
+                period: 0,
+            };
+            let mut pending = HashMap::new();
+            pending.insert(key, Usage::default());
+            pending
+                .get_mut(&key)
+                .unwrap()
+                .add(TrafficKind::Download, 10);
+
+            write(&db, &pending)?;
+            write(&db, &pending)?;
+
+            let rows: i64 = traffic::table
+                .filter(traffic::user_id.eq(uid))
+                .count()
+                .get_result(&db)?;
+            assert_eq!(rows, 1);
+            assert_eq!(usage(&db, uid, None, None, None)?.download, 20);
+            Ok(())
+        });
+    }
+}
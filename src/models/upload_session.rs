@@ -0,0 +1,222 @@
+use crate::{
+    config::Config,
+    handlers::{requests::upload_request::UploadRequest, upload_session::SessionLock},
+    models::user::User,
+    response_code::{diesel_option, Origin, RestError},
+    schema::upload_sessions,
+    utils::random_string,
+    DbConnection,
+};
+use chrono::prelude::*;
+use diesel::{prelude::*, result::Error as DieselErr};
+use std::{fs, path::Path};
+
+/// An upload which can be continued across
+/// multiple requests until it gets finalized
+#[derive(Identifiable, Queryable, Associations, Debug, AsChangeset, Clone)]
+#[belongs_to(User)]
+pub struct UploadSession {
+    pub id: i32,
+    pub token: String,
+    pub user_id: i32,
+    pub local_name: String,
+    pub request: String,
+    pub expected_size: i64,
+    pub received: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "upload_sessions"]
+pub struct NewUploadSession {
+    pub token: String,
+    pub user_id: i32,
+    pub local_name: String,
+    pub request: String,
+    pub expected_size: i64,
+}
+
+impl NewUploadSession {
+    /// Create a new NewUploadSession object for the passed request
+    pub fn new(
+        user_id: i32,
+        upload_request: &UploadRequest,
+        expected_size: i64,
+    ) -> Result<Self, RestError> {
+        Ok(NewUploadSession {
+            token: random_string(40),
+            user_id,
+            local_name: random_string(30),
+            request: serde_json::to_string(upload_request).map_err(|_| RestError::Internal)?,
+            expected_size,
+        })
+    }
+
+    /// Create the upload session
+    pub fn create(self, db: &DbConnection) -> Result<UploadSession, DieselErr> {
+        diesel::insert_into(upload_sessions::table)
+            .values(&self)
+            .get_result(db)
+    }
+}
+
+impl UploadSession {
+    /// Find an upload session by its token. The owner has to be
+    /// passed as well, in order to prevent unauthorized access
+    pub fn find(db: &DbConnection, q_token: &str, uid: i32) -> Result<UploadSession, RestError> {
+        use crate::schema::upload_sessions::dsl::*;
+
+        upload_sessions
+            .filter(token.eq(q_token).and(user_id.eq(uid)))
+            .first(db)
+            .map_err(|i| diesel_option(i, Origin::UploadSession))
+    }
+
+    /// Get all sessions which weren't updated since 'before'
+    pub fn find_stale(
+        db: &DbConnection,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<UploadSession>, DieselErr> {
+        use crate::schema::upload_sessions::dsl::*;
+
+        upload_sessions.filter(updated_at.lt(before)).load(db)
+    }
+
+    /// Get the upload request the session was created with
+    pub fn upload_request(&self) -> Result<UploadRequest, RestError> {
+        serde_json::from_str(&self.request).map_err(|_| RestError::Internal)
+    }
+
+    /// Returns true if all expected bytes were received
+    pub fn is_complete(&self) -> bool {
+        self.received == self.expected_size
+    }
+
+    /// Returns the time the session expires
+    pub fn expires_at(&self, config: &Config) -> DateTime<Utc> {
+        self.updated_at + config.upload_session_expiry()
+    }
+
+    /// Set the amount of received bytes
+    pub fn set_received(&mut self, db: &DbConnection, n: i64) -> Result<(), DieselErr> {
+        use crate::schema::upload_sessions::dsl::*;
+
+        self.received = n;
+        self.updated_at = Utc::now();
+
+        diesel::update(upload_sessions)
+            .set((received.eq(self.received), updated_at.eq(self.updated_at)))
+            .filter(id.eq(self.id))
+            .execute(db)?;
+        Ok(())
+    }
+
+    /// Delete the session from the database. The local
+    /// file has to be moved or deleted by the caller
+    pub fn delete(&self, db: &DbConnection) -> Result<(), DieselErr> {
+        diesel::delete(self).execute(db)?;
+        Ok(())
+    }
+
+    /// Delete the session along with its partial file
+    pub fn discard(&self, db: &DbConnection, config: &Config) -> Result<(), DieselErr> {
+        self.delete(db)?;
+
+        // Delete partial file. Ignore errors
+        fs::remove_file(Path::new(&config.server.file_output_path).join(&self.local_name)).ok();
+
+        Ok(())
+    }
+}
+
+/// Delete all sessions which have expired along with their
+/// partial files and return the amount of removed sessions.
+/// Sessions which are receiving data right now are kept
+pub fn delete_expired(db: &DbConnection, config: &Config) -> Result<usize, DieselErr> {
+    use crate::schema::upload_sessions::dsl::*;
+
+    let before = Utc::now() - config.upload_session_expiry();
+    let mut deleted = 0;
+
+    for session in UploadSession::find_stale(db, before)? {
+        let _lock = match SessionLock::acquire(session.id) {
+            Ok(lock) => lock,
+            Err(_) => continue,
+        };
+
+        // The session might have been updated before it was locked
+        let n = diesel::delete(upload_sessions.filter(id.eq(session.id)))
+            .filter(updated_at.lt(before))
+            .execute(db)?;
+
+        if n > 0 {
+            fs::remove_file(Path::new(&config.server.file_output_path).join(&session.local_name))
+                .ok();
+            deleted += 1;
+        }
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::users;
+
+    #[test]
+    #[ignore = "needs a database"]
+    fn test_delete_expired_locked() {
+        let db = crate::db::test_connection();
+        db.test_transaction::<_, DieselErr, _>(|| {
+            let config = Config::default();
+            let uid: i32 = diesel::insert_into(users::table)
+                .values((
+                    users::username.eq("test_delete_expired_locked"),
+                    users::password.eq(""),
+                ))
+                .returning(users::id)
+                .get_result(&db)
+                .unwrap();
+
+            let mut session = NewUploadSession {
+                token: random_string(40),
+                user_id: uid,
+                local_name: random_string(30),
+                request: String::new(),
+                expected_size: 10,
+            }
+            .create(&db)
+            .unwrap();
+
+            diesel::update(&session)
+                .set(
+                    upload_sessions::updated_at.eq(Utc::now() - config.upload_session_expiry() * 2),
+                )
+                .execute(&db)
+                .unwrap();
+
+            // Sessions receiving data are kept, even if they expired
+            let lock = SessionLock::acquire(session.id).unwrap();
+            delete_expired(&db, &config).unwrap();
+            assert!(UploadSession::find(&db, &session.token, uid).is_ok());
+
+            // Receiving data refreshes the session
+            session.set_received(&db, 5).unwrap();
+            drop(lock);
+            delete_expired(&db, &config).unwrap();
+            assert!(UploadSession::find(&db, &session.token, uid).is_ok());
+
+            diesel::update(&session)
+                .set(
+                    upload_sessions::updated_at.eq(Utc::now() - config.upload_session_expiry() * 2),
+                )
+                .execute(&db)
+                .unwrap();
+            delete_expired(&db, &config).unwrap();
+            assert!(UploadSession::find(&db, &session.token, uid).is_err());
+            Ok(())
+        });
+    }
+}
@@ -1,17 +1,34 @@
 use crate::{
+    config::{Config, PasswordHashing},
     response_code::{self, RestError},
     utils, DbConnection,
 };
 
 use crate::schema::*;
 use bigdecimal::{BigDecimal, ToPrimitive};
+use chrono::Utc;
 use diesel::{
-    dsl::{count_star, sum},
+    backend::Backend,
+    deserialize::{self, FromSql},
+    dsl::{count_star, sql},
+    expression::dsl::sum,
     prelude::*,
     result::{DatabaseErrorKind, Error::DatabaseError},
+    serialize::{self, Output, ToSql},
+    sql_types::{BigInt, SmallInt},
 };
 
-use super::namespace::{CreateNamespace, Namespace};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, io};
+
+use super::{
+    attribute::AttributeType,
+    file::File,
+    login_session::LoginSession,
+    namespace::{CreateNamespace, Namespace},
+    quota::Quota,
+    upload_session::UploadSession,
+};
 
 #[derive(Identifiable, Queryable, Associations, Clone, Debug, Default)]
 pub struct User {
@@ -19,6 +36,9 @@ pub struct User {
     pub username: String,
     pub password: String,
     pub disabled: bool,
+    pub quota_bytes: Option<i64>,
+    pub quota_files: Option<i64>,
+    pub role: Role,
 }
 
 #[derive(Insertable)]
@@ -26,12 +46,127 @@ pub struct User {
 pub struct NewUser {
     pub username: String,
     pub password: String,
+    pub quota_bytes: Option<i64>,
+    pub quota_files: Option<i64>,
+    pub role: Role,
+}
+
+/// The role of a user. Admins can manage other users
+#[derive(
+    Debug, Clone, Copy, Default, Serialize, Deserialize, AsExpression, FromSqlRow, PartialEq,
+)]
+#[sql_type = "SmallInt"]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
 }
 
 impl User {
     // Create a NewUser object
     pub fn new(username: String, password: String) -> NewUser {
-        NewUser { username, password }
+        NewUser {
+            username,
+            password,
+            quota_bytes: None,
+            quota_files: None,
+            role: Role::User,
+        }
+    }
+
+    /// List all users
+    pub fn list(db: &DbConnection) -> Result<Vec<User>, diesel::result::Error> {
+        use crate::schema::users::dsl::*;
+
+        users.order(id).load(db)
+    }
+
+    /// Returns true if the user has the admin role
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// Enable or disable the user. Disabling a user ends all of their sessions
+    pub fn set_disabled(&self, db: &DbConnection, value: bool) -> Result<(), RestError> {
+        db.transaction::<_, RestError, _>(|| {
+            diesel::update(self)
+                .set(users::disabled.eq(value))
+                .execute(db)?;
+
+            if value {
+                LoginSession::revoke_all(db, self.id)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Set a new password. All sessions of the user are ended
+    pub fn set_password(
+        &self,
+        db: &DbConnection,
+        config: &Config,
+        password: &str,
+    ) -> Result<(), RestError> {
+        let hash = utils::hash_pw(&config.password_hashing, password)?;
+
+        db.transaction::<_, RestError, _>(|| {
+            diesel::update(self)
+                .set(users::password.eq(hash))
+                .execute(db)?;
+
+            LoginSession::revoke_all(db, self.id)?;
+            Ok(())
+        })
+    }
+
+    /// Set the quota of the user
+    pub fn set_quota(&self, db: &DbConnection, quota: Quota) -> Result<(), RestError> {
+        diesel::update(self)
+            .set((
+                users::quota_bytes.eq(quota.bytes),
+                users::quota_files.eq(quota.files),
+            ))
+            .execute(db)?;
+        Ok(())
+    }
+
+    /// Set the role of the user
+    pub fn set_role(&self, db: &DbConnection, role: Role) -> Result<(), RestError> {
+        diesel::update(self).set(users::role.eq(role)).execute(db)?;
+        Ok(())
+    }
+
+    /// Delete the user along with all of their namespaces, files and sessions.
+    /// Returns the names of stored objects which aren't referenced anymore and
+    /// have to be deleted by the caller
+    pub fn delete(&self, db: &DbConnection, config: &Config) -> Result<Vec<String>, RestError> {
+        db.transaction::<_, RestError, _>(|| {
+            let mut orphaned = vec![];
+
+            // Files in the trash are deleted as well
+            let user_files: Vec<File> = files::table.filter(files::user_id.eq(self.id)).load(db)?;
+
+            for file in user_files {
+                orphaned.extend(file.delete(db)?);
+            }
+
+            let sessions: Vec<UploadSession> = UploadSession::belonging_to(self).load(db)?;
+            for session in sessions {
+                session.discard(db, config)?;
+            }
+
+            diesel::delete(attributes::table.filter(attributes::user_id.eq(self.id)))
+                .execute(db)?;
+            diesel::delete(traffic::table.filter(traffic::user_id.eq(self.id))).execute(db)?;
+            LoginSession::revoke_all(db, self.id)?;
+            diesel::delete(namespaces::table.filter(namespaces::user_id.eq(self.id)))
+                .execute(db)?;
+            diesel::delete(self).execute(db)?;
+
+            Ok(orphaned)
+        })
     }
 
     // Find a user by its Name
@@ -44,6 +179,21 @@ impl User {
             .map_err(response_code::login_error)
     }
 
+    /// Get the names of the users with the passed IDs
+    pub fn usernames(
+        db: &DbConnection,
+        ids: &[i32],
+    ) -> Result<HashMap<i32, String>, diesel::result::Error> {
+        use crate::schema::users::dsl::*;
+
+        Ok(users
+            .filter(id.eq_any(ids))
+            .select((id, username))
+            .load::<(i32, String)>(db)?
+            .into_iter()
+            .collect())
+    }
+
     // Find a user by its ID
     pub fn find_by_id(db: &DbConnection, user_id: i32) -> Result<User, RestError> {
         use crate::schema::users::dsl::*;
@@ -54,9 +204,11 @@ impl User {
             .map_err(response_code::login_error)
     }
 
-    /// Create a new user session
+    /// Create a new user session. Password hashes not using the
+    /// current parameters are replaced after a successful login
     pub fn login(
         db: &DbConnection,
+        config: &Config,
         username: &str,
         password: &str,
         mid: &Option<String>,
@@ -69,11 +221,18 @@ impl User {
             return Err(RestError::UserDisabled);
         }
 
-        // Salt & validate password
-        if user.password != utils::hash_pw(username, password) {
+        // Validate password
+        if !utils::verify_pw(&user.password, username, password) {
             return Err(RestError::Unauthorized);
         }
 
+        let hashing = &config.password_hashing;
+        if utils::needs_rehash(hashing, &user.password) {
+            diesel::update(users::table.find(user.id))
+                .set(users::password.eq(utils::hash_pw(hashing, password)?))
+                .execute(db)?;
+        }
+
         // Clear old session(s)
         if let Some(mid) = mid {
             diesel::delete(
@@ -92,6 +251,7 @@ impl User {
             token: utils::random_string(60),
             machine_id: mid.clone(),
             user_id: user.id,
+            expires_at: Utc::now() + config.session_lifetime(),
         };
 
         // Insert new token
@@ -102,6 +262,23 @@ impl User {
         Ok(new_token.token)
     }
 
+    /// Get the amount of users whose password
+    /// is still hashed using the legacy scheme
+    pub fn count_legacy_passwords(db: &DbConnection) -> Result<i64, diesel::result::Error> {
+        users::table
+            .select(count_star())
+            .filter(users::password.not_like("$argon2%"))
+            .get_result(db)
+    }
+
+    /// The quota of the user
+    pub fn quota(&self) -> Quota {
+        Quota {
+            bytes: self.quota_bytes,
+            files: self.quota_files,
+        }
+    }
+
     /// Gets the full namespace for the user
     pub fn get_default_namespace(
         &self,
@@ -114,42 +291,78 @@ impl User {
             .first(db)
     }
 
-    /// Get the total size of all files by a user
-    pub fn total_filesize(&self, db: &DbConnection) -> Result<i64, diesel::result::Error> {
+    /// Get the total size of all files by a user,
+    /// optionally only within one namespace
+    pub fn total_filesize(
+        &self,
+        db: &DbConnection,
+        namespace: Option<i32>,
+    ) -> Result<i64, diesel::result::Error> {
         use crate::schema::files::dsl::*;
 
-        let res: Option<BigDecimal> = files
+        let mut query = files
             .filter(user_id.eq(self.id))
             .select(sum(file_size))
-            .first(db)?;
+            .into_boxed();
+
+        if let Some(namespace) = namespace {
+            query = query.filter(namespace_id.eq(namespace));
+        }
+
+        let res: Option<BigDecimal> = query.first(db)?;
 
         Ok(res.map(|i| i.to_i64().unwrap_or(0)).unwrap_or(0))
     }
 
-    /// Get the amount of all files of a user
-    pub fn total_filecount(&self, db: &DbConnection) -> Result<i64, diesel::result::Error> {
+    /// Get the amount of all files of a user,
+    /// optionally only within one namespace
+    pub fn total_filecount(
+        &self,
+        db: &DbConnection,
+        namespace: Option<i32>,
+    ) -> Result<i64, diesel::result::Error> {
         use crate::schema::files::dsl::*;
-        files
+
+        let mut query = files
             .select(count_star())
             .filter(user_id.eq(self.id))
-            .get_result(db)
+            .into_boxed();
+
+        if let Some(namespace) = namespace {
+            query = query.filter(namespace_id.eq(namespace));
+        }
+
+        query.get_result(db)
     }
 
-    /// Get the count of all namespaces of a user
+    /// Get the count of tags and groups of a
+    /// user, optionally only within one namespace
     pub fn total_attribute_count(
         &self,
         db: &DbConnection,
+        namespace: Option<i32>,
     ) -> Result<(i64, i64), diesel::result::Error> {
         use crate::schema::attributes::dsl::*;
-        let res = attributes
-            .select(count_star())
+
+        let mut query = attributes
+            .select(sql::<(SmallInt, BigInt)>("type, COUNT(*)"))
             .filter(user_id.eq(self.id))
             .group_by(type_)
-            .get_results(db)?;
+            .into_boxed();
+
+        if let Some(namespace) = namespace {
+            query = query.filter(namespace_id.eq(namespace));
+        }
 
-        let tags = res.get(0).copied().unwrap_or(0);
-        let groups = res.get(1).copied().unwrap_or(0);
-        Ok((tags, groups))
+        let res: Vec<(AttributeType, i64)> = query.load(db)?;
+        let count = |kind| {
+            res.iter()
+                .find(|(i, _)| *i == kind)
+                .map(|(_, count)| *count)
+                .unwrap_or(0)
+        };
+
+        Ok((count(AttributeType::Tag), count(AttributeType::Group)))
     }
 
     /// Get the count of all namespaces of a user
@@ -163,15 +376,18 @@ impl User {
 }
 
 impl NewUser {
-    pub fn get_password_hashed(&self) -> String {
-        crate::utils::hash_pw(&self.username, &self.password)
+    pub fn get_password_hashed(&self, hashing: &PasswordHashing) -> Result<String, RestError> {
+        utils::hash_pw(hashing, &self.password)
     }
 
-    /// Register a new user
-    pub fn create(self, db: &DbConnection) -> Result<Self, RestError> {
+    /// Register a new user. The default quotas are applied
+    pub fn create(self, db: &DbConnection, config: &Config) -> Result<Self, RestError> {
         let user = NewUser {
-            password: self.get_password_hashed(),
+            password: self.get_password_hashed(&config.password_hashing)?,
             username: self.username,
+            quota_bytes: config.quota.default_bytes,
+            quota_files: config.quota.default_files,
+            role: self.role,
         };
 
         let res = diesel::insert_into(users::table)
@@ -193,3 +409,33 @@ impl NewUser {
         Ok(user)
     }
 }
+
+impl<DB: Backend> ToSql<SmallInt, DB> for Role
+where
+    i16: ToSql<SmallInt, DB>,
+{
+    fn to_sql<W>(&self, out: &mut Output<W, DB>) -> serialize::Result
+    where
+        W: io::Write,
+    {
+        match *self {
+            Role::User => 0,
+            Role::Admin => 1,
+        }
+        .to_sql(out)
+    }
+}
+
+impl<DB: Backend> FromSql<SmallInt, DB> for Role
+where
+    i16: FromSql<SmallInt, DB>,
+{
+    fn from_sql(bytes: Option<&DB::RawValue>) -> deserialize::Result<Self> {
+        let v = i16::from_sql(bytes)?;
+        Ok(match v {
+            0 => Role::User,
+            1 => Role::Admin,
+            _ => return Err("Invalid Role".into()),
+        })
+    }
+}
@@ -30,6 +30,11 @@ pub enum Origin {
     Group,
     Record,
     User,
+    UploadSession,
+    FileVersion,
+    Session,
+    ShareLink,
+    DropLink,
 }
 
 impl Debug for Origin {
@@ -46,6 +51,11 @@ impl Debug for Origin {
                 Origin::Group => "Group",
                 Origin::Record => "Record",
                 Origin::User => "User",
+                Origin::UploadSession => "UploadSession",
+                Origin::FileVersion => "FileVersion",
+                Origin::Session => "Session",
+                Origin::ShareLink => "ShareLink",
+                Origin::DropLink => "DropLink",
             }
         )
     }
@@ -96,6 +106,33 @@ pub enum RestError {
     #[error("Not allowed")]
     NotAllowed,
 
+    #[error("Offset doesn't match")]
+    OffsetMismatch,
+
+    #[error("Upload already in progress")]
+    UploadInProgress,
+
+    #[error("Unsupported protocol version")]
+    UnsupportedVersion,
+
+    #[error("Payload too large")]
+    PayloadTooLarge,
+
+    #[error("Quota exceeded")]
+    QuotaExceeded,
+
+    #[error("Content doesn't match the expected digest")]
+    DigestMismatch,
+
+    #[error("Archive too large for the requested format")]
+    ArchiveTooLarge,
+
+    #[error("Remote server error")]
+    RemoteError,
+
+    #[error("Remote server timed out")]
+    RemoteTimeout,
+
     // Internal
     #[error("Unknown Internal Error")]
     Internal,
@@ -121,6 +158,15 @@ impl RestError {
             Self::NotAllowed => "NotAllowed".to_string(),
             Self::NotPublic => "NotPublic".to_string(),
             Self::PartialContent => "PartialContent".to_string(),
+            Self::OffsetMismatch => "OffsetMismatch".to_string(),
+            Self::UploadInProgress => "UploadInProgress".to_string(),
+            Self::UnsupportedVersion => "UnsupportedVersion".to_string(),
+            Self::PayloadTooLarge => "PayloadTooLarge".to_string(),
+            Self::QuotaExceeded => "QuotaExceeded".to_string(),
+            Self::DigestMismatch => "DigestMismatch".to_string(),
+            Self::ArchiveTooLarge => "ArchiveTooLarge".to_string(),
+            Self::RemoteError => "RemoteError".to_string(),
+            Self::RemoteTimeout => "RemoteTimeout".to_string(),
             _ => "BadRequest".to_string(),
         }
     }
@@ -141,6 +187,15 @@ impl ResponseError for RestError {
             Self::AlreadyPublic => StatusCode::CONFLICT,
             Self::NotPublic => StatusCode::CONFLICT,
             Self::PartialContent => StatusCode::PARTIAL_CONTENT,
+            Self::OffsetMismatch => StatusCode::CONFLICT,
+            Self::UploadInProgress => StatusCode::CONFLICT,
+            Self::UnsupportedVersion => StatusCode::PRECONDITION_FAILED,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+            Self::DigestMismatch => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::ArchiveTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::RemoteError => StatusCode::BAD_GATEWAY,
+            Self::RemoteTimeout => StatusCode::GATEWAY_TIMEOUT,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -9,6 +9,35 @@ table! {
     }
 }
 
+table! {
+    blobs (id) {
+        id -> Int4,
+        hash -> Text,
+        local_name -> Text,
+        size -> Int8,
+        ref_count -> Int4,
+        created_at -> Timestamptz,
+        encrypted -> Bool,
+    }
+}
+
+table! {
+    drop_links (id) {
+        id -> Int4,
+        user_id -> Int4,
+        namespace_id -> Int4,
+        slug -> Text,
+        label -> Nullable<Text>,
+        tags -> Array<Text>,
+        created_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+        password -> Nullable<Text>,
+        max_file_size -> Nullable<Int8>,
+        max_files -> Nullable<Int4>,
+        uploads -> Int4,
+    }
+}
+
 table! {
     file_attributes (id) {
         id -> Int4,
@@ -17,6 +46,23 @@ table! {
     }
 }
 
+table! {
+    file_versions (id) {
+        id -> Int4,
+        file_id -> Int4,
+        version -> Int4,
+        blob_id -> Nullable<Int4>,
+        local_name -> Text,
+        file_size -> Int8,
+        file_type -> Text,
+        checksum -> Text,
+        wrapped_key -> Nullable<Text>,
+        created_at -> Timestamptz,
+        sha256 -> Nullable<Text>,
+        blake3 -> Nullable<Text>,
+    }
+}
+
 table! {
     files (id) {
         id -> Int4,
@@ -27,10 +73,17 @@ table! {
         file_size -> Int8,
         file_type -> Text,
         is_public -> Bool,
-        public_filename -> Nullable<Text>,
         namespace_id -> Int4,
         encryption -> Int4,
         checksum -> Text,
+        source_url -> Nullable<Text>,
+        blob_id -> Nullable<Int4>,
+        wrapped_key -> Nullable<Text>,
+        deleted_at -> Nullable<Timestamptz>,
+        corrupted -> Bool,
+        sha256 -> Nullable<Text>,
+        blake3 -> Nullable<Text>,
+        uploader_id -> Nullable<Int4>,
     }
 }
 
@@ -41,6 +94,19 @@ table! {
         token -> Text,
         requests -> Int8,
         machine_id -> Nullable<Text>,
+        created_at -> Timestamptz,
+        last_used -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    namespace_members (id) {
+        id -> Int4,
+        namespace_id -> Int4,
+        user_id -> Int4,
+        role -> Int2,
+        created_at -> Timestamptz,
     }
 }
 
@@ -49,6 +115,48 @@ table! {
         id -> Int4,
         name -> Text,
         user_id -> Int4,
+        quota_bytes -> Nullable<Int8>,
+        quota_files -> Nullable<Int8>,
+    }
+}
+
+table! {
+    share_links (id) {
+        id -> Int4,
+        file_id -> Int4,
+        slug -> Text,
+        label -> Nullable<Text>,
+        created_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+        password -> Nullable<Text>,
+        max_downloads -> Nullable<Int4>,
+        downloads -> Int4,
+    }
+}
+
+table! {
+    traffic (id) {
+        id -> Int4,
+        user_id -> Int4,
+        namespace_id -> Nullable<Int4>,
+        period -> Timestamptz,
+        upload -> Int8,
+        download -> Int8,
+        preview -> Int8,
+    }
+}
+
+table! {
+    upload_sessions (id) {
+        id -> Int4,
+        token -> Text,
+        user_id -> Int4,
+        local_name -> Text,
+        request -> Text,
+        expected_size -> Int8,
+        received -> Int8,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -58,23 +166,44 @@ table! {
         username -> Text,
         password -> Text,
         disabled -> Bool,
+        quota_bytes -> Nullable<Int8>,
+        quota_files -> Nullable<Int8>,
+        role -> Int2,
     }
 }
 
 joinable!(attributes -> namespaces (namespace_id));
 joinable!(attributes -> users (user_id));
+joinable!(drop_links -> namespaces (namespace_id));
+joinable!(drop_links -> users (user_id));
 joinable!(file_attributes -> attributes (attribute_id));
 joinable!(file_attributes -> files (file_id));
+joinable!(file_versions -> blobs (blob_id));
+joinable!(file_versions -> files (file_id));
+joinable!(files -> blobs (blob_id));
 joinable!(files -> namespaces (namespace_id));
 joinable!(files -> users (user_id));
 joinable!(login_sessions -> users (user_id));
+joinable!(namespace_members -> namespaces (namespace_id));
+joinable!(namespace_members -> users (user_id));
 joinable!(namespaces -> users (user_id));
+joinable!(share_links -> files (file_id));
+joinable!(traffic -> namespaces (namespace_id));
+joinable!(traffic -> users (user_id));
+joinable!(upload_sessions -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
     attributes,
+    blobs,
+    drop_links,
     file_attributes,
+    file_versions,
     files,
     login_sessions,
+    namespace_members,
     namespaces,
+    share_links,
+    traffic,
+    upload_sessions,
     users,
 );
@@ -0,0 +1,248 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{config::Config, response_code::RestError};
+
+use bytes::Bytes;
+use chacha20::{
+    cipher::{NewCipher, StreamCipher, StreamCipherSeek},
+    ChaCha20,
+};
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305,
+};
+use futures::{StreamExt, TryStreamExt};
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+
+use super::ByteStream;
+
+/// Size of master, user and data keys
+const KEY_SIZE: usize = 32;
+
+/// Size of the nonce prepended to wrapped keys
+const NONCE_SIZE: usize = 12;
+
+/// Salt used to derive user keys from the master key
+const USER_KEY_SALT: &[u8] = b"dv_server user key";
+
+/// The key of a blob. Its content is encrypted using ChaCha20, which
+/// allows decrypting arbitrary ranges without reading the whole blob.
+/// Every blob has its own key, so a fixed nonce is used
+#[derive(Clone)]
+pub struct DataKey([u8; KEY_SIZE]);
+
+impl DataKey {
+    /// Generate a new random key
+    pub fn generate() -> Self {
+        let mut key = [0; KEY_SIZE];
+        OsRng.fill_bytes(&mut key);
+        DataKey(key)
+    }
+
+    /// Create a cipher positioned at byte 'offset' of the content
+    fn cipher(&self, offset: u64) -> ChaCha20 {
+        let mut cipher = ChaCha20::new(&self.0.into(), &[0; NONCE_SIZE].into());
+        cipher.seek(offset);
+        cipher
+    }
+
+    /// Encrypt a local file in place
+    pub fn encrypt_file(&self, path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut cipher = self.cipher(0);
+        let mut buf = vec![0; 65_536];
+
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+
+            cipher.apply_keystream(&mut buf[..n]);
+            file.seek(SeekFrom::Current(-(n as i64)))?;
+            file.write_all(&buf[..n])?;
+        }
+
+        file.sync_all()
+    }
+
+    /// Decrypt a stream of encrypted content, starting at 'offset'
+    pub fn decrypt(&self, stream: ByteStream, offset: u64) -> ByteStream {
+        let mut cipher = self.cipher(offset);
+
+        stream
+            .map_ok(move |chunk| {
+                let mut data = chunk.to_vec();
+                cipher.apply_keystream(&mut data);
+                Bytes::from(data)
+            })
+            .boxed_local()
+    }
+}
+
+/// Derives the keys of users from the master key and uses them to
+/// wrap data keys. Keys wrapped with a previous master key can still
+/// be unwrapped until they got rotated
+#[derive(Clone)]
+pub struct Keyring {
+    master_key: [u8; KEY_SIZE],
+    previous_master_keys: Vec<[u8; KEY_SIZE]>,
+}
+
+impl Keyring {
+    /// Load the keyring from the config. Returns
+    /// None if no master key is configured
+    pub fn from_config(config: &Config) -> Result<Option<Keyring>, String> {
+        let master_key = match config.encryption.master_key {
+            Some(ref key) => parse_key(key)?,
+            None => return Ok(None),
+        };
+
+        let previous_master_keys = config
+            .encryption
+            .previous_master_keys
+            .iter()
+            .map(|i| parse_key(i))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(Keyring {
+            master_key,
+            previous_master_keys,
+        }))
+    }
+
+    /// Wrap a data key with the key of the user
+    pub fn wrap(&self, key: &DataKey, user_id: i32) -> String {
+        let mut nonce = [0; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+
+        let wrapped = user_key(&self.master_key, user_id)
+            .encrypt(&nonce.into(), &key.0[..])
+            .expect("Couldn't wrap data key");
+
+        base64::encode([&nonce[..], &wrapped].concat())
+    }
+
+    /// Unwrap a data key using the key of the user. Returns the key
+    /// and whether it was wrapped with the current master key
+    pub fn unwrap(&self, wrapped: &str, user_id: i32) -> Result<(DataKey, bool), RestError> {
+        let wrapped = base64::decode(wrapped).map_err(|_| RestError::Internal)?;
+        if wrapped.len() < NONCE_SIZE {
+            return Err(RestError::Internal);
+        }
+
+        let (nonce, wrapped) = wrapped.split_at(NONCE_SIZE);
+        let master_keys = std::iter::once(&self.master_key).chain(self.previous_master_keys.iter());
+
+        for (i, master_key) in master_keys.enumerate() {
+            if let Ok(key) = user_key(master_key, user_id).decrypt(nonce.into(), wrapped) {
+                let mut data_key = [0; KEY_SIZE];
+                if key.len() != KEY_SIZE {
+                    break;
+                }
+
+                data_key.copy_from_slice(&key);
+                return Ok((DataKey(data_key), i == 0));
+            }
+        }
+
+        warn!("Couldn't unwrap data key of user {}", user_id);
+        Err(RestError::Internal)
+    }
+}
+
+/// Derive the key of a user from a master key
+fn user_key(master_key: &[u8; KEY_SIZE], user_id: i32) -> ChaCha20Poly1305 {
+    let mut key = [0; KEY_SIZE];
+    Hkdf::<Sha256>::new(Some(USER_KEY_SALT), master_key)
+        .expand(&user_id.to_be_bytes(), &mut key)
+        .expect("Invalid key length");
+
+    ChaCha20Poly1305::new(&key.into())
+}
+
+/// Parse a base64 encoded key
+fn parse_key(key: &str) -> Result<[u8; KEY_SIZE], String> {
+    let decoded = base64::decode(key.trim()).map_err(|e| e.to_string())?;
+    if decoded.len() != KEY_SIZE {
+        return Err(format!("Master keys must be {} bytes long", KEY_SIZE));
+    }
+
+    let mut key = [0; KEY_SIZE];
+    key.copy_from_slice(&decoded);
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    fn keyring(master_key: &str, previous_master_keys: &[&str]) -> Keyring {
+        let mut config = Config::default();
+        config.encryption.master_key = Some(base64::encode(master_key));
+        config.encryption.previous_master_keys =
+            previous_master_keys.iter().map(base64::encode).collect();
+
+        Keyring::from_config(&config).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_wrap_key() {
+        let old = keyring("0123456789abcdef0123456789abcdef", &[]);
+        let key = DataKey::generate();
+        let wrapped = old.wrap(&key, 1);
+
+        let (unwrapped, current) = old.unwrap(&wrapped, 1).unwrap();
+        assert_eq!(unwrapped.0, key.0);
+        assert!(current);
+
+        // Keys are bound to their user
+        assert!(old.unwrap(&wrapped, 2).is_err());
+
+        // Rotated master keys can still be used for unwrapping
+        let new = keyring(
+            "fedcba9876543210fedcba9876543210",
+            &["0123456789abcdef0123456789abcdef"],
+        );
+        let (unwrapped, current) = new.unwrap(&wrapped, 1).unwrap();
+        assert_eq!(unwrapped.0, key.0);
+        assert!(!current);
+    }
+
+    #[actix_rt::test]
+    async fn test_decrypt_range() {
+        let content: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let path =
+            std::env::temp_dir().join(format!("dv_encrypt_{}", crate::utils::random_string(10)));
+        std::fs::write(&path, &content).unwrap();
+
+        let key = DataKey::generate();
+        key.encrypt_file(&path).unwrap();
+        let encrypted = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(encrypted.len(), content.len());
+        assert_ne!(encrypted, content);
+
+        for (offset, length) in [(0, content.len()), (70_001, 12_345)].iter().copied() {
+            let chunks: Vec<Result<Bytes, actix_web::Error>> = encrypted[offset..offset + length]
+                .chunks(1000)
+                .map(|i| Ok(Bytes::copy_from_slice(i)))
+                .collect();
+
+            let decrypted: Vec<Bytes> = key
+                .decrypt(stream::iter(chunks).boxed_local(), offset as u64)
+                .try_collect()
+                .await
+                .unwrap();
+
+            assert_eq!(decrypted.concat(), &content[offset..offset + length]);
+        }
+    }
+}
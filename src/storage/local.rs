@@ -0,0 +1,127 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use super::{ByteStream, ObjectInfo, StorageBackend};
+use crate::{handlers::chunked::ChunkedReadFile, response_code::RestError};
+
+use async_std::{fs, io::prelude::*};
+use futures::{future::LocalBoxFuture, FutureExt, StreamExt};
+
+/// Stores objects as files in a local directory
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        LocalStorage {
+            root: root.as_ref().to_owned(),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn put<'a>(
+        &'a self,
+        name: &'a str,
+        mut stream: ByteStream,
+        size: u64,
+    ) -> LocalBoxFuture<'a, Result<(), RestError>> {
+        async move {
+            let path = self.path(name);
+            let mut file = fs::File::create(&path).await?;
+            let mut written: u64 = 0;
+
+            let result = async {
+                while let Some(chunk) = stream.next().await {
+                    let data = chunk.map_err(|_| RestError::UnknownIo)?;
+                    file.write_all(&data).await?;
+                    written += data.len() as u64;
+                }
+
+                if written != size {
+                    return Err(RestError::PartialContent);
+                }
+
+                file.flush().await?;
+                file.sync_all().await?;
+                Ok(())
+            }
+            .await;
+
+            if result.is_err() {
+                fs::remove_file(&path).await.ok();
+            }
+
+            result
+        }
+        .boxed_local()
+    }
+
+    fn put_file<'a>(
+        &'a self,
+        name: &'a str,
+        path: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<(), RestError>> {
+        async move {
+            let target = self.path(name);
+
+            // Uploads are written into the storage directory already
+            if target == path {
+                return Ok(());
+            }
+
+            if fs::rename(path, &target).await.is_err() {
+                // Different filesystems
+                fs::copy(path, &target).await?;
+                fs::remove_file(path).await.ok();
+            }
+
+            Ok(())
+        }
+        .boxed_local()
+    }
+
+    fn get<'a>(
+        &'a self,
+        name: &'a str,
+        offset: u64,
+        length: u64,
+    ) -> LocalBoxFuture<'a, Result<ByteStream, RestError>> {
+        async move {
+            let file = std::fs::File::open(self.path(name))?;
+            Ok(ChunkedReadFile::new(length, offset, file).boxed_local())
+        }
+        .boxed_local()
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<(), RestError>> {
+        async move {
+            match fs::remove_file(self.path(name)).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            }
+        }
+        .boxed_local()
+    }
+
+    fn stat<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<ObjectInfo, RestError>> {
+        async move {
+            let metadata = fs::metadata(self.path(name)).await?;
+            Ok(ObjectInfo {
+                size: metadata.len(),
+            })
+        }
+        .boxed_local()
+    }
+
+    fn local_path(&self, name: &str) -> Option<PathBuf> {
+        Some(self.path(name))
+    }
+}
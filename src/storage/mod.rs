@@ -0,0 +1,192 @@
+pub mod encryption;
+pub mod local;
+pub mod s3;
+pub mod scrub;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    config::{Config, StorageBackendType},
+    handlers::chunked::ChunkedReadFile,
+    response_code::RestError,
+    utils,
+};
+
+use async_std::{fs, io::prelude::*};
+use bytes::Bytes;
+use futures::{
+    future::LocalBoxFuture,
+    stream::{LocalBoxStream, StreamExt},
+    FutureExt,
+};
+
+use encryption::DataKey;
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+/// Stream of an objects content
+pub type ByteStream = LocalBoxStream<'static, Result<Bytes, actix_web::Error>>;
+
+/// Metadata of a stored object
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjectInfo {
+    pub size: u64,
+}
+
+/// A place where the content of files is stored. Objects are
+/// addressed by their name, which is the local_name of a blob
+pub trait StorageBackend: Send + Sync {
+    /// Store 'size' bytes read from 'stream' as object 'name'
+    fn put<'a>(
+        &'a self,
+        name: &'a str,
+        stream: ByteStream,
+        size: u64,
+    ) -> LocalBoxFuture<'a, Result<(), RestError>>;
+
+    /// Move a local file into the storage as object 'name'
+    fn put_file<'a>(
+        &'a self,
+        name: &'a str,
+        path: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<(), RestError>> {
+        async move {
+            let file = std::fs::File::open(path)?;
+            let size = file.metadata()?.len();
+
+            self.put(
+                name,
+                ChunkedReadFile::new(size, 0, file).boxed_local(),
+                size,
+            )
+            .await?;
+
+            fs::remove_file(path).await.ok();
+            Ok(())
+        }
+        .boxed_local()
+    }
+
+    /// Read 'length' bytes of the object 'name', starting at 'offset'
+    fn get<'a>(
+        &'a self,
+        name: &'a str,
+        offset: u64,
+        length: u64,
+    ) -> LocalBoxFuture<'a, Result<ByteStream, RestError>>;
+
+    /// Delete the object 'name'. Deleting a
+    /// not existing object is not an error
+    fn delete<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<(), RestError>>;
+
+    /// Get the metadata of the object 'name'
+    fn stat<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<ObjectInfo, RestError>>;
+
+    /// The path of the object 'name' if it's
+    /// stored in the local filesystem
+    fn local_path(&self, _name: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Create the storage backend selected in the config
+pub fn from_config(config: &Config) -> Result<Arc<dyn StorageBackend>, String> {
+    Ok(match config.storage.backend {
+        StorageBackendType::Local => Arc::new(LocalStorage::new(&config.server.file_output_path)),
+        StorageBackendType::S3 => {
+            let s3_config = config
+                .storage
+                .s3
+                .as_ref()
+                .ok_or_else(|| "Missing [storage.s3] config".to_string())?;
+
+            Arc::new(S3Storage::new(s3_config.clone())?)
+        }
+    })
+}
+
+/// Read 'length' bytes of the object 'name', starting at 'offset'.
+/// The content gets decrypted if a key is passed
+pub async fn read(
+    storage: &dyn StorageBackend,
+    name: &str,
+    key: Option<&DataKey>,
+    offset: u64,
+    length: u64,
+) -> Result<ByteStream, RestError> {
+    let stream = storage.get(name, offset, length).await?;
+
+    Ok(match key {
+        Some(key) => key.decrypt(stream, offset),
+        None => stream,
+    })
+}
+
+/// Get the path of a local file in the upload directory.
+/// Uploads are written there before being moved into the storage
+pub fn staging_path(config: &Config, name: &str) -> PathBuf {
+    Path::new(&config.server.file_output_path).join(name)
+}
+
+/// An object available in the local filesystem. Objects of remote
+/// storages and encrypted objects are written into a temporary
+/// file, which gets removed once the LocalCopy is dropped
+pub struct LocalCopy {
+    path: PathBuf,
+    temporary: bool,
+}
+
+impl LocalCopy {
+    /// A file at 'path' which gets removed when it's dropped
+    pub fn temporary(path: PathBuf) -> Self {
+        LocalCopy {
+            path,
+            temporary: true,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for LocalCopy {
+    fn drop(&mut self) {
+        if self.temporary {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+/// Make the decrypted content of the object 'name'
+/// available in the local filesystem
+pub async fn local_copy(
+    storage: &dyn StorageBackend,
+    config: &Config,
+    name: &str,
+    key: Option<&DataKey>,
+) -> Result<LocalCopy, RestError> {
+    if let Some(path) = storage.local_path(name).filter(|_| key.is_none()) {
+        return Ok(LocalCopy {
+            path,
+            temporary: false,
+        });
+    }
+
+    let copy = LocalCopy::temporary(staging_path(config, &utils::random_string(30)));
+
+    let size = storage.stat(name).await?.size;
+    let mut stream = read(storage, name, key, 0, size).await?;
+    let mut file = fs::File::create(copy.path()).await?;
+
+    while let Some(chunk) = stream.next().await {
+        file.write_all(&chunk.map_err(|_| RestError::UnknownIo)?)
+            .await?;
+    }
+
+    file.flush().await?;
+    Ok(copy)
+}
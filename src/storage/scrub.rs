@@ -0,0 +1,533 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    encryption::{DataKey, Keyring},
+    StorageBackend,
+};
+use crate::{
+    config::{Config, OrphanAction, ScrubConfig},
+    models::file,
+    response_code::{Origin, RestError},
+    schema::{blobs, file_versions, files, upload_sessions},
+    DbConnection, DbPool,
+};
+
+use actix_web::web;
+use diesel::{prelude::*, result::Error as DieselErr};
+use futures::StreamExt;
+use serde::Serialize;
+
+/// Local files modified more recently are never considered orphaned,
+/// since uploads are written there before they get stored
+const ORPHAN_MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// What a scrub checks and how it handles its findings
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScrubOptions {
+    /// Read the content of all objects and compare it with their checksums
+    pub checksums: bool,
+    pub orphans: OrphanAction,
+    /// Mark files with missing or damaged content as corrupted
+    pub flag_corrupted: bool,
+}
+
+impl From<&ScrubConfig> for ScrubOptions {
+    fn from(config: &ScrubConfig) -> Self {
+        ScrubOptions {
+            checksums: config.checksums,
+            orphans: config.orphans,
+            flag_corrupted: config.flag_corrupted,
+        }
+    }
+}
+
+/// A file or a version of a file
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FileRef {
+    #[serde(rename = "fileID")]
+    pub file_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+}
+
+/// A file or version referencing a stored object, along
+/// with the size and checksum it expects the object to have
+#[derive(Debug, Clone, PartialEq)]
+struct Reference {
+    file: FileRef,
+    size: i64,
+    checksum: String,
+    wrapped_key: Option<String>,
+    owner: i32,
+}
+
+/// An inconsistency found by a scrub
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Problem {
+    /// The object doesn't exist
+    Missing { name: String, files: Vec<FileRef> },
+    /// The object has a different size than recorded
+    Size {
+        name: String,
+        expected: i64,
+        actual: u64,
+        files: Vec<FileRef>,
+    },
+    /// The content of the object doesn't match its checksum
+    Checksum {
+        name: String,
+        expected: String,
+        actual: String,
+        files: Vec<FileRef>,
+    },
+    /// A local file not belonging to any file or upload
+    Orphan {
+        name: String,
+        size: u64,
+        action: OrphanAction,
+    },
+    /// The object couldn't be checked
+    Error { name: String, error: String },
+}
+
+/// The result of a scrub
+#[derive(Debug, Default, Serialize)]
+pub struct ScrubReport {
+    /// Amount of checked objects
+    pub objects: usize,
+    pub problems: Vec<Problem>,
+    /// Amount of files newly marked as corrupted
+    pub flagged: usize,
+    /// Amount of files which aren't corrupted anymore
+    pub cleared: usize,
+}
+
+/// Check that all stored objects exist and match the files referencing them,
+/// and look for orphaned local files. Orphans are only searched for in the
+/// upload directory, which is where objects of the local storage are kept
+pub async fn scrub(
+    pool: &DbPool,
+    config: &Config,
+    storage: &dyn StorageBackend,
+    options: ScrubOptions,
+) -> Result<ScrubReport, RestError> {
+    let db_pool = pool.clone();
+    let objects = web::block(move || referenced_objects(&db_pool.get()?)).await??;
+
+    let keyring = Keyring::from_config(config).unwrap_or_else(|err| {
+        warn!("Invalid master key: {}", err);
+        None
+    });
+
+    let mut report = ScrubReport {
+        objects: objects.len(),
+        ..ScrubReport::default()
+    };
+
+    let mut corrupted = vec![];
+    let mut intact = vec![];
+
+    for (name, refs) in objects.iter() {
+        let problems =
+            match check_object(storage, keyring.as_ref(), name, refs, options.checksums).await {
+                Ok(problems) => problems,
+                Err(err) => {
+                    report.problems.push(Problem::Error {
+                        name: name.clone(),
+                        error: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+        // Only the current content of a file can be flagged
+        let damaged: Vec<i32> = problems
+            .iter()
+            .flat_map(|i| match i {
+                Problem::Missing { files, .. }
+                | Problem::Size { files, .. }
+                | Problem::Checksum { files, .. } => files.clone(),
+                _ => vec![],
+            })
+            .filter(|i| i.version.is_none())
+            .map(|i| i.file_id)
+            .collect();
+
+        for reference in refs.iter().filter(|i| i.file.version.is_none()) {
+            let file = (reference.file.file_id, name.clone());
+            if damaged.contains(&reference.file.file_id) {
+                corrupted.push(file);
+            } else {
+                intact.push(file);
+            }
+        }
+
+        report.problems.extend(problems);
+    }
+
+    let db_pool = pool.clone();
+    let output_path = config.server.file_output_path.clone();
+    let orphans = web::block(move || -> Result<_, RestError> {
+        let referenced = referenced_names(&db_pool.get()?)?;
+        Ok(find_orphans(
+            Path::new(&output_path),
+            &referenced,
+            ORPHAN_MIN_AGE,
+        )?)
+    })
+    .await??;
+
+    for (name, size) in orphans {
+        let res = match options.orphans {
+            OrphanAction::Report => Ok(()),
+            OrphanAction::Quarantine => quarantine(config, &name),
+            OrphanAction::Delete => {
+                fs::remove_file(Path::new(&config.server.file_output_path).join(&name))
+            }
+        };
+
+        report.problems.push(match res {
+            Ok(()) => Problem::Orphan {
+                name,
+                size,
+                action: options.orphans,
+            },
+            Err(err) => Problem::Error {
+                name,
+                error: err.to_string(),
+            },
+        });
+    }
+
+    if options.flag_corrupted {
+        let db_pool = pool.clone();
+        let (flagged, cleared) = web::block(move || -> Result<_, RestError> {
+            let db = db_pool.get()?;
+            Ok((
+                file::set_corrupted(&db, &corrupted, true)?,
+                file::set_corrupted(&db, &intact, false)?,
+            ))
+        })
+        .await??;
+
+        report.flagged = flagged;
+        report.cleared = cleared;
+    }
+
+    Ok(report)
+}
+
+/// Id, object name, size, checksum, wrapped key and owner of a file
+type FileRow = (i32, String, i64, String, Option<String>, i32);
+
+/// Like 'FileRow', with the version after the file id
+type VersionRow = (i32, i32, String, i64, String, Option<String>, i32);
+
+/// Get all objects referenced by files and versions, by their name
+fn referenced_objects(db: &DbConnection) -> Result<BTreeMap<String, Vec<Reference>>, RestError> {
+    let mut objects: BTreeMap<String, Vec<Reference>> = BTreeMap::new();
+
+    let current: Vec<FileRow> = files::table
+        .select((
+            files::id,
+            files::local_name,
+            files::file_size,
+            files::checksum,
+            files::wrapped_key,
+            files::user_id,
+        ))
+        .load(db)?;
+
+    for (file_id, name, size, checksum, wrapped_key, owner) in current {
+        objects.entry(name).or_default().push(Reference {
+            file: FileRef {
+                file_id,
+                version: None,
+            },
+            size,
+            checksum,
+            wrapped_key,
+            owner,
+        });
+    }
+
+    let versions: Vec<VersionRow> = file_versions::table
+        .inner_join(files::table)
+        .select((
+            file_versions::file_id,
+            file_versions::version,
+            file_versions::local_name,
+            file_versions::file_size,
+            file_versions::checksum,
+            file_versions::wrapped_key,
+            files::user_id,
+        ))
+        .load(db)?;
+
+    for (file_id, version, name, size, checksum, wrapped_key, owner) in versions {
+        objects.entry(name).or_default().push(Reference {
+            file: FileRef {
+                file_id,
+                version: Some(version),
+            },
+            size,
+            checksum,
+            wrapped_key,
+            owner,
+        });
+    }
+
+    Ok(objects)
+}
+
+/// Get the names of all local files which are still in use
+fn referenced_names(db: &DbConnection) -> Result<HashSet<String>, DieselErr> {
+    let mut names = HashSet::new();
+
+    names.extend(files::table.select(files::local_name).load::<String>(db)?);
+    names.extend(
+        file_versions::table
+            .select(file_versions::local_name)
+            .load::<String>(db)?,
+    );
+    names.extend(blobs::table.select(blobs::local_name).load::<String>(db)?);
+    names.extend(
+        upload_sessions::table
+            .select(upload_sessions::local_name)
+            .load::<String>(db)?,
+    );
+
+    Ok(names)
+}
+
+/// Check a single object against the files referencing it
+async fn check_object(
+    storage: &dyn StorageBackend,
+    keyring: Option<&Keyring>,
+    name: &str,
+    refs: &[Reference],
+    checksums: bool,
+) -> Result<Vec<Problem>, RestError> {
+    let size = match storage.stat(name).await {
+        Ok(info) => info.size,
+        Err(RestError::DNotFound(Origin::LocalFile)) => {
+            return Ok(vec![Problem::Missing {
+                name: name.to_string(),
+                files: refs.iter().map(|i| i.file).collect(),
+            }]);
+        }
+        Err(err) => return Err(err),
+    };
+
+    // Only read the content if there is something to compare it with
+    let checksum = if checksums
+        && refs
+            .iter()
+            .any(|i| i.size as u64 == size && !i.checksum.is_empty())
+    {
+        let key = match refs.iter().find(|i| i.wrapped_key.is_some()) {
+            Some(reference) => {
+                let keyring = keyring.ok_or(RestError::Internal)?;
+                let wrapped_key = reference.wrapped_key.as_deref().unwrap_or_default();
+                Some(keyring.unwrap(wrapped_key, reference.owner)?.0)
+            }
+            None => None,
+        };
+
+        Some(content_checksum(storage, name, key.as_ref(), size).await?)
+    } else {
+        None
+    };
+
+    Ok(compare(name, refs, size, checksum.as_deref()))
+}
+
+/// Compare the actual size and checksum of an object with
+/// what the references expect. Mismatches are grouped by
+/// the expected value
+fn compare(name: &str, refs: &[Reference], size: u64, checksum: Option<&str>) -> Vec<Problem> {
+    let mut sizes: BTreeMap<i64, Vec<FileRef>> = BTreeMap::new();
+    let mut checksums: BTreeMap<&str, Vec<FileRef>> = BTreeMap::new();
+
+    for reference in refs {
+        if reference.size as u64 != size {
+            sizes
+                .entry(reference.size)
+                .or_default()
+                .push(reference.file);
+            continue;
+        }
+
+        match checksum {
+            Some(checksum) if !reference.checksum.is_empty() && reference.checksum != checksum => {
+                checksums
+                    .entry(&reference.checksum)
+                    .or_default()
+                    .push(reference.file)
+            }
+            _ => (),
+        }
+    }
+
+    let sizes = sizes.into_iter().map(|(expected, files)| Problem::Size {
+        name: name.to_string(),
+        expected,
+        actual: size,
+        files,
+    });
+
+    let checksums = checksums
+        .into_iter()
+        .map(|(expected, files)| Problem::Checksum {
+            name: name.to_string(),
+            expected: expected.to_string(),
+            actual: checksum.unwrap_or_default().to_string(),
+            files,
+        });
+
+    sizes.chain(checksums).collect()
+}
+
+/// Calculate the crc32 checksum of the (decrypted) content of an object
+async fn content_checksum(
+    storage: &dyn StorageBackend,
+    name: &str,
+    key: Option<&DataKey>,
+    size: u64,
+) -> Result<String, RestError> {
+    let mut stream = super::read(storage, name, key, 0, size).await?;
+    let mut hasher = crc32fast::Hasher::new();
+
+    while let Some(chunk) = stream.next().await {
+        hasher.update(&chunk.map_err(|_| RestError::UnknownIo)?);
+    }
+
+    Ok(format!("{:08x}", hasher.finalize()))
+}
+
+/// Find the files in 'dir' which aren't referenced and weren't modified
+/// within 'min_age'. Returns their names and sizes. Directories are ignored
+fn find_orphans(
+    dir: &Path,
+    referenced: &HashSet<String>,
+    min_age: Duration,
+) -> io::Result<Vec<(String, u64)>> {
+    let mut orphans = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+
+        if referenced.contains(&name) {
+            continue;
+        }
+
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|i| SystemTime::now().duration_since(i).ok())
+            .unwrap_or_default();
+
+        if age >= min_age {
+            orphans.push((name, metadata.len()));
+        }
+    }
+
+    orphans.sort();
+    Ok(orphans)
+}
+
+/// Move a local file into the quarantine directory
+fn quarantine(config: &Config, name: &str) -> io::Result<()> {
+    let dir = config.quarantine_path();
+    fs::create_dir_all(&dir)?;
+    fs::rename(
+        Path::new(&config.server.file_output_path).join(name),
+        dir.join(name),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(file_id: i32, size: i64, checksum: &str) -> Reference {
+        Reference {
+            file: FileRef {
+                file_id,
+                version: None,
+            },
+            size,
+            checksum: checksum.to_string(),
+            wrapped_key: None,
+            owner: 1,
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let refs = vec![
+            reference(1, 10, "aaaaaaaa"),
+            reference(2, 10, "bbbbbbbb"),
+            reference(3, 12, "aaaaaaaa"),
+            reference(4, 10, ""),
+        ];
+
+        // Without a checksum only sizes are compared
+        assert_eq!(
+            compare("obj", &refs, 10, None),
+            vec![Problem::Size {
+                name: "obj".to_string(),
+                expected: 12,
+                actual: 10,
+                files: vec![refs[2].file],
+            }]
+        );
+
+        // Files without a checksum aren't compared
+        let problems = compare("obj", &refs, 10, Some("aaaaaaaa"));
+        assert_eq!(problems.len(), 2);
+        assert_eq!(
+            problems[1],
+            Problem::Checksum {
+                name: "obj".to_string(),
+                expected: "bbbbbbbb".to_string(),
+                actual: "aaaaaaaa".to_string(),
+                files: vec![refs[1].file],
+            }
+        );
+    }
+
+    #[test]
+    fn test_find_orphans() {
+        let dir = std::env::temp_dir().join(crate::utils::random_string(10));
+        fs::create_dir_all(dir.join("quarantine")).unwrap();
+        fs::write(dir.join("used"), b"used").unwrap();
+        fs::write(dir.join("orphan"), b"orphan").unwrap();
+
+        let referenced: HashSet<String> = vec!["used".to_string()].into_iter().collect();
+
+        // Recently modified files might still be uploading
+        let orphans = find_orphans(&dir, &referenced, ORPHAN_MIN_AGE).unwrap();
+        assert!(orphans.is_empty());
+
+        let orphans = find_orphans(&dir, &referenced, Duration::from_secs(0)).unwrap();
+        assert_eq!(orphans, vec![("orphan".to_string(), 6)]);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
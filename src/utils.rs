@@ -1,6 +1,14 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
 use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
+use rand::{rngs::OsRng, thread_rng, Rng};
 use sha2::{Digest, Sha512};
+use std::convert::TryFrom;
+use subtle::ConstantTimeEq;
+
+use crate::{config::PasswordHashing, response_code::RestError};
 
 pub fn sha512(s: &[&str]) -> String {
     let mut hasher = Sha512::new();
@@ -18,6 +26,100 @@ pub fn random_string(len: usize) -> String {
         .collect()
 }
 
-pub fn hash_pw(username: &str, pass: &str) -> String {
-    crate::utils::sha512(&[&username, &pass])
+/// Hash a password using Argon2id. Returns a PHC string
+pub fn hash_pw(config: &PasswordHashing, pass: &str) -> Result<String, RestError> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Ok(argon2(config)?
+        .hash_password(pass.as_bytes(), &salt)
+        .map_err(|_| RestError::Internal)?
+        .to_string())
+}
+
+/// Check a password against a stored hash. Legacy
+/// hashes are a sha512 of the username and password
+pub fn verify_pw(hash: &str, username: &str, pass: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(pass.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => hash
+            .as_bytes()
+            .ct_eq(legacy_hash_pw(username, pass).as_bytes())
+            .into(),
+    }
+}
+
+/// Returns true if the hash doesn't use Argon2id
+/// with the currently configured parameters
+pub fn needs_rehash(config: &PasswordHashing, hash: &str) -> bool {
+    let parsed = match PasswordHash::new(hash) {
+        Ok(parsed) => parsed,
+        Err(_) => return true,
+    };
+
+    if parsed.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+
+    Params::try_from(&parsed)
+        .map(|params| {
+            params.m_cost() != config.memory_cost
+                || params.t_cost() != config.time_cost
+                || params.p_cost() != config.parallelism
+        })
+        .unwrap_or(true)
+}
+
+/// The password hash used before Argon2id
+fn legacy_hash_pw(username: &str, pass: &str) -> String {
+    sha512(&[&username, &pass])
+}
+
+fn argon2(config: &PasswordHashing) -> Result<Argon2<'static>, RestError> {
+    let params = Params::new(
+        config.memory_cost,
+        config.time_cost,
+        config.parallelism,
+        None,
+    )
+    .map_err(|_| RestError::Internal)?;
+
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> PasswordHashing {
+        PasswordHashing {
+            memory_cost: 64,
+            time_cost: 1,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn test_hash_pw() {
+        let hash = hash_pw(&config(), "secret").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_pw(&hash, "user", "secret"));
+        assert!(!verify_pw(&hash, "user", "wrong"));
+        assert!(!needs_rehash(&config(), &hash));
+
+        let stronger = PasswordHashing {
+            time_cost: 2,
+            ..config()
+        };
+        assert!(needs_rehash(&stronger, &hash));
+    }
+
+    #[test]
+    fn test_legacy_hash_pw() {
+        let hash = sha512(&["user", "secret"]);
+        assert!(verify_pw(&hash, "user", "secret"));
+        assert!(!verify_pw(&hash, "user", "wrong"));
+        assert!(needs_rehash(&config(), &hash));
+    }
 }